@@ -16,7 +16,7 @@ macro_rules! mk_static {
 }
 
 // #[derive(Debug, Clone, Copy, Default)]
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq)]
 pub struct Vector3 {
     pub x: f32,
     pub y: f32,
@@ -31,30 +31,96 @@ impl core::fmt::Debug for Vector3 {
             .finish()
     }
 }
+// NOTE trig via libm (software implementations, i.e. not hardware accelerated)
 impl Vector3 {
-    // FIXME find a no_std trig library (libm doesn't leverage hardware)
-    // pub fn euler_to_quaternion(&self) -> Quaternion {
-    //     // Convert Euler angles (in radians) to quaternion
-    //     // Using the ZYX rotation order (yaw, pitch, roll)
-    //     let (roll, pitch, yaw) = (self.x, self.y, self.z);
+    pub const ZERO: Vector3 = Vector3::new(0.0, 0.0, 0.0);
 
-    //     let cr = (roll * 0.5).cos();
-    //     let sr = (roll * 0.5).sin();
-    //     let cp = (pitch * 0.5).cos();
-    //     let sp = (pitch * 0.5).sin();
-    //     let cy = (yaw * 0.5).cos();
-    //     let sy = (yaw * 0.5).sin();
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Vector3 { x, y, z }
+    }
+
+    pub fn dot(&self, other: &Vector3) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(&self, other: &Vector3) -> Vector3 {
+        Vector3 {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
+    pub fn norm(&self) -> f32 {
+        libm::sqrtf(self.dot(self))
+    }
 
-    //     let w = cr * cp * cy + sr * sp * sy;
-    //     let x = sr * cp * cy - cr * sp * sy;
-    //     let y = cr * sp * cy + sr * cp * sy;
-    //     let z = cr * cp * sy - sr * sp * cy;
+    /// unit vector of the same direction (None if zero length)
+    pub fn normalized(&self) -> Option<Vector3> {
+        let norm = self.norm();
+        if norm > f32::EPSILON {
+            Some(*self * (1.0 / norm))
+        } else {
+            None
+        }
+    }
 
-    //     Quaternion { w, x, y, z }
-    // }
+    pub fn euler_to_quaternion(&self) -> Quaternion {
+        // Convert Euler angles (in radians) to quaternion
+        // Using the ZYX rotation order (yaw, pitch, roll)
+        let (roll, pitch, yaw) = (self.x, self.y, self.z);
+
+        let cr = libm::cosf(roll * 0.5);
+        let sr = libm::sinf(roll * 0.5);
+        let cp = libm::cosf(pitch * 0.5);
+        let sp = libm::sinf(pitch * 0.5);
+        let cy = libm::cosf(yaw * 0.5);
+        let sy = libm::sinf(yaw * 0.5);
+
+        let w = cr * cp * cy + sr * sp * sy;
+        let x = sr * cp * cy - cr * sp * sy;
+        let y = cr * sp * cy + sr * cp * sy;
+        let z = cr * cp * sy - sr * sp * cy;
+
+        Quaternion { w, x, y, z }
+    }
+}
+impl core::ops::Add for Vector3 {
+    type Output = Vector3;
+    fn add(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+impl core::ops::AddAssign for Vector3 {
+    fn add_assign(&mut self, other: Vector3) {
+        *self = *self + other;
+    }
+}
+impl core::ops::Sub for Vector3 {
+    type Output = Vector3;
+    fn sub(self, other: Vector3) -> Vector3 {
+        Vector3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+impl core::ops::SubAssign for Vector3 {
+    fn sub_assign(&mut self, other: Vector3) {
+        *self = *self - other;
+    }
+}
+impl core::ops::Mul<f32> for Vector3 {
+    type Output = Vector3;
+    fn mul(self, scale: f32) -> Vector3 {
+        Vector3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+impl core::ops::Neg for Vector3 {
+    type Output = Vector3;
+    fn neg(self) -> Vector3 {
+        Vector3::new(-self.x, -self.y, -self.z)
+    }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq)]
 pub struct Quaternion {
     pub w: f32,
     pub x: f32,
@@ -72,51 +138,167 @@ impl core::fmt::Debug for Quaternion {
     }
 }
 impl Quaternion {
-    // FIXME find a no_std trig library (libm doesn't leverage hardware)
-    // pub fn rotate_vector(&self, v: Vector3, inverse: bool) -> Vector3 {
-    //     // Rotate a vector by a quaternion using the formula:
-    //     // v' = q * v * q^-1
-    //     // Where q^-1 is the conjugate since we normalize the quaternion
-    //     const EPS: f32 = 1e-6;
-    //     let n2 = self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z;
-    //     let scale = 1.0 / (n2.sqrt() + EPS);
-
-    //     let w = self.w * scale;
-    //     let mut x = self.x * scale;
-    //     let mut y = self.y * scale;
-    //     let mut z = self.z * scale;
-
-    //     // 2. Conjugate if inverse
-    //     if inverse {
-    //         x = -x;
-    //         y = -y;
-    //         z = -z;
-    //     }
-
-    //     // 3. Apply the expanded formula
-    //     let vx = v.x;
-    //     let vy = v.y;
-    //     let vz = v.z;
-    //     let xx = w * w * vx + 2.0 * y * w * vz - 2.0 * z * w * vy
-    //         + x * x * vx
-    //         + 2.0 * y * x * vy
-    //         + 2.0 * z * x * vz
-    //         - z * z * vx
-    //         - y * y * vx;
-    //     let yy = 2.0 * x * y * vx + y * y * vy + 2.0 * z * y * vz + 2.0 * w * z * vx - z * z * vy
-    //         + w * w * vy
-    //         - 2.0 * w * x * vz
-    //         - x * x * vy;
-    //     let zz = 2.0 * x * z * vx + 2.0 * y * z * vy + z * z * vz - 2.0 * w * y * vx
-    //         + w * w * vz
-    //         + 2.0 * w * x * vy
-    //         - y * y * vz
-    //         - x * x * vz;
-
-    //     Vector3 {
-    //         x: xx,
-    //         y: yy,
-    //         z: zz,
-    //     }
-    // }
+    /// no rotation
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// rotation of `angle` (radians) about the axis
+    pub fn from_axis_angle(axis: &Vector3, angle: f32) -> Self {
+        match axis.normalized() {
+            Some(axis) => {
+                let (s, c) = (libm::sinf(angle * 0.5), libm::cosf(angle * 0.5));
+                Quaternion {
+                    w: c,
+                    x: axis.x * s,
+                    y: axis.y * s,
+                    z: axis.z * s,
+                }
+            }
+            None => Self::IDENTITY,
+        }
+    }
+
+    /// rotation of the rotation vector (axis scaled by angle in radians)
+    pub fn from_rotation_vector(v: &Vector3) -> Self {
+        Self::from_axis_angle(v, v.norm())
+    }
+
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn norm(&self) -> f32 {
+        libm::sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z)
+    }
+
+    /// unit quaternion (identity if zero length)
+    pub fn normalized(&self) -> Quaternion {
+        let norm = self.norm();
+        if norm > f32::EPSILON {
+            *self * (1.0 / norm)
+        } else {
+            Self::IDENTITY
+        }
+    }
+
+    /// Euler angles (radians) using the ZYX rotation order (yaw, pitch, roll)
+    pub fn to_euler(&self) -> Vector3 {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+        let roll = libm::atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        let pitch = libm::asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let yaw = libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+        Vector3::new(roll, pitch, yaw)
+    }
+
+    /// angle (radians) of the rotation from this to the other
+    pub fn angle_to(&self, other: &Quaternion) -> f32 {
+        let dot = self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z;
+        2.0 * libm::acosf(dot.abs().min(1.0))
+    }
+
+    pub fn rotate_vector(&self, v: Vector3, inverse: bool) -> Vector3 {
+        // Rotate a vector by a quaternion using the formula:
+        // v' = q * v * q^-1
+        // Where q^-1 is the conjugate since we normalize the quaternion
+        let q = self.normalized();
+        let q = if inverse { q.conjugate() } else { q };
+        let u = Vector3::new(q.x, q.y, q.z);
+        // expanded as v' = v + 2w(u x v) + 2u x (u x v)
+        let t = u.cross(&v) * 2.0;
+        v + t * q.w + u.cross(&t)
+    }
+}
+impl core::ops::Mul for Quaternion {
+    type Output = Quaternion;
+    /// Hamilton product (rotation of `other` followed by `self`)
+    fn mul(self, other: Quaternion) -> Quaternion {
+        let (a, b) = (self, other);
+        Quaternion {
+            w: a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            x: a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            y: a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            z: a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        }
+    }
+}
+impl core::ops::Mul<f32> for Quaternion {
+    type Output = Quaternion;
+    fn mul(self, scale: f32) -> Quaternion {
+        Quaternion {
+            w: self.w * scale,
+            x: self.x * scale,
+            y: self.y * scale,
+            z: self.z * scale,
+        }
+    }
+}
+impl core::ops::Add for Quaternion {
+    type Output = Quaternion;
+    fn add(self, other: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w + other.w,
+            x: self.x + other.x,
+            y: self.y + other.y,
+            z: self.z + other.z,
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3) - i.e. validating retained or stored data
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn euler_round_trip() {
+        let euler = Vector3::new(0.3, -0.5, 2.0);
+        let q = euler.euler_to_quaternion();
+        assert!((q.norm() - 1.0).abs() < EPSILON);
+        let actual = q.to_euler();
+        assert!((actual - euler).norm() < EPSILON, "{actual:?} != {euler:?}");
+    }
+
+    #[test]
+    fn rotates_vectors() {
+        // 90 degrees yaw rotates x onto y
+        let q =
+            Quaternion::from_axis_angle(&Vector3::new(0.0, 0.0, 1.0), core::f32::consts::FRAC_PI_2);
+        let v = q.rotate_vector(Vector3::new(1.0, 0.0, 0.0), false);
+        assert!((v - Vector3::new(0.0, 1.0, 0.0)).norm() < EPSILON, "{v:?}");
+        let v = q.rotate_vector(v, true);
+        assert!((v - Vector3::new(1.0, 0.0, 0.0)).norm() < EPSILON, "{v:?}");
+        // composition of rotations
+        let q2 = q * q;
+        let v = q2.rotate_vector(Vector3::new(1.0, 0.0, 0.0), false);
+        assert!((v - Vector3::new(-1.0, 0.0, 0.0)).norm() < EPSILON, "{v:?}");
+        assert!((q.angle_to(&q2) - core::f32::consts::FRAC_PI_2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn check_crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
 }
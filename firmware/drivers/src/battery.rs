@@ -0,0 +1,125 @@
+//! Battery monitoring over ADC
+//!
+//! The pack voltage is sampled through a voltage divider, and the current
+//! (optionally) through a current sensor (i.e. hall effect or shunt
+//! amplifier) whose output voltage is linear in the current.
+//!     voltage = sample * divider_ratio
+//!     current = (sample - offset) / scale
+
+use core::cell::RefCell;
+
+/// measured pack voltage and current
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BatteryData {
+    /// volts
+    pub voltage: f32,
+    /// amperes (None without a current sensor)
+    pub current: Option<f32>,
+}
+
+pub trait BatteryReader {
+    /// Retrieves the latest measurement of the pack.
+    fn get_data(&self) -> Result<BatteryData, &str>;
+}
+
+/// single ADC channel (i.e. per the HAL of the MCU)
+pub trait AdcChannel {
+    /// latest sample (counts)
+    fn read(&mut self) -> Result<u16, &'static str>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdcConfig {
+    /// voltage of the full scale sample (volts)
+    pub reference_voltage: f32,
+    /// resolution of the samples (bits)
+    pub resolution: u8,
+    /// pack voltage per the voltage of the divider output
+    pub divider_ratio: f32,
+    /// current sensor output per ampere (mV/A)
+    pub current_scale: f32,
+    /// current sensor output at zero current (mV)
+    pub current_offset: f32,
+}
+impl Default for AdcConfig {
+    /// typical of flight controller boards (i.e. a 10k/1k divider)
+    fn default() -> Self {
+        AdcConfig {
+            reference_voltage: 3.3,
+            resolution: 12,
+            divider_ratio: 11.0,
+            current_scale: 40.0,
+            current_offset: 0.0,
+        }
+    }
+}
+impl AdcConfig {
+    /// voltage (volts) at the pin per the sample
+    fn pin_voltage(&self, sample: u16) -> f32 {
+        let full_scale = ((1u32 << self.resolution) - 1) as f32;
+        sample as f32 * self.reference_voltage / full_scale
+    }
+
+    pub fn voltage(&self, sample: u16) -> f32 {
+        self.pin_voltage(sample) * self.divider_ratio
+    }
+
+    pub fn current(&self, sample: u16) -> f32 {
+        (self.pin_voltage(sample) * 1000.0 - self.current_offset) / self.current_scale
+    }
+}
+
+pub struct AdcBattery<V: AdcChannel, C: AdcChannel> {
+    pub config: AdcConfig,
+    voltage: RefCell<V>,
+    current: Option<RefCell<C>>,
+}
+
+impl<V: AdcChannel, C: AdcChannel> AdcBattery<V, C> {
+    pub fn new(config: AdcConfig, voltage: V, current: Option<C>) -> Self {
+        AdcBattery {
+            config,
+            voltage: RefCell::new(voltage),
+            current: current.map(RefCell::new),
+        }
+    }
+}
+
+impl<V: AdcChannel, C: AdcChannel> BatteryReader for AdcBattery<V, C> {
+    fn get_data(&self) -> Result<BatteryData, &str> {
+        let voltage = self.config.voltage(self.voltage.borrow_mut().read()?);
+        let current = match &self.current {
+            Some(channel) => Some(self.config.current(channel.borrow_mut().read()?)),
+            None => None,
+        };
+        Ok(BatteryData { voltage, current })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Sample(u16);
+    impl AdcChannel for Sample {
+        fn read(&mut self) -> Result<u16, &'static str> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn scales_samples() {
+        let config = AdcConfig::default();
+        // 16.8V (4S full) is 1.527V at the pin
+        let voltage = (1.527 / 3.3 * 4095.0) as u16;
+        // 20A is 0.8V at the pin
+        let current = (0.8 / 3.3 * 4095.0) as u16;
+        let battery = AdcBattery::new(config, Sample(voltage), Some(Sample(current)));
+        let data = battery.get_data().unwrap();
+        assert!((data.voltage - 16.8).abs() < 0.02, "{data:?}");
+        assert!((data.current.unwrap() - 20.0).abs() < 0.05, "{data:?}");
+
+        let battery = AdcBattery::<_, Sample>::new(config, Sample(voltage), None);
+        assert_eq!(battery.get_data().unwrap().current, None);
+    }
+}
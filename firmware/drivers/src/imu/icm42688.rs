@@ -144,9 +144,9 @@ impl<'a, SPIDEVICE: embedded_hal_async::spi::SpiDevice> ICM42688<'a, SPIDEVICE>
         }
 
         Ok(ICM42688 {
-            spi_dev: spi_dev,
-            gyro_scale: gyro_scale,
-            accel_scale: accel_scale,
+            spi_dev,
+            gyro_scale,
+            accel_scale,
         })
     }
 
@@ -169,16 +169,13 @@ impl<'a, SPIDEVICE: embedded_hal_async::spi::SpiDevice> ICM42688<'a, SPIDEVICE>
         const REG_START: u8 = 0x1f;
         buf[0] = FLAG_READ_REG | REG_START;
 
-        let result = self.spi_dev.transfer_in_place(&mut buf).await;
-        if result.is_err() {
-            return Err(result.unwrap_err());
-        }
+        self.spi_dev.transfer_in_place(&mut buf).await?;
 
         debug!("read_imu [{:?}]", buf);
 
         Ok(crate::imu_traits::ImuData {
-            accelerometer: Self::rawaccel_to_mps2(&self, &buf[1..7]),
-            gyroscope: Self::rawgyro_to_dps(&self, &buf[7..13]),
+            accelerometer: Self::rawaccel_to_mps2(self, &buf[1..7]),
+            gyroscope: Self::rawgyro_to_dps(self, &buf[7..13]),
             ..Default::default()
         })
     }
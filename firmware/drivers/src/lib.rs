@@ -2,6 +2,7 @@
 
 // re-export shared dependencies
 pub use nmea;   // GPS sentence parsing support
+pub use embedded_hal;   // hardware abstraction traits
 
 // provide IMU traits
 pub mod imu_traits;
@@ -17,5 +18,14 @@ pub mod gps_traits {
 // provide IMU drivers
 pub mod imu;
 
+// provide R/C input traits
+pub mod rc_traits;
+
+// provide motor (ESC) and servo drivers
+pub mod motor;
+
+// provide battery (voltage/current) monitoring
+pub mod battery;
+
 
 pub mod radio;
\ No newline at end of file
@@ -0,0 +1,403 @@
+//! DShot digital ESC protocol
+//!
+//! Each frame is 16 bits sent MSB first
+//! <pre>
+//! struct DshotFrame {
+//!     value: u11,         // 0 (stop), 1-47 (commands), 48-2047 (throttle)
+//!     telemetry: u1,      // request the ESC send telemetry
+//!     crc: u4,            // value ^ (value >> 4) ^ (value >> 8) of (value:telemetry)
+//! }
+//! </pre>
+//! Each bit is a fixed period pulse where the high time encodes the bit
+//!     * 1 - high for 75% of the bit period
+//!     * 0 - high for 37.5% of the bit period
+//!
+//! ### Bidirectional DShot
+//! The signal is inverted (idle high) and the CRC is inverted. After each frame
+//! the ESC responds on the same wire (at 5/4 the bit rate) with 21 bits
+//! (start bit + GCR encoded 16 bits) where each transition is a 1.
+//! <pre>
+//! struct TelemetryResponse {
+//!     exponent: u3,
+//!     mantissa: u9,       // period (us) of an electrical revolution = mantissa << exponent
+//!     crc: u4,            // ~(value ^ (value >> 4) ^ (value >> 8)) of (exponent:mantissa)
+//! }
+//! </pre>
+//!
+//! https://brushlesswhoop.com/dshot-and-bidirectional-dshot/
+//! https://github.com/betaflight/betaflight/blob/master/src/main/drivers/dshot.c
+
+use super::MotorTelemetry;
+
+/// bits per frame
+pub const FRAME_BITS: usize = 16;
+/// low bit periods sent after each frame (frame separation)
+pub const RESET_BITS: usize = 2;
+/// duty cycles needed to generate a frame (see `Waveform`)
+pub const WAVEFORM_LEN: usize = FRAME_BITS + RESET_BITS;
+
+/// smallest value interpreted as throttle (values below are commands)
+pub const MIN_THROTTLE: u16 = 48;
+/// largest throttle value
+pub const MAX_THROTTLE: u16 = 2047;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Speed {
+    DShot150,
+    DShot300,
+    DShot600,
+}
+impl Speed {
+    /// bit rate (bits per second)
+    pub fn bit_rate_hz(&self) -> u32 {
+        match self {
+            Speed::DShot150 => 150_000,
+            Speed::DShot300 => 300_000,
+            Speed::DShot600 => 600_000,
+        }
+    }
+
+    /// duration of a frame (including reset) in nanoseconds
+    pub fn frame_ns(&self) -> u32 {
+        ((WAVEFORM_LEN as u64) * 1_000_000_000 / (self.bit_rate_hz() as u64)) as u32
+    }
+}
+
+/// special commands (only acted upon while motors are stopped)
+/// https://github.com/betaflight/betaflight/blob/master/src/main/drivers/dshot_command.h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    MotorStop = 0,
+    Beep1 = 1,
+    Beep2 = 2,
+    Beep3 = 3,
+    Beep4 = 4,
+    Beep5 = 5,
+    EscInfo = 6,
+    SpinDirection1 = 7,
+    SpinDirection2 = 8,
+    Mode3dOff = 9,
+    Mode3dOn = 10,
+    SettingsRequest = 11,
+    SaveSettings = 12,
+    ExtendedTelemetryEnable = 13,
+    ExtendedTelemetryDisable = 14,
+    SpinDirectionNormal = 20,
+    SpinDirectionReversed = 21,
+}
+impl Command {
+    /// number of consecutive frames needed for the ESC to accept the command
+    pub fn repeat(&self) -> usize {
+        match self {
+            Command::SpinDirection1
+            | Command::SpinDirection2
+            | Command::Mode3dOff
+            | Command::Mode3dOn
+            | Command::SaveSettings
+            | Command::ExtendedTelemetryEnable
+            | Command::ExtendedTelemetryDisable
+            | Command::SpinDirectionNormal
+            | Command::SpinDirectionReversed => 6,
+            _ => 1,
+        }
+    }
+
+    /// command requires telemetry bit be set
+    pub fn telemetry(&self) -> bool {
+        !matches!(
+            self,
+            Command::MotorStop
+                | Command::Beep1
+                | Command::Beep2
+                | Command::Beep3
+                | Command::Beep4
+                | Command::Beep5
+        )
+    }
+}
+
+/// value for the throttle (0.0 - 1.0)
+///     0.0 stops the motor, otherwise scales into [MIN_THROTTLE, MAX_THROTTLE]
+pub fn throttle_value(throttle: f32) -> u16 {
+    if throttle <= 0.0 {
+        return Command::MotorStop as u16;
+    }
+    let range = (MAX_THROTTLE - MIN_THROTTLE) as f32;
+    MIN_THROTTLE + (throttle.min(1.0) * range) as u16
+}
+
+/// 4-bit checksum of the (value:telemetry) bits
+fn crc(data: u16) -> u16 {
+    (data ^ (data >> 4) ^ (data >> 8)) & 0x0F
+}
+
+/// encode the value (command or throttle) into a frame
+pub fn frame(value: u16, telemetry: bool) -> u16 {
+    let data = ((value & MAX_THROTTLE) << 1) | (telemetry as u16);
+    (data << 4) | crc(data)
+}
+
+/// encode the command into a frame
+pub fn command_frame(command: Command) -> u16 {
+    frame(command as u16, command.telemetry())
+}
+
+/// encode the value (command or throttle) into a bidirectional frame (inverted crc)
+pub fn frame_bidirectional(value: u16, telemetry: bool) -> u16 {
+    frame(value, telemetry) ^ 0x0F
+}
+
+/// timer compare values (duty cycles) generating frames
+///     bit periods are `max_duty` timer ticks
+pub struct Waveform {
+    max_duty: u16,
+}
+impl Waveform {
+    pub fn new(max_duty: u16) -> Self {
+        Waveform { max_duty }
+    }
+
+    /// duty cycle for a bit
+    pub fn duty(&self, bit: bool) -> u16 {
+        let period = self.max_duty as u32;
+        if bit {
+            (period * 3 / 4) as u16
+        } else {
+            (period * 3 / 8) as u16
+        }
+    }
+
+    /// duty cycles for the frame (MSB first) followed by the reset period
+    pub fn encode(&self, frame: u16, duty: &mut [u16; WAVEFORM_LEN]) {
+        for (i, d) in duty.iter_mut().take(FRAME_BITS).enumerate() {
+            *d = self.duty((frame >> (FRAME_BITS - 1 - i)) & 1 == 1);
+        }
+        duty[FRAME_BITS..].fill(0);
+    }
+}
+
+/// bits of the telemetry response (start bit + 20 GCR bits)
+pub const RESPONSE_BITS: usize = 21;
+/// period value sent by a stopped motor
+const PERIOD_STOPPED: u16 = 0xFFF;
+
+/// GCR quintet to nibble (0xFF - invalid)
+const GCR_DECODE: [u8; 32] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x09, 0x0A, 0x0B, 0xFF, 0x0D, 0x0E, 0x0F,
+    0xFF, 0xFF, 0x02, 0x03, 0xFF, 0x05, 0x06, 0x07, 0xFF, 0x00, 0x08, 0x01, 0xFF, 0x04, 0x0C, 0xFF,
+];
+
+/// reconstruct the response from the timestamps of its transitions
+///     `bit_ticks` is the duration of a response bit (in timestamp ticks)
+pub fn response_from_edges(edges: &[u32], bit_ticks: u32) -> Result<u32, &'static str> {
+    if edges.len() < 2 {
+        return Err("no response");
+    }
+    let mut value: u32 = 0;
+    let mut bits = 0;
+    for pair in edges.windows(2) {
+        // each transition is a 1 followed by the 0s until the next transition
+        let len = (pair[1].wrapping_sub(pair[0]) + bit_ticks / 2) / bit_ticks;
+        let len = (len as usize).max(1);
+        if bits + len > RESPONSE_BITS {
+            return Err("response too long");
+        }
+        value = (value << len) | (1 << (len - 1));
+        bits += len;
+    }
+    // the line returns to idle after the final transition
+    if bits < RESPONSE_BITS {
+        let len = RESPONSE_BITS - bits;
+        value = (value << len) | (1 << (len - 1));
+    }
+    Ok(value)
+}
+
+/// decode the response into the period value (exponent:mantissa)
+pub fn decode_response(response: u32) -> Result<u16, &'static str> {
+    // discard the start bit
+    let gcr = response & 0xFFFFF;
+    let mut value: u16 = 0;
+    for quintet in (0..4).rev() {
+        let nibble = GCR_DECODE[((gcr >> (quintet * 5)) & 0x1F) as usize];
+        if nibble == 0xFF {
+            return Err("invalid gcr");
+        }
+        value = (value << 4) | (nibble as u16);
+    }
+    let csum = value ^ (value >> 4) ^ (value >> 8) ^ (value >> 12);
+    if csum & 0x0F != 0x0F {
+        return Err("invalid crc");
+    }
+    Ok(value >> 4)
+}
+
+/// electrical revolutions per minute of the period value
+pub fn erpm(period: u16) -> u32 {
+    if period == PERIOD_STOPPED {
+        return 0;
+    }
+    let period_us = ((period & 0x1FF) as u32) << (period >> 9);
+    if period_us == 0 {
+        return 0;
+    }
+    60_000_000 / period_us
+}
+
+/// mechanical revolutions per minute
+///     `pole_count` is the number of magnets of the motor (typically 14)
+pub fn erpm_to_rpm(erpm: u32, pole_count: u8) -> f32 {
+    (erpm as f32) / ((pole_count / 2).max(1) as f32)
+}
+
+/// weight of the latest response in the error rate (exponential moving average)
+const ERROR_RATE_ALPHA: f32 = 0.01;
+
+/// tracks the telemetry responses of the motors
+///     motors are reported as failing (error rate 1.0) until a response is decoded
+pub struct RpmTelemetry<const N: usize> {
+    pole_count: u8,
+    motors: [MotorTelemetry; N],
+}
+impl<const N: usize> RpmTelemetry<N> {
+    pub const fn new(pole_count: u8) -> Self {
+        RpmTelemetry {
+            pole_count,
+            motors: [MotorTelemetry {
+                rpm: 0.0,
+                error_rate: 1.0,
+            }; N],
+        }
+    }
+
+    /// account for the response (if any) of the motor
+    ///     upon failure, the previous rpm is retained
+    pub fn update(&mut self, motor: usize, response: Option<u32>) {
+        let telemetry = &mut self.motors[motor];
+        match response.ok_or("no response").and_then(decode_response) {
+            Ok(period) => {
+                telemetry.rpm = erpm_to_rpm(erpm(period), self.pole_count);
+                telemetry.error_rate -= ERROR_RATE_ALPHA * telemetry.error_rate;
+            }
+            Err(_) => {
+                telemetry.error_rate += ERROR_RATE_ALPHA * (1.0 - telemetry.error_rate);
+            }
+        }
+    }
+
+    pub fn get(&self) -> [MotorTelemetry; N] {
+        self.motors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GCR_ENCODE: [u32; 16] = [
+        0x19, 0x1B, 0x12, 0x13, 0x1D, 0x15, 0x16, 0x17, 0x1A, 0x09, 0x0A, 0x0B, 0x1E, 0x0D, 0x0E,
+        0x0F,
+    ];
+
+    /// transition timestamps of the response for the period value
+    fn response_edges(period: u16, bit_ticks: u32, edges: &mut [u32; RESPONSE_BITS]) -> usize {
+        let csum = period ^ (period >> 4) ^ (period >> 8);
+        let value = (period << 4) | (!csum & 0x0F);
+        let mut gcr: u32 = 0;
+        for nibble in (0..4).rev() {
+            gcr = (gcr << 5) | GCR_ENCODE[((value >> (nibble * 4)) & 0x0F) as usize];
+        }
+        // start bit
+        let response = (1 << 20) | gcr;
+        let mut count = 0;
+        for bit in (0..RESPONSE_BITS).rev() {
+            if (response >> bit) & 1 == 1 {
+                edges[count] = 1000 + (RESPONSE_BITS - bit) as u32 * bit_ticks;
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    /// https://brushlesswhoop.com/dshot-and-bidirectional-dshot/#calculating-the-crc
+    fn encodes_frame() {
+        assert_eq!(frame(1046, false), 0b1000001011000110);
+        assert_eq!(frame(1046, true), 0b1000001011010111);
+    }
+
+    #[test]
+    fn encodes_commands() {
+        assert_eq!(command_frame(Command::MotorStop), 0);
+        // beep does not request telemetry
+        assert_eq!(command_frame(Command::Beep1), 0b0000000000100010);
+        // settings require telemetry bit and repeats
+        assert_eq!(command_frame(Command::SaveSettings) >> 4, (12 << 1) | 1);
+        assert_eq!(Command::SaveSettings.repeat(), 6);
+    }
+
+    #[test]
+    fn scales_throttle() {
+        assert_eq!(throttle_value(0.0), 0);
+        assert_eq!(throttle_value(-1.0), 0);
+        assert_eq!(throttle_value(0.0001), MIN_THROTTLE);
+        assert_eq!(throttle_value(1.0), MAX_THROTTLE);
+        assert_eq!(throttle_value(2.0), MAX_THROTTLE);
+    }
+
+    #[test]
+    fn encodes_waveform() {
+        let waveform = Waveform::new(80);
+        let mut duty = [0xFFFF; WAVEFORM_LEN];
+        waveform.encode(frame(1046, false), &mut duty);
+        let expected_bits = [1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 0];
+        for (i, bit) in expected_bits.iter().enumerate() {
+            assert_eq!(duty[i], if *bit == 1 { 60 } else { 30 }, "bit {i}");
+        }
+        assert_eq!(&duty[FRAME_BITS..], &[0; RESET_BITS], "missing reset");
+    }
+
+    #[test]
+    fn encodes_bidirectional_frame() {
+        assert_eq!(frame_bidirectional(1046, false), 0b1000001011001001);
+    }
+
+    #[test]
+    fn decodes_rpm_response() {
+        // 2000us period (mantissa 250, exponent 3) is 30000 erpm
+        let period = (3 << 9) | 250;
+        let mut edges = [0; RESPONSE_BITS];
+        let count = response_edges(period, 16, &mut edges);
+        let response = response_from_edges(&edges[..count], 16).unwrap();
+        assert_eq!(decode_response(response), Ok(period));
+        assert_eq!(erpm(period), 30_000);
+        assert_eq!(erpm_to_rpm(erpm(period), 14), 30_000.0 / 7.0);
+        assert_eq!(erpm(PERIOD_STOPPED), 0);
+    }
+
+    #[test]
+    fn tracks_errors() {
+        let mut telemetry = RpmTelemetry::<2>::new(14);
+        assert!(telemetry.get().iter().all(|m| m.error_rate == 1.0));
+        let mut edges = [0; RESPONSE_BITS];
+        let count = response_edges(250, 16, &mut edges);
+        let response = response_from_edges(&edges[..count], 16).ok();
+        for _ in 0..500 {
+            telemetry.update(0, response);
+            telemetry.update(1, None);
+        }
+        // corrupted response
+        telemetry.update(0, response.map(|r| r ^ 0x10));
+        let motors = telemetry.get();
+        assert_eq!(motors[0].rpm, erpm_to_rpm(erpm(250), 14));
+        assert!(motors[0].error_rate > 0.0 && motors[0].error_rate < 0.02);
+        assert_eq!(motors[1].rpm, 0.0);
+        assert!(motors[1].error_rate > 0.6);
+    }
+
+    #[test]
+    fn frame_timing() {
+        // DShot600 bit is 1.67us
+        assert_eq!(Speed::DShot600.frame_ns(), 30_000);
+    }
+}
@@ -0,0 +1,39 @@
+//! Support for motor (ESC) and servo outputs
+
+
+/// digital ESC protocol
+pub mod dshot;
+
+/// pulse width ESC and servo protocols
+pub mod pwm;
+
+/// motor outputs
+///     commands are normalized (0.0 stopped - 1.0 full speed) and only take
+///     effect while armed, motors are stopped upon being disarmed
+pub trait Motors<const N: usize> {
+    /// set the command for all motors
+    fn set_outputs(&self, outputs: [f32; N]);
+
+    /// allow the motors to spin
+    fn arm(&self);
+
+    /// stop all motors (and ignore commands until armed)
+    fn disarm(&self);
+
+    fn is_armed(&self) -> bool;
+}
+
+/// measured motor speed
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotorTelemetry {
+    /// revolutions per minute
+    pub rpm: f32,
+    /// fraction (0.0 - 1.0) of recent measurements that failed
+    pub error_rate: f32,
+}
+
+/// motors providing speed feedback (i.e. bidirectional DShot)
+pub trait RpmReader<const N: usize> {
+    /// Retrieves the latest measurements of all motors.
+    fn get_rpm(&self) -> Result<[MotorTelemetry; N], &str>;
+}
@@ -0,0 +1,239 @@
+//! Pulse width ESC and servo outputs
+//!
+//! Each output is a pulse repeated at the update rate, where the pulse width
+//! encodes the command. Servos (and standard ESCs) use 1000-2000us pulses,
+//! faster ESC protocols (OneShot, Multishot) scale the pulse width down so
+//! that they can be updated at higher rates.
+//!
+//! Outputs are driven by any `embedded_hal::pwm::SetDutyCycle` channel, where
+//! the PWM frequency of the channel must be configured to the update rate.
+
+use core::cell::{Cell, RefCell};
+
+use embedded_hal::pwm::SetDutyCycle;
+use log::*;
+
+use super::Motors;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    /// servos and standard ESCs (1000-2000us)
+    Standard,
+    /// 125-250us
+    OneShot125,
+    /// 42-84us
+    OneShot42,
+    /// 5-25us
+    Multishot,
+}
+impl Protocol {
+    /// nominal pulse widths (microseconds)
+    pub fn calibration(&self) -> Calibration {
+        let (min_us, max_us) = match self {
+            Protocol::Standard => (1000.0, 2000.0),
+            Protocol::OneShot125 => (125.0, 250.0),
+            Protocol::OneShot42 => (42.0, 84.0),
+            Protocol::Multishot => (5.0, 25.0),
+        };
+        Calibration {
+            min_us,
+            center_us: (min_us + max_us) / 2.0,
+            max_us,
+        }
+    }
+}
+
+/// pulse widths (microseconds) of an output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// low throttle (or servo end point)
+    pub min_us: f32,
+    /// servo neutral
+    pub center_us: f32,
+    /// full throttle (or servo end point)
+    pub max_us: f32,
+}
+
+pub struct PwmOutputs<P: SetDutyCycle, const N: usize> {
+    channels: RefCell<[P; N]>,
+    calibration: [Calibration; N],
+    /// duration of each update (microseconds)
+    period_us: f32,
+    armed: Cell<bool>,
+}
+
+impl<P: SetDutyCycle, const N: usize> PwmOutputs<P, N> {
+    /// outputs using the nominal pulse widths of the protocol
+    ///     channels must be configured with a PWM frequency of `update_rate_hz`
+    pub fn new(
+        channels: [P; N],
+        protocol: Protocol,
+        update_rate_hz: u32,
+    ) -> Result<Self, &'static str> {
+        Self::new_calibrated(channels, [protocol.calibration(); N], update_rate_hz)
+    }
+
+    /// outputs using per channel pulse widths
+    ///     channels must be configured with a PWM frequency of `update_rate_hz`
+    pub fn new_calibrated(
+        channels: [P; N],
+        calibration: [Calibration; N],
+        update_rate_hz: u32,
+    ) -> Result<Self, &'static str> {
+        let period_us = 1_000_000.0 / (update_rate_hz as f32);
+        for c in calibration.iter() {
+            if !(c.min_us <= c.center_us && c.center_us <= c.max_us) {
+                return Err("invalid calibration");
+            }
+            if c.max_us >= period_us {
+                return Err("update rate too fast for pulse width");
+            }
+        }
+        Ok(PwmOutputs {
+            channels: RefCell::new(channels),
+            calibration,
+            period_us,
+            armed: Cell::new(false),
+        })
+    }
+
+    pub fn calibration(&self, channel: usize) -> Calibration {
+        self.calibration[channel]
+    }
+
+    /// output a pulse width (microseconds) limited to the calibration
+    pub fn set_pulse_us(&self, channel: usize, pulse_us: f32) -> Result<(), &'static str> {
+        let c = &self.calibration[channel];
+        let pulse_us = pulse_us.clamp(c.min_us, c.max_us);
+        let mut channels = self.channels.borrow_mut();
+        let pwm = &mut channels[channel];
+        let duty = (pulse_us / self.period_us * (pwm.max_duty_cycle() as f32) + 0.5) as u16;
+        pwm.set_duty_cycle(duty).map_err(|e| {
+            error!("pwm failed [{:?}]", e);
+            "pwm failed"
+        })
+    }
+
+    /// output a throttle (0.0 - 1.0) between min and max
+    pub fn set_throttle(&self, channel: usize, throttle: f32) -> Result<(), &'static str> {
+        let c = &self.calibration[channel];
+        self.set_pulse_us(
+            channel,
+            c.min_us + throttle.clamp(0.0, 1.0) * (c.max_us - c.min_us),
+        )
+    }
+
+    /// output a servo position (-1.0 - 1.0) about the center
+    pub fn set_position(&self, channel: usize, position: f32) -> Result<(), &'static str> {
+        let c = &self.calibration[channel];
+        let position = position.clamp(-1.0, 1.0);
+        let pulse_us = if position < 0.0 {
+            c.center_us + position * (c.center_us - c.min_us)
+        } else {
+            c.center_us + position * (c.max_us - c.center_us)
+        };
+        self.set_pulse_us(channel, pulse_us)
+    }
+}
+
+/// pulse width ESCs
+impl<P: SetDutyCycle, const N: usize> Motors<N> for PwmOutputs<P, N> {
+    fn set_outputs(&self, outputs: [f32; N]) {
+        if !self.armed.get() {
+            return;
+        }
+        for (motor, output) in outputs.iter().enumerate() {
+            if let Err(e) = self.set_throttle(motor, *output) {
+                warn!("motor {motor} not updated [{e}]");
+            }
+        }
+    }
+
+    fn arm(&self) {
+        self.armed.set(true);
+    }
+
+    fn disarm(&self) {
+        self.armed.set(false);
+        for motor in 0..N {
+            if let Err(e) = self.set_throttle(motor, 0.0) {
+                error!("motor {motor} not stopped [{e}]");
+            }
+        }
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+
+    struct MockPwm {
+        duty: u16,
+    }
+    impl embedded_hal::pwm::ErrorType for MockPwm {
+        type Error = Infallible;
+    }
+    impl SetDutyCycle for MockPwm {
+        fn max_duty_cycle(&self) -> u16 {
+            10_000
+        }
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+            self.duty = duty;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn scales_pulse_widths() {
+        // 50Hz is a 20ms period
+        let outputs = PwmOutputs::new_calibrated(
+            [MockPwm { duty: 0 }, MockPwm { duty: 0 }],
+            [
+                Protocol::Standard.calibration(),
+                Calibration {
+                    min_us: 1100.0,
+                    center_us: 1400.0,
+                    max_us: 1900.0,
+                },
+            ],
+            50,
+        )
+        .unwrap();
+        let duty = |channel: usize| outputs.channels.borrow()[channel].duty;
+
+        outputs.set_throttle(0, 0.5).unwrap();
+        assert_eq!(duty(0), 750);
+        outputs.set_throttle(0, 2.0).unwrap();
+        assert_eq!(duty(0), 1000);
+
+        outputs.set_position(1, 0.0).unwrap();
+        assert_eq!(duty(1), 700);
+        outputs.set_position(1, -1.0).unwrap();
+        assert_eq!(duty(1), 550);
+        outputs.set_position(1, 0.5).unwrap();
+        assert_eq!(duty(1), 825);
+    }
+
+    #[test]
+    fn ignores_outputs_while_disarmed() {
+        let motors = PwmOutputs::new([MockPwm { duty: 0 }], Protocol::OneShot125, 2_000).unwrap();
+        motors.set_outputs([1.0]);
+        assert_eq!(motors.channels.borrow()[0].duty, 0);
+        motors.arm();
+        motors.set_outputs([1.0]);
+        assert_eq!(motors.channels.borrow()[0].duty, 5_000);
+        motors.disarm();
+        assert_eq!(motors.channels.borrow()[0].duty, 2_500);
+    }
+
+    #[test]
+    fn rejects_update_rate_beyond_pulse_width() {
+        assert!(PwmOutputs::new([MockPwm { duty: 0 }], Protocol::OneShot125, 4_000).is_err());
+        assert!(PwmOutputs::new([MockPwm { duty: 0 }], Protocol::OneShot125, 2_000).is_ok());
+    }
+}
@@ -14,6 +14,8 @@
 //! </pre>
 //! Where CRC is calculated per [CRC-8/DVB-S2](https://www.etsi.org/deliver/etsi_en/302300_302399/302307/01.02.01_60/en_302307v010201p.pdf#page=16)
 
+use super::RcChannels;
+
 pub const SYNC: u8 = 0xC8;
 
 /// https://github.com/betaflight/betaflight/blob/master/src/main/rx/crsf_protocol.h#L44
@@ -77,6 +79,34 @@ fn crc(data: &[u8]) -> u8 {
     crc
 }
 
+/// number of channels provided by the RcChannelsPacked frame
+pub const NUM_CHANNELS: usize = 16;
+const RC_CHANNELS_PAYLOAD_SIZE: usize = 22;
+
+/// decode the RcChannelsPacked payload (16 channels of 11 bits, LSB first)
+/// https://github.com/betaflight/betaflight/blob/master/src/main/rx/crsf.c#L467
+pub fn rc_channels(payload: &[u8]) -> Result<RcChannels, &'static str> {
+    if payload.len() < RC_CHANNELS_PAYLOAD_SIZE {
+        return Err("rc channels payload too short");
+    }
+    let mut channels = RcChannels {
+        count: NUM_CHANNELS,
+        ..Default::default()
+    };
+    for (i, value) in channels.values.iter_mut().enumerate() {
+        let bit = i * 11;
+        let byte = bit / 8;
+        let mut raw = (payload[byte] as u32) | ((payload[byte + 1] as u32) << 8);
+        if byte + 2 < RC_CHANNELS_PAYLOAD_SIZE {
+            raw |= (payload[byte + 2] as u32) << 16;
+        }
+        let ticks = (raw >> (bit % 8)) & 0x7FF;
+        // 172..1811 ticks maps to 988..2012us
+        *value = (ticks * 5 / 8 + 880) as u16;
+    }
+    Ok(channels)
+}
+
 /// https://github.com/betaflight/betaflight/blob/master/src/main/telemetry/crsf.c#L239
 pub struct GpsData {
     /// TODO what are the units?
@@ -121,4 +151,24 @@ mod tests {
         let input = "123456789".as_bytes().trim_ascii_end();
         assert_eq!(crc(input), 0xBC, "failed crc calculation");
     }
+
+    #[test]
+    fn csrf_rc_channels() {
+        // all channels centered (992 ticks)
+        let mut payload = [0u8; RC_CHANNELS_PAYLOAD_SIZE];
+        for i in 0..NUM_CHANNELS {
+            let bit = i * 11;
+            for b in 0..11 {
+                if (992 >> b) & 1 == 1 {
+                    payload[(bit + b) / 8] |= 1 << ((bit + b) % 8);
+                }
+            }
+        }
+        let channels = rc_channels(&payload).unwrap();
+        assert_eq!(channels.count, NUM_CHANNELS);
+        assert!(
+            channels.values.iter().all(|v| *v == 1500),
+            "failed channel decode"
+        );
+    }
 }
@@ -0,0 +1,395 @@
+//! FlySky iBUS
+//!
+//! Serial (115200 8N1) protocol used by FlySky receivers. Every frame is
+//! prefixed by its total length and suffixed by a checksum.
+//! <pre>
+//! struct IbusFrame {
+//!     length: u8,
+//!     command: u8,
+//!     payload: [u8; length - 4],
+//!     /// 0xFFFF - sum(length, command, payload)
+//!     checksum: u16le,
+//! }
+//! </pre>
+//!
+//! ### Servo (RX) port
+//! The receiver sends a servo frame (length 0x20, command 0x40) every ~7ms
+//! containing 14 channels (u16le pulse width in microseconds).
+//!
+//! ### Sensor (telemetry) port
+//! A half-duplex single-wire bus where the receiver polls sensor addresses
+//! (1..=15, address 0 is the receiver itself) and the sensors respond.
+//! <pre>
+//! receiver                    sensor
+//!   [0x04, 0x80|addr, chk] --> [0x04, 0x80|addr, chk]                  discover
+//!   [0x04, 0x90|addr, chk] --> [0x06, 0x90|addr, type, size, chk]      type
+//!   [0x04, 0xA0|addr, chk] --> [4+size, 0xA0|addr, value[size], chk]   measurement
+//! </pre>
+//! As the bus is half-duplex, the UART will receive its own responses - the
+//! decoder skips as many bytes as were transmitted (the echo of a discover
+//! response is otherwise indistinguishable from the command).
+//!
+//! https://github.com/betaflight/betaflight/blob/master/src/main/rx/ibus.c
+//! https://github.com/betaflight/betaflight/blob/master/src/main/telemetry/ibus_shared.c
+
+use log::*;
+
+use super::RcChannels;
+
+/// number of channels provided by the servo frame
+pub const NUM_CHANNELS: usize = 14;
+/// largest frame supported by the protocol
+pub const MAX_FRAME_SIZE: usize = 0x20;
+/// smallest frame supported by the protocol (length, command, checksum)
+const MIN_FRAME_SIZE: usize = 4;
+
+const SERVO_FRAME_SIZE: u8 = 0x20;
+const CMD_SERVO: u8 = 0x40;
+const CMD_DISCOVER: u8 = 0x80;
+const CMD_SENSOR_TYPE: u8 = 0x90;
+const CMD_MEASUREMENT: u8 = 0xA0;
+const CMD_MASK: u8 = 0xF0;
+const ADDRESS_MASK: u8 = 0x0F;
+
+/// 0xFFFF - sum of all bytes
+fn checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0xFFFF_u16, |chk, b| chk.wrapping_sub(*b as u16))
+}
+
+/// determine if the frame (including checksum) is valid
+fn is_valid(frame: &[u8]) -> bool {
+    let len = frame.len();
+    if len < MIN_FRAME_SIZE || (frame[0] as usize) != len {
+        return false;
+    }
+    let chk = u16::from_le_bytes([frame[len - 2], frame[len - 1]]);
+    checksum(&frame[..len - 2]) == chk
+}
+
+/// frames sent by the receiver
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    /// servo (channel) update
+    Channels(RcChannels),
+    /// sensor polling
+    Command(Command),
+}
+
+/// sensor polling commands (from the receiver)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// request for the sensor at address to announce itself
+    Discover(u8),
+    /// request for the type of sensor at address
+    SensorType(u8),
+    /// request for the latest measurement of the sensor at address
+    Measurement(u8),
+}
+
+/// decode a complete (validated) frame
+pub fn decode(frame: &[u8]) -> Result<Frame, &'static str> {
+    if !is_valid(frame) {
+        return Err("invalid frame");
+    }
+    let command = frame[1];
+    if frame[0] == SERVO_FRAME_SIZE && command == CMD_SERVO {
+        let mut channels = RcChannels {
+            count: NUM_CHANNELS,
+            ..Default::default()
+        };
+        for (i, value) in channels.values.iter_mut().take(NUM_CHANNELS).enumerate() {
+            *value = u16::from_le_bytes([frame[2 + 2 * i], frame[3 + 2 * i]]);
+        }
+        return Ok(Frame::Channels(channels));
+    }
+    if frame.len() == MIN_FRAME_SIZE {
+        let address = command & ADDRESS_MASK;
+        return match command & CMD_MASK {
+            CMD_DISCOVER => Ok(Frame::Command(Command::Discover(address))),
+            CMD_SENSOR_TYPE => Ok(Frame::Command(Command::SensorType(address))),
+            CMD_MEASUREMENT => Ok(Frame::Command(Command::Measurement(address))),
+            _ => Err("unknown command"),
+        };
+    }
+    Err("unknown frame")
+}
+
+/// reassembles frames from a serial byte stream
+pub struct Decoder {
+    buf: [u8; MAX_FRAME_SIZE],
+    len: usize,
+    /// echoed bytes of the responses yet to be received
+    echo: usize,
+}
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            buf: [0; MAX_FRAME_SIZE],
+            len: 0,
+            echo: 0,
+        }
+    }
+
+    /// bytes transmitted on the (half-duplex) bus, whose echo is skipped
+    pub fn transmitted(&mut self, len: usize) {
+        self.echo += len;
+    }
+
+    /// consume a received byte, providing the frame once complete
+    ///     invalid frames are dropped
+    pub fn push(&mut self, byte: u8) -> Option<Frame> {
+        if self.echo > 0 {
+            self.echo -= 1;
+            return None;
+        }
+        // a frame begins with its length
+        if self.len == 0 && !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE).contains(&(byte as usize)) {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < (self.buf[0] as usize) {
+            return None;
+        }
+
+        let frame_len = self.len;
+        self.len = 0;
+        match decode(&self.buf[..frame_len]) {
+            Ok(frame) => Some(frame),
+            Err(e) => {
+                warn!("dropped ibus frame [{}]", e);
+                None
+            }
+        }
+    }
+
+    /// discard any partially received frame (i.e. upon a gap in the stream)
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+}
+
+/// https://github.com/betaflight/betaflight/blob/master/src/main/telemetry/ibus_shared.h
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SensorType {
+    /// 0.01V
+    InternalVoltage = 0x00,
+    /// 0.1°C offset by 40°C (i.e. 400 is 0°C)
+    Temperature = 0x01,
+    /// revolutions per minute
+    RpmFlysky = 0x02,
+    /// 0.01V
+    ExternalVoltage = 0x03,
+    /// 0.01V
+    CellVoltage = 0x04,
+    /// 0.01A
+    BatteryCurrent = 0x05,
+    /// percent remaining
+    Fuel = 0x06,
+    /// revolutions per minute
+    Rpm = 0x07,
+    /// degrees
+    Heading = 0x08,
+    /// 0.01m/s
+    ClimbRate = 0x09,
+    /// fix type and number of satellites
+    GpsStatus = 0x0B,
+    /// 1e-7 degrees
+    GpsLatitude = 0x80,
+    /// 1e-7 degrees
+    GpsLongitude = 0x81,
+    /// 0.01m
+    GpsAltitude = 0x82,
+    /// 0.01m (relative to launch)
+    Altitude = 0x83,
+    /// 0.01m (relative to launch)
+    AltitudeMax = 0x84,
+}
+impl SensorType {
+    /// number of bytes used to report a measurement
+    pub fn size(&self) -> usize {
+        if (*self as u8) >= 0x80 { 4 } else { 2 }
+    }
+}
+
+/// maximum number of sensors on the bus (address 0 is the receiver)
+pub const MAX_SENSORS: usize = 15;
+/// largest sensor response
+pub const MAX_RESPONSE_SIZE: usize = 8;
+
+/// sensor side of the telemetry bus
+///     sensors occupy consecutive addresses (in the order provided) starting
+///     with the first address polled by the receiver
+pub struct Telemetry<const N: usize> {
+    sensors: [SensorType; N],
+    values: [i32; N],
+    /// address assigned by the receiver to the first sensor
+    first_address: Option<u8>,
+}
+impl<const N: usize> Telemetry<N> {
+    pub fn new(sensors: [SensorType; N]) -> Self {
+        assert!(N <= MAX_SENSORS, "too many ibus sensors");
+        Telemetry {
+            sensors,
+            values: [0; N],
+            first_address: None,
+        }
+    }
+
+    /// update the value reported for the sensor (per SensorType units)
+    pub fn set_value(&mut self, sensor: usize, value: i32) {
+        self.values[sensor] = value;
+    }
+
+    /// find the sensor at the bus address
+    fn sensor_index(&mut self, address: u8) -> Option<usize> {
+        if address == 0 {
+            // address 0 is the receiver itself
+            return None;
+        }
+        // the first discovery determines the address of the first sensor
+        let first = *self.first_address.get_or_insert(address);
+        if address < first {
+            return None;
+        }
+        let index = (address - first) as usize;
+        if index < N { Some(index) } else { None }
+    }
+
+    /// provide the response (if any) to a receiver command
+    ///     returns the number of bytes to transmit from `response`
+    pub fn respond(&mut self, command: Command, response: &mut [u8; MAX_RESPONSE_SIZE]) -> usize {
+        let len = match command {
+            Command::Discover(address) => {
+                if self.sensor_index(address).is_none() {
+                    return 0;
+                }
+                response[1] = CMD_DISCOVER | address;
+                MIN_FRAME_SIZE
+            }
+            Command::SensorType(address) => {
+                let Some(i) = self.sensor_index(address) else {
+                    return 0;
+                };
+                response[1] = CMD_SENSOR_TYPE | address;
+                response[2] = self.sensors[i] as u8;
+                response[3] = self.sensors[i].size() as u8;
+                MIN_FRAME_SIZE + 2
+            }
+            Command::Measurement(address) => {
+                let Some(i) = self.sensor_index(address) else {
+                    return 0;
+                };
+                let size = self.sensors[i].size();
+                response[1] = CMD_MEASUREMENT | address;
+                response[2..2 + size].copy_from_slice(&self.values[i].to_le_bytes()[..size]);
+                MIN_FRAME_SIZE + size
+            }
+        };
+        response[0] = len as u8;
+        let chk = checksum(&response[..len - 2]);
+        response[len - 2..len].copy_from_slice(&chk.to_le_bytes());
+        len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// servo frame of 14 channels (sticks centered, throttle low, channels 5-6 high)
+    const SERVO_FRAME: [u8; 32] = [
+        0x20, 0x40, 0xDC, 0x05, 0xDC, 0x05, 0xE8, 0x03, 0xDC, 0x05, 0xD0, 0x07, 0xD0, 0x07, 0xDC,
+        0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05, 0xDC, 0x05,
+        0x5B, 0xF3,
+    ];
+
+    #[test]
+    fn decodes_servo_frame() {
+        let mut decoder = Decoder::new();
+        let mut frame = None;
+        for b in SERVO_FRAME {
+            frame = decoder.push(b);
+        }
+        let Some(Frame::Channels(channels)) = frame else {
+            panic!("servo frame not decoded");
+        };
+        assert_eq!(channels.count, NUM_CHANNELS);
+        assert_eq!(channels.get(0), Some(1500));
+        assert_eq!(channels.get(2), Some(1000));
+        assert_eq!(channels.get(4), Some(2000));
+        assert_eq!(channels.get(NUM_CHANNELS), None);
+    }
+
+    #[test]
+    fn drops_corrupt_frame() {
+        let mut corrupt = SERVO_FRAME;
+        corrupt[5] ^= 0x01;
+        let mut decoder = Decoder::new();
+        for b in corrupt {
+            assert_eq!(decoder.push(b), None);
+        }
+    }
+
+    #[test]
+    fn responds_to_sensor_polling() {
+        let mut telemetry = Telemetry::new([SensorType::ExternalVoltage, SensorType::Altitude]);
+        telemetry.set_value(0, 1260);
+        telemetry.set_value(1, -150);
+        let mut response = [0; MAX_RESPONSE_SIZE];
+
+        // discover (echo)
+        let discover = [0x04, 0x81, 0x7A, 0xFF];
+        let Ok(Frame::Command(command)) = decode(&discover) else {
+            panic!("command not decoded");
+        };
+        let len = telemetry.respond(command, &mut response);
+        assert_eq!(&response[..len], &discover);
+
+        // type
+        let len = telemetry.respond(Command::SensorType(2), &mut response);
+        assert_eq!(&response[..4], &[0x06, 0x92, 0x83, 0x04]);
+        assert!(is_valid(&response[..len]));
+
+        // measurement
+        let len = telemetry.respond(Command::Measurement(1), &mut response);
+        assert_eq!(&response[..4], &[0x06, 0xA1, 0xEC, 0x04]);
+        assert!(is_valid(&response[..len]));
+        let len = telemetry.respond(Command::Measurement(2), &mut response);
+        assert_eq!(&response[2..6], &(-150_i32).to_le_bytes());
+        assert!(is_valid(&response[..len]));
+
+        // unassigned addresses are not answered
+        assert_eq!(telemetry.respond(Command::Discover(3), &mut response), 0);
+    }
+
+    #[test]
+    fn skips_echoed_responses() {
+        let mut telemetry = Telemetry::new([SensorType::ExternalVoltage]);
+        let mut decoder = Decoder::new();
+        let mut response = [0; MAX_RESPONSE_SIZE];
+        // polled by the receiver, where each response is received back
+        for command in [CMD_DISCOVER, CMD_SENSOR_TYPE, CMD_MEASUREMENT] {
+            let chk = checksum(&[0x04, command | 1]).to_le_bytes();
+            let mut frame = None;
+            for b in [0x04, command | 1, chk[0], chk[1]] {
+                frame = decoder.push(b);
+            }
+            let Some(Frame::Command(command)) = frame else {
+                panic!("command not decoded");
+            };
+            let len = telemetry.respond(command, &mut response);
+            assert!(len > 0);
+            decoder.transmitted(len);
+            for b in &response[..len] {
+                assert_eq!(decoder.push(*b), None);
+            }
+        }
+    }
+}
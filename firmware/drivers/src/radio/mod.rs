@@ -2,7 +2,47 @@
 /// common R/C serial framing protocol
 pub mod csrf;
 
+/// FlySky iBUS receivers (and sensor telemetry)
+pub mod ibus;
+
 
 // FIXME implement
 // /// mesh over wifi
-// pub mod mesh;
\ No newline at end of file
+// pub mod mesh;
+
+/// most channels provided by any supported receiver protocol
+pub const MAX_RC_CHANNELS: usize = 16;
+
+/// R/C channel values as provided by a receiver
+///     values are pulse widths in microseconds (nominally 1000-2000, centered at 1500)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RcChannels {
+    pub values: [u16; MAX_RC_CHANNELS],
+    /// number of channels provided by the receiver
+    pub count: usize,
+}
+impl Default for RcChannels {
+    fn default() -> Self {
+        RcChannels {
+            values: [RC_CENTER_US; MAX_RC_CHANNELS],
+            count: 0,
+        }
+    }
+}
+impl RcChannels {
+    /// value of the channel (0 indexed), if provided by the receiver
+    pub fn get(&self, channel: usize) -> Option<u16> {
+        if channel < self.count {
+            Some(self.values[channel])
+        } else {
+            None
+        }
+    }
+}
+
+/// minimum nominal channel pulse width (microseconds)
+pub const RC_MIN_US: u16 = 1000;
+/// center channel pulse width (microseconds)
+pub const RC_CENTER_US: u16 = 1500;
+/// maximum nominal channel pulse width (microseconds)
+pub const RC_MAX_US: u16 = 2000;
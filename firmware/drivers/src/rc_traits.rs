@@ -0,0 +1,363 @@
+//! R/C (remote control) input
+//!
+//! Receivers (CRSF, iBUS, ...) provide raw channel pulse widths. The `RcMapper`
+//! converts those channels into pilot intent (`RcCommand`) per an `RcConfig`,
+//! so consumers are independent of the receiver protocol and transmitter setup.
+
+use crate::radio::{RC_CENTER_US, RC_MAX_US, RC_MIN_US, RcChannels};
+
+// -- Standard R/C functions ---
+pub trait RcInput {
+    /// Retrieves the latest channels provided by the receiver.
+    fn get_channels(&self) -> Result<RcChannels, &str>;
+}
+
+/// assignment of the sticks to receiver channels (0 indexed)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelMap {
+    /// aileron (roll), elevator (pitch), throttle, rudder (yaw) - i.e. FrSky/CRSF
+    AETR,
+    /// throttle, aileron (roll), elevator (pitch), rudder (yaw) - i.e. Spektrum
+    TAER,
+    Custom {
+        roll: usize,
+        pitch: usize,
+        throttle: usize,
+        yaw: usize,
+    },
+}
+impl ChannelMap {
+    /// channels of the (roll, pitch, throttle, yaw) sticks
+    pub fn channels(&self) -> (usize, usize, usize, usize) {
+        match *self {
+            ChannelMap::AETR => (0, 1, 2, 3),
+            ChannelMap::TAER => (1, 2, 0, 3),
+            ChannelMap::Custom {
+                roll,
+                pitch,
+                throttle,
+                yaw,
+            } => (roll, pitch, throttle, yaw),
+        }
+    }
+}
+
+/// shaping of a centered stick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StickCurve {
+    /// pulse width (microseconds) around center treated as center
+    pub deadband_us: u16,
+    /// 0.0 (linear) to 1.0 (cubic) - softens response around center
+    pub expo: f32,
+    /// output at full deflection (i.e. deg/s for rate control)
+    pub rate: f32,
+}
+impl Default for StickCurve {
+    fn default() -> Self {
+        StickCurve {
+            deadband_us: 5,
+            expo: 0.0,
+            rate: 1.0,
+        }
+    }
+}
+impl StickCurve {
+    /// shape the pulse width into [-rate, rate]
+    pub fn apply(&self, value_us: u16) -> f32 {
+        let offset = (value_us as i32) - (RC_CENTER_US as i32);
+        let half_range = (RC_MAX_US - RC_CENTER_US) as i32;
+        // full deflection remains beyond the deadband
+        let deadband = (self.deadband_us as i32).min(half_range - 1);
+        if offset.abs() <= deadband {
+            return 0.0;
+        }
+        // rescale so that output is continuous at the edge of the deadband
+        let span = (half_range - deadband) as f32;
+        let x = ((offset - offset.signum() * deadband) as f32 / span).clamp(-1.0, 1.0);
+        let shaped = x * (1.0 - self.expo) + x * x * x * self.expo;
+        shaped * self.rate
+    }
+}
+
+/// flight modes selectable by the pilot
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RcMode {
+    /// pilot provides the trajectory
+    #[default]
+    Direct,
+    /// maintain position
+    Hold,
+    /// follow the mission
+    Autonomous,
+    /// return to home (then land)
+    Return,
+}
+
+/// selects the mode while the channel is within [min_us, max_us]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeRange {
+    pub channel: usize,
+    pub min_us: u16,
+    pub max_us: u16,
+    pub mode: RcMode,
+}
+
+/// arming requested while the channel is within [min_us, max_us]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArmingSwitch {
+    pub channel: usize,
+    pub min_us: u16,
+    pub max_us: u16,
+}
+
+/// maximum number of mode ranges
+pub const MAX_MODE_RANGES: usize = 6;
+
+pub struct RcConfig {
+    pub channel_map: ChannelMap,
+    pub roll: StickCurve,
+    pub pitch: StickCurve,
+    pub yaw: StickCurve,
+    /// pulse width (microseconds) at or below which throttle is considered low
+    pub throttle_low_us: u16,
+    pub arming: ArmingSwitch,
+    /// mode switch positions (first matching range selects the mode)
+    pub modes: [Option<ModeRange>; MAX_MODE_RANGES],
+    /// mode when no range matches
+    pub default_mode: RcMode,
+}
+impl Default for RcConfig {
+    /// AETR with arming on channel 5 (high), a 3 position mode switch on
+    ///     channel 6 and return to home on channel 7 (high)
+    fn default() -> Self {
+        let mut modes = [None; MAX_MODE_RANGES];
+        modes[0] = Some(ModeRange {
+            channel: 6,
+            min_us: 1700,
+            max_us: 2100,
+            mode: RcMode::Return,
+        });
+        modes[1] = Some(ModeRange {
+            channel: 5,
+            min_us: 900,
+            max_us: 1300,
+            mode: RcMode::Direct,
+        });
+        modes[2] = Some(ModeRange {
+            channel: 5,
+            min_us: 1300,
+            max_us: 1700,
+            mode: RcMode::Hold,
+        });
+        modes[3] = Some(ModeRange {
+            channel: 5,
+            min_us: 1700,
+            max_us: 2100,
+            mode: RcMode::Autonomous,
+        });
+        RcConfig {
+            channel_map: ChannelMap::AETR,
+            roll: StickCurve::default(),
+            pitch: StickCurve::default(),
+            yaw: StickCurve::default(),
+            throttle_low_us: 1050,
+            arming: ArmingSwitch {
+                channel: 4,
+                min_us: 1700,
+                max_us: 2100,
+            },
+            modes,
+            default_mode: RcMode::Direct,
+        }
+    }
+}
+
+/// pilot intent
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RcCommand {
+    /// shaped per RcConfig::roll (positive right)
+    pub roll: f32,
+    /// shaped per RcConfig::pitch (positive nose up)
+    pub pitch: f32,
+    /// shaped per RcConfig::yaw (positive clockwise from above)
+    pub yaw: f32,
+    /// 0.0 (low) to 1.0 (high), None when the channel is missing (unknown)
+    pub throttle: Option<f32>,
+    /// pilot requests the vehicle be armed
+    pub arm: bool,
+    pub mode: RcMode,
+}
+
+/// converts receiver channels into pilot intent
+pub struct RcMapper {
+    pub config: RcConfig,
+    arm: bool,
+    /// arming switch position of the previous update
+    arm_switch: Option<bool>,
+}
+impl RcMapper {
+    pub fn new(config: RcConfig) -> Self {
+        RcMapper {
+            config,
+            arm: false,
+            arm_switch: None,
+        }
+    }
+
+    /// map the channels into pilot intent
+    ///     arming requires the switch be moved into the arming range while
+    ///     throttle is low (a switch left armed, i.e. at power up, is ignored).
+    ///     Moving the switch out of the arming range always disarms.
+    pub fn update(&mut self, channels: &RcChannels) -> RcCommand {
+        let config = &self.config;
+        let (roll_ch, pitch_ch, throttle_ch, yaw_ch) = config.channel_map.channels();
+        let channel = |ch: usize| channels.get(ch).unwrap_or(RC_CENTER_US);
+
+        // a missing throttle is unknown (rather than low), refusing arming
+        let throttle_us = channels.get(throttle_ch);
+        let throttle_low = throttle_us.is_some_and(|us| us <= config.throttle_low_us);
+
+        let arm_us = channels.get(config.arming.channel).unwrap_or(RC_MIN_US);
+        let arm_switch = (config.arming.min_us..=config.arming.max_us).contains(&arm_us);
+        if !arm_switch {
+            self.arm = false;
+        } else if self.arm_switch == Some(false) && throttle_low {
+            self.arm = true;
+        }
+        self.arm_switch = Some(arm_switch);
+
+        let mode = config
+            .modes
+            .iter()
+            .flatten()
+            .find(|m| {
+                channels
+                    .get(m.channel)
+                    .is_some_and(|v| (m.min_us..=m.max_us).contains(&v))
+            })
+            .map_or(config.default_mode, |m| m.mode);
+
+        RcCommand {
+            roll: config.roll.apply(channel(roll_ch)),
+            pitch: config.pitch.apply(channel(pitch_ch)),
+            yaw: config.yaw.apply(channel(yaw_ch)),
+            throttle: throttle_us.map(|us| {
+                ((us.clamp(RC_MIN_US, RC_MAX_US) - RC_MIN_US) as f32)
+                    / ((RC_MAX_US - RC_MIN_US) as f32)
+            }),
+            arm: self.arm,
+            mode,
+        }
+    }
+
+    /// upon loss of the receiver, drop the arming request
+    ///     the switch must be cycled to re-arm once the receiver recovers
+    pub fn signal_lost(&mut self) {
+        self.arm = false;
+        self.arm_switch = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(values: &[u16]) -> RcChannels {
+        let mut channels = RcChannels {
+            count: values.len(),
+            ..Default::default()
+        };
+        channels.values[..values.len()].copy_from_slice(values);
+        channels
+    }
+
+    #[test]
+    fn shapes_sticks() {
+        let curve = StickCurve {
+            deadband_us: 10,
+            expo: 0.5,
+            rate: 200.0,
+        };
+        assert_eq!(curve.apply(1505), 0.0, "deadband not applied");
+        assert_eq!(curve.apply(2000), 200.0, "full deflection not at rate");
+        assert_eq!(curve.apply(1000), -200.0, "full deflection not at rate");
+        // expo softens the response around center
+        let half = curve.apply(1755);
+        assert!(half > 0.0 && half < 100.0, "expo not applied [{half}]");
+
+        // a deadband spanning the deflection is clamped
+        let curve = StickCurve {
+            deadband_us: 600,
+            ..curve
+        };
+        assert_eq!(curve.apply(1900), 0.0);
+        assert_eq!(curve.apply(2000), 200.0, "full deflection not at rate");
+        assert_eq!(curve.apply(1000), -200.0, "full deflection not at rate");
+    }
+
+    #[test]
+    fn maps_taer() {
+        let mut mapper = RcMapper::new(RcConfig {
+            channel_map: ChannelMap::TAER,
+            ..Default::default()
+        });
+        let command = mapper.update(&channels(&[2000, 1000, 1500, 1500]));
+        assert_eq!(command.throttle, Some(1.0));
+        assert_eq!(command.roll, -1.0);
+        assert_eq!(command.pitch, 0.0);
+    }
+
+    #[test]
+    fn arms_upon_switch_transition_with_throttle_low() {
+        let mut mapper = RcMapper::new(RcConfig::default());
+        let mut armed = |throttle: u16, switch: u16| {
+            mapper
+                .update(&channels(&[1500, 1500, throttle, 1500, switch, 2000]))
+                .arm
+        };
+        // switch already armed at start is ignored
+        assert!(!armed(1000, 2000));
+        // toggle the switch with throttle high is ignored
+        assert!(!armed(1000, 1000));
+        assert!(!armed(1500, 2000));
+        // toggle the switch with throttle low arms
+        assert!(!armed(1000, 1000));
+        assert!(armed(1000, 2000));
+        // remains armed with throttle raised
+        assert!(armed(1800, 2000));
+        // switch off disarms
+        assert!(!armed(1800, 1000));
+    }
+
+    #[test]
+    fn refuses_arming_without_throttle() {
+        // throttle beyond the channels of the receiver
+        let mut mapper = RcMapper::new(RcConfig {
+            channel_map: ChannelMap::Custom {
+                roll: 0,
+                pitch: 1,
+                throttle: 7,
+                yaw: 3,
+            },
+            ..Default::default()
+        });
+        let mut update = |switch: u16| mapper.update(&channels(&[1500, 1500, 1000, 1500, switch]));
+        assert!(!update(1000).arm);
+        let command = update(2000);
+        assert_eq!(command.throttle, None);
+        assert!(!command.arm);
+    }
+
+    #[test]
+    fn selects_mode() {
+        let mut mapper = RcMapper::new(RcConfig::default());
+        let mode = |mapper: &mut RcMapper, switch: u16| {
+            mapper
+                .update(&channels(&[1500, 1500, 1000, 1500, 1000, switch]))
+                .mode
+        };
+        assert_eq!(mode(&mut mapper, 1000), RcMode::Direct);
+        assert_eq!(mode(&mut mapper, 1500), RcMode::Hold);
+        assert_eq!(mode(&mut mapper, 2000), RcMode::Autonomous);
+    }
+}
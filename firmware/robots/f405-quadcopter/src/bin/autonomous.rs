@@ -6,6 +6,10 @@ use panic_reset as _;
 
 use log::*;
 
+use rusty_robot_drivers::motor::dshot;
+use rusty_robot_f405_quadcopter::dshot::DshotMotors;
+use rusty_robot_systems::flight_controller::recovery::{RETAINED_SIZE, Retained};
+
 // FIXME move to common
 // support a dynamically constructed static object
 // When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
@@ -24,8 +28,21 @@ embassy_stm32::bind_interrupts!(pub struct Irqs {
     OTG_FS => embassy_stm32::usb::InterruptHandler<embassy_stm32::peripherals::USB_OTG_FS>;
 });
 
+// flight state retained across resets (i.e. panic_reset mid-flight)
+//     placed in memory that isn't initialized by the startup code, so it is
+//     only accessed by volatile copies (its content is validated by the load)
+#[unsafe(link_section = ".uninit.retained")]
+static mut RETAINED: core::mem::MaybeUninit<[u8; RETAINED_SIZE]> = core::mem::MaybeUninit::uninit();
+
 #[embassy_executor::main]
 async fn main(spawner: embassy_executor::Spawner) {
+    // check for a flight to resume prior to anything else
+    // SAFETY: only accessed by the main context, where the volatile read
+    //  copies out whatever the memory holds (validated by the checksum)
+    let bytes =
+        unsafe { core::ptr::read_volatile((&raw const RETAINED).cast::<[u8; RETAINED_SIZE]>()) };
+    let snapshot = Retained::from_bytes(bytes).load();
+
     let peripherals = rusty_robot_f405_quadcopter::init();
 
     // create the USB driver
@@ -44,7 +61,43 @@ async fn main(spawner: embassy_executor::Spawner) {
         .spawn(rusty_robot_f405_quadcopter::usb::logger_task(usb_driver))
         .unwrap();
     info!("Initializing...");
+    match snapshot {
+        Ok(snapshot) => info!("resuming [{:?}]", snapshot.state),
+        Err(e) => info!("cold boot [{e}]"),
+    }
+    // TODO flight controller - until then a snapshot isn't stored, so no
+    //  flight is resumed
+    //  FlightController::resume(drone, config, &snapshot) upon a valid snapshot
+    //  each cycle store(&fc.snapshot()) into a Retained, then write_volatile
+    //  its bytes into RETAINED
+
+    // start the motor outputs
+    // TODO grok the betaflight configs to choose pins (motors must share a timer)
+    {
+        use embassy_stm32::gpio::OutputType;
+        use embassy_stm32::timer::low_level::CountingMode;
+        use embassy_stm32::timer::simple_pwm::{PwmPin, SimplePwm};
+        let pwm = SimplePwm::new(
+            peripherals.TIM3,
+            Some(PwmPin::new(peripherals.PB4, OutputType::PushPull)),
+            Some(PwmPin::new(peripherals.PB5, OutputType::PushPull)),
+            Some(PwmPin::new(peripherals.PB0, OutputType::PushPull)),
+            Some(PwmPin::new(peripherals.PB1, OutputType::PushPull)),
+            rusty_robot_f405_quadcopter::dshot::pwm_frequency(MOTORS.speed),
+            CountingMode::EdgeAlignedUp,
+        );
+        spawner.spawn(motors_task(pwm, peripherals.DMA1_CH2)).unwrap();
+    }
+}
+
+static MOTORS: DshotMotors = DshotMotors::new(dshot::Speed::DShot600);
 
+#[embassy_executor::task]
+async fn motors_task(
+    pwm: embassy_stm32::timer::simple_pwm::SimplePwm<'static, embassy_stm32::peripherals::TIM3>,
+    dma: embassy_stm32::Peri<'static, embassy_stm32::peripherals::DMA1_CH2>,
+) {
+    MOTORS.run(pwm, dma).await;
 }
 
 // #[embassy_executor::task]
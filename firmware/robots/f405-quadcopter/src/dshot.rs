@@ -0,0 +1,205 @@
+//! DShot motor outputs
+//!
+//! Motors share a 4 channel timer, where each PWM period is a DShot bit. A DMA
+//! burst (on timer update) loads the compare values of all channels for each
+//! bit, so that all motors are updated simultaneously without CPU involvement.
+//!
+//! In bidirectional mode the outputs are inverted and the ESCs respond to each
+//! frame with their eRPM, which is tracked per motor for the flight controller.
+//! The responses aren't yet captured (see the FIXME of `run()`), so the
+//! robot uses the unidirectional mode and doesn't provide the motor speeds.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_stm32::Peri;
+use embassy_stm32::time::Hertz;
+use embassy_stm32::timer::low_level::OutputPolarity;
+use embassy_stm32::timer::{Channel, GeneralInstance4Channel, UpDma, simple_pwm::SimplePwm};
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Ticker};
+
+use rusty_robot_drivers::motor::{MotorTelemetry, Motors, RpmReader, dshot};
+
+pub const NUM_MOTORS: usize = 4;
+
+/// rate at which frames are sent to the ESCs
+///     ESCs disarm if frames are not received regularly
+const FRAME_RATE_HZ: u64 = 4_000;
+
+#[derive(Clone, Copy)]
+struct Output {
+    /// per motor value (throttle or command)
+    values: [u16; NUM_MOTORS],
+    telemetry: bool,
+    /// number of frames to send (0 - continuously)
+    repeat: usize,
+}
+impl Output {
+    const STOP: Output = Output {
+        values: [dshot::Command::MotorStop as u16; NUM_MOTORS],
+        telemetry: false,
+        repeat: 0,
+    };
+}
+
+pub struct DshotMotors {
+    pub speed: dshot::Speed,
+    output: Signal<CriticalSectionRawMutex, Output>,
+    armed: AtomicBool,
+    /// telemetry of bidirectional mode
+    telemetry: Option<Mutex<CriticalSectionRawMutex, RefCell<dshot::RpmTelemetry<NUM_MOTORS>>>>,
+}
+
+/// PWM frequency needed for the speed (one PWM period per bit)
+pub fn pwm_frequency(speed: dshot::Speed) -> Hertz {
+    Hertz(speed.bit_rate_hz())
+}
+
+impl DshotMotors {
+    pub const fn new(speed: dshot::Speed) -> Self {
+        DshotMotors {
+            speed,
+            output: Signal::new(),
+            armed: AtomicBool::new(false),
+            telemetry: None,
+        }
+    }
+
+    /// motors (ESCs) responding with their eRPM
+    ///     `pole_count` is the number of magnets of the motors (typically 14)
+    pub const fn new_bidirectional(speed: dshot::Speed, pole_count: u8) -> Self {
+        DshotMotors {
+            speed,
+            output: Signal::new(),
+            armed: AtomicBool::new(false),
+            telemetry: Some(Mutex::new(RefCell::new(dshot::RpmTelemetry::new(
+                pole_count,
+            )))),
+        }
+    }
+
+    /// account for the response of the motor to the latest frame
+    ///     `edges` are the timestamps of the transitions of the response and
+    ///     `bit_ticks` the duration of a response bit (5/4 the frame bit rate)
+    pub fn record_response(&self, motor: usize, edges: &[u32], bit_ticks: u32) {
+        if let Some(telemetry) = &self.telemetry {
+            let response = dshot::response_from_edges(edges, bit_ticks).ok();
+            telemetry.lock(|t| t.borrow_mut().update(motor, response));
+        }
+    }
+
+    /// send a special command to all motors (ignored while armed)
+    pub fn command(&self, command: dshot::Command) {
+        self.command_motor(None, command);
+    }
+
+    /// send a special command to a motor (others are stopped, ignored while armed)
+    pub fn command_motor(&self, motor: Option<usize>, command: dshot::Command) {
+        if self.is_armed() {
+            log::warn!("ignored dshot command {:?} while armed", command);
+            return;
+        }
+        let mut values = [dshot::Command::MotorStop as u16; NUM_MOTORS];
+        match motor {
+            Some(i) => values[i] = command as u16,
+            None => values = [command as u16; NUM_MOTORS],
+        }
+        self.output.signal(Output {
+            values,
+            telemetry: command.telemetry(),
+            repeat: command.repeat(),
+        });
+    }
+
+    /// continuously send frames to the ESCs
+    ///     pwm must be configured per `pwm_frequency()` with channels 1-4 as the motors
+    pub async fn run<T: GeneralInstance4Channel>(
+        &self,
+        mut pwm: SimplePwm<'static, T>,
+        mut dma: Peri<'static, impl UpDma<T>>,
+    ) -> ! {
+        let waveform = dshot::Waveform::new(pwm.max_duty_cycle());
+        let bidirectional = self.telemetry.is_some();
+        if bidirectional {
+            // bidirectional signals idle high
+            for ch in [Channel::Ch1, Channel::Ch2, Channel::Ch3, Channel::Ch4] {
+                pwm.channel(ch).set_polarity(OutputPolarity::ActiveLow);
+            }
+        }
+        // compare values interleaved by bit - [bit0: ch1..ch4, bit1: ch1..ch4, ...]
+        let mut duty = [0u16; dshot::WAVEFORM_LEN * NUM_MOTORS];
+        let mut bits = [0u16; dshot::WAVEFORM_LEN];
+
+        let mut output = Output::STOP;
+        let mut ticker = Ticker::every(Duration::from_hz(FRAME_RATE_HZ));
+        loop {
+            if let Some(update) = self.output.try_take() {
+                output = update;
+            }
+
+            for (motor, value) in output.values.iter().enumerate() {
+                let frame = if bidirectional {
+                    dshot::frame_bidirectional(*value, output.telemetry)
+                } else {
+                    dshot::frame(*value, output.telemetry)
+                };
+                waveform.encode(frame, &mut bits);
+                for (bit, d) in bits.iter().enumerate() {
+                    duty[bit * NUM_MOTORS + motor] = *d;
+                }
+            }
+            pwm.waveform_up_multi_channel(dma.reborrow(), Channel::Ch1, Channel::Ch4, &duty)
+                .await;
+            // FIXME capture the bidirectional responses
+            //  embassy doesn't yet support switching a timer pin between PWM output and
+            //  input capture, once it does capture the edges of each motor and provide
+            //  them to `record_response()`
+
+            // once a command has been repeated, stop the motors
+            match output.repeat {
+                0 => {}
+                1 => output = Output::STOP,
+                _ => output.repeat -= 1,
+            }
+
+            ticker.next().await;
+        }
+    }
+}
+
+impl Motors<NUM_MOTORS> for DshotMotors {
+    fn set_outputs(&self, outputs: [f32; NUM_MOTORS]) {
+        if !self.is_armed() {
+            return;
+        }
+        self.output.signal(Output {
+            values: outputs.map(dshot::throttle_value),
+            telemetry: false,
+            repeat: 0,
+        });
+    }
+
+    fn arm(&self) {
+        self.armed.store(true, Ordering::Relaxed);
+    }
+
+    fn disarm(&self) {
+        self.armed.store(false, Ordering::Relaxed);
+        self.output.signal(Output::STOP);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.armed.load(Ordering::Relaxed)
+    }
+}
+
+impl RpmReader<NUM_MOTORS> for DshotMotors {
+    fn get_rpm(&self) -> Result<[MotorTelemetry; NUM_MOTORS], &str> {
+        match &self.telemetry {
+            Some(telemetry) => Ok(telemetry.lock(|t| t.borrow().get())),
+            None => Err("not bidirectional"),
+        }
+    }
+}
@@ -31,6 +31,9 @@ pub fn init() -> embassy_stm32::Peripherals {
     embassy_stm32::init(clock_config)
 }
 
+/// DShot motor outputs
+pub mod dshot;
+
 pub mod usb {
     const EP_OUT_BUFFER_SIZE: usize = 10 * 1024;
     // provide a static so that usb_driver can be used in threads
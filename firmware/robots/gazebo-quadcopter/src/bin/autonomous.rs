@@ -3,9 +3,12 @@ use embassy_time::{Duration, Ticker};
 use log::*;
 use std::env;
 
-use rusty_robot_common::mk_static;
-use rusty_robot_gazebo_quadcopter::GazeboDrone;
-use rusty_robot_systems::flight_controller::quadcopter::FlightController;
+use rusty_robot_common::{Vector3, mk_static};
+use rusty_robot_drivers::motor::RpmReader;
+use rusty_robot_gazebo_quadcopter::{GazeboDrone, NUM_MOTORS};
+use rusty_robot_systems::flight_controller::mixer::Geometry;
+use rusty_robot_systems::flight_controller::multicopter::{Config, FlightController};
+use rusty_robot_systems::flight_controller::state_machine::{Event, State};
 
 use embassy_executor::Spawner;
 #[embassy_executor::main]
@@ -31,9 +34,19 @@ async fn main(spawner: Spawner) {
     spawner.spawn(drone_task(drone)).unwrap();
 
     // create the flight controller as a static instance
-    let fc = &mut *mk_static!(FlightController<GazeboDrone>, FlightController::new(drone));
-    // run the flight controller in main context
     const CYCLE_RATE_HZ: u64 = 8000;
+    let mut config = Config::new(Geometry::quad_x(), CYCLE_RATE_HZ as u32);
+    // the simulated gyro has no bias
+    if let Some(calibration) = config.imu_calibration.as_mut() {
+        calibration.set_gyro(&Vector3::ZERO, None);
+    }
+    let fc = &mut *mk_static!(
+        FlightController<GazeboDrone, NUM_MOTORS>,
+        FlightController::new(drone, config)
+    );
+    // run the flight controller in main context
+    let mut cycles: u64 = 0;
+    let mut launched = false;
     let mut ticker = Ticker::every(Duration::from_hz(CYCLE_RATE_HZ));
     loop {
         // TODO autonomous::step(drone);
@@ -43,8 +56,32 @@ async fn main(spawner: Spawner) {
         // let velocities_pct: [u8; 4] = [51, 51, 51, 51];
         // <GazeboDrone as rusty_robot_systems::QuadCopterMotors>::set_data(drone, velocities_pct);
 
+        // the autonomous control is the (mesh) link
+        fc.link_received();
+        // the motor speeds (as the ESC telemetry)
+        if let Ok(telemetry) = drone.get_rpm() {
+            fc.telemetry_received(&telemetry);
+        }
         fc.step();
 
+        // evaluate the attitude estimate against the simulation (once a second)
+        cycles += 1;
+        if cycles.is_multiple_of(CYCLE_RATE_HZ) {
+            if let Some(truth) = drone.ground_truth() {
+                let error = fc.attitude().angle_to(&truth).to_degrees();
+                debug!("attitude error {error:.2} deg [{:?}]", fc.attitude());
+            }
+            debug!(
+                "navigation {:?} [{:?}]",
+                fc.navigation(),
+                fc.navigation_health()
+            );
+            // arm and launch (once) as soon as the pre-arm checks pass
+            if !launched && fc.state() == State::Safe && fc.arm(true).is_ok() {
+                launched = fc.handle(Event::Launch).is_ok();
+            }
+        }
+
         ticker.next().await
     }
 }
@@ -1,23 +1,49 @@
 use gz::{self as gazebosim};
 
-use rusty_robot_common::Vector3;
+use rusty_robot_common::{Quaternion, Vector3};
 use rusty_robot_drivers::imu_traits::{ImuData, ImuReader};
+use rusty_robot_drivers::motor::{MotorTelemetry, Motors, RpmReader};
 use rusty_robot_drivers::{gps_traits, nmea};
 
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 
 pub struct GazeboDrone {
     pub imu_topic: String,
     imu_signal: Signal<CriticalSectionRawMutex, ImuData>,
+    /// orientation of the simulation (for evaluation of estimators)
+    orientation_signal: Signal<CriticalSectionRawMutex, Quaternion>,
 
     pub gps_topic: String,
     gps_signal: Signal<CriticalSectionRawMutex, nmea::Nmea>,
 
     pub motors_topic: String,
-    motors_signal: Signal<CriticalSectionRawMutex, [u8; 4]>,
+    motors_signal: Signal<CriticalSectionRawMutex, [f32; NUM_MOTORS]>,
+    motors_armed: AtomicBool,
+    /// latest motor commands (the simulated speeds)
+    motor_outputs: Mutex<[f32; NUM_MOTORS]>,
 }
 
-/// max RPM of motors (at 100%)
+pub const NUM_MOTORS: usize = 4;
+
+/// rotation of ENU into NED (180 degrees about north-east)
+const ENU_TO_NED: Quaternion = Quaternion {
+    w: 0.0,
+    x: std::f32::consts::FRAC_1_SQRT_2,
+    y: std::f32::consts::FRAC_1_SQRT_2,
+    z: 0.0,
+};
+/// rotation of FRD into FLU (180 degrees about forward)
+const FRD_TO_FLU: Quaternion = Quaternion {
+    w: 0.0,
+    x: 1.0,
+    y: 0.0,
+    z: 0.0,
+};
+
+/// max RPM of motors (at 1.0)
 // note the skybot caps motor velocity at 1000 radians/s (aka 9550 RPM)
 const MAX_MOTOR_RPM: f64 = 10_000.0;
 
@@ -28,6 +54,7 @@ impl GazeboDrone {
                 "/world/openworld/model/{robot_name}/link/base_link/sensor/imu_sensor/imu"
             ),
             imu_signal: Signal::new(),
+            orientation_signal: Signal::new(),
 
             gps_topic: format!(
                 "/world/openworld/model/{robot_name}/link/base_link/sensor/navsat_sensor/navsat",
@@ -36,6 +63,8 @@ impl GazeboDrone {
 
             motors_topic: format!("/{robot_name}/command/motor_speed"),
             motors_signal: Signal::new(),
+            motors_armed: AtomicBool::new(false),
+            motor_outputs: Mutex::new([0.0; NUM_MOTORS]),
         }
     }
 
@@ -49,20 +78,32 @@ impl GazeboDrone {
                 log::trace!("imu msg {:?}", msg.entity_name);
 
                 // convert the message into an IMU state
+                //  gazebo provides FLU (x forward, y left, z up) and rad/s, where
+                //  the flight controller expects FRD and deg/s
                 let imu_data = ImuData {
                     accelerometer: Some(Vector3 {
                         x: msg.linear_acceleration.x as f32,
-                        y: msg.linear_acceleration.y as f32,
-                        z: msg.linear_acceleration.z as f32,
+                        y: -msg.linear_acceleration.y as f32,
+                        z: -msg.linear_acceleration.z as f32,
                     }),
                     gyroscope: Some(Vector3 {
-                        x: msg.angular_velocity.x as f32,
-                        y: msg.angular_velocity.y as f32,
-                        z: msg.angular_velocity.z as f32,
+                        x: (msg.angular_velocity.x as f32).to_degrees(),
+                        y: -(msg.angular_velocity.y as f32).to_degrees(),
+                        z: -(msg.angular_velocity.z as f32).to_degrees(),
                     }),
                     ..Default::default()
                 };
 
+                // orientation (FLU to ENU) as FRD to NED
+                let orientation = Quaternion {
+                    w: msg.orientation.w as f32,
+                    x: msg.orientation.x as f32,
+                    y: msg.orientation.y as f32,
+                    z: msg.orientation.z as f32,
+                };
+                self.orientation_signal
+                    .signal(ENU_TO_NED * orientation * FRD_TO_FLU);
+
                 // publish the update
                 self.imu_signal.signal(imu_data);
             })
@@ -79,6 +120,10 @@ impl GazeboDrone {
                 nmea.latitude = Some(msg.latitude_deg);
                 nmea.longitude = Some(msg.longitude_deg);
                 nmea.altitude = Some(msg.altitude as f32);
+                // ground velocity as speed (knots) and course (degrees from north)
+                let (north, east) = (msg.velocity_north as f32, msg.velocity_east as f32);
+                nmea.speed_over_ground = Some((north * north + east * east).sqrt() / 0.514_444);
+                nmea.true_course = Some(east.atan2(north).to_degrees());
 
                 // publish the update
                 self.gps_signal.signal(nmea);
@@ -92,14 +137,14 @@ impl GazeboDrone {
         // handle publishing signals back to gazebosim
         loop {
             // awaits motor update
-            let velocities_pct = self.motors_signal.wait().await;
+            let outputs = self.motors_signal.wait().await;
             let mut msg = gazebosim::msgs::actuators::Actuators::new();
-            msg.velocity = vec![
-                Self::rpm_to_radians_per_second(MAX_MOTOR_RPM / 100.0 * (velocities_pct[0] as f64)),
-                Self::rpm_to_radians_per_second(MAX_MOTOR_RPM / 100.0 * (velocities_pct[1] as f64)),
-                Self::rpm_to_radians_per_second(MAX_MOTOR_RPM / 100.0 * (velocities_pct[2] as f64)),
-                Self::rpm_to_radians_per_second(MAX_MOTOR_RPM / 100.0 * (velocities_pct[3] as f64)),
-            ];
+            msg.velocity = outputs
+                .iter()
+                .map(|output| {
+                    Self::rpm_to_radians_per_second(MAX_MOTOR_RPM * (output.clamp(0.0, 1.0) as f64))
+                })
+                .collect();
             if motors_publisher.publish(&msg) {
                 log::trace!("sent motor update {:?}", msg.velocity);
             } else {
@@ -108,6 +153,11 @@ impl GazeboDrone {
         }
     }
 
+    /// latest orientation (FRD to NED) of the simulation
+    pub fn ground_truth(&self) -> Option<Quaternion> {
+        self.orientation_signal.try_take()
+    }
+
     fn rpm_to_radians_per_second(rpm: f64) -> f64 {
         use std::f64::consts::PI;
         rpm * (2.0 * PI / 60.0)
@@ -137,8 +187,37 @@ impl gps_traits::Gps for GazeboDrone {
     }
 }
 
-impl rusty_robot_systems::flight_controller::quadcopter::Motors for GazeboDrone {
-    fn set_data(&self, velocities_pct: [u8; 4]) {
-        self.motors_signal.signal(velocities_pct);
+impl Motors<NUM_MOTORS> for GazeboDrone {
+    fn set_outputs(&self, outputs: [f32; NUM_MOTORS]) {
+        if self.is_armed() {
+            *self.motor_outputs.lock().unwrap() = outputs;
+            self.motors_signal.signal(outputs);
+        }
+    }
+
+    fn arm(&self) {
+        self.motors_armed.store(true, Ordering::Relaxed);
+    }
+
+    fn disarm(&self) {
+        self.motors_armed.store(false, Ordering::Relaxed);
+        *self.motor_outputs.lock().unwrap() = [0.0; NUM_MOTORS];
+        self.motors_signal.signal([0.0; NUM_MOTORS]);
+    }
+
+    fn is_armed(&self) -> bool {
+        self.motors_armed.load(Ordering::Relaxed)
+    }
+}
+
+impl RpmReader<NUM_MOTORS> for GazeboDrone {
+    /// the commanded speeds (the simulation doesn't report the rotor speeds,
+    ///     which follow the commands per the time constant of its motor model)
+    fn get_rpm(&self) -> Result<[MotorTelemetry; NUM_MOTORS], &str> {
+        let outputs = *self.motor_outputs.lock().unwrap();
+        Ok(outputs.map(|output| MotorTelemetry {
+            rpm: (MAX_MOTOR_RPM * output.clamp(0.0, 1.0) as f64) as f32,
+            error_rate: 0.0,
+        }))
     }
 }
@@ -0,0 +1,720 @@
+//! IMU (gyroscope and accelerometer) calibration
+//!
+//! Procedures, each averaging samples while the vehicle is at rest:
+//!     * gyro bias - the average rotation, with its temperature coefficient
+//!       fitted to the biases at rest across a span of temperatures
+//!     * accelerometer - offset and scale of each axis, per the averages of
+//!       the six positions (each axis pointing up then down)
+//!     * board orientation - rotation of the IMU into the body frame, the
+//!       mounting rotation trimmed per the average while the body is level
+//!     * magnetometer - hard and soft iron (see `magnetometer`)
+//!
+//! The results are retained as parameters (`ImuCalibration`) and applied to
+//! the samples by the flight controller (per its configuration), or to the
+//! samples of any `ImuReader` by the correction layer (`CorrectedImu`).
+
+use rusty_robot_common::{Quaternion, Vector3, crc32};
+use rusty_robot_drivers::imu_traits::{ImuData, ImuReader};
+
+use super::magnetometer::{MagCalibration, MagFit};
+use crate::estimation::attitude::GRAVITY;
+
+/// `ImuData::calibration_status` bits
+pub const GYRO_CALIBRATED: u8 = 1 << 0;
+pub const ACCEL_CALIBRATED: u8 = 1 << 1;
+pub const ORIENTATION_CALIBRATED: u8 = 1 << 2;
+pub const MAG_CALIBRATED: u8 = 1 << 3;
+
+/// size of the encoded parameters (bytes)
+pub const PARAMETERS_SIZE: usize = 128;
+
+/// identifies (the version of) the parameters layout
+const MAGIC: u32 = 0x4943_4132; // "ICA2"
+
+/// parameters of the corrections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuCalibration {
+    /// calibrated corrections (`*_CALIBRATED` bits)
+    pub status: u8,
+    /// gyro bias at the reference temperature (deg/s)
+    pub gyro_bias: Vector3,
+    /// change of the gyro bias per degree (deg/s/°C)
+    pub gyro_temperature_coefficient: Vector3,
+    /// temperature of the gyro bias (°C)
+    pub reference_temperature: f32,
+    /// accelerometer reading at zero acceleration (m/s²)
+    pub accel_offset: Vector3,
+    /// accelerometer correction per axis
+    pub accel_scale: Vector3,
+    /// rotation of the IMU frame into the body frame
+    pub orientation: Quaternion,
+    /// hard and soft iron correction (IMU frame)
+    pub mag: MagCalibration,
+}
+impl Default for ImuCalibration {
+    /// uncalibrated (no correction)
+    fn default() -> Self {
+        ImuCalibration {
+            status: 0,
+            gyro_bias: Vector3::ZERO,
+            gyro_temperature_coefficient: Vector3::ZERO,
+            reference_temperature: 25.0,
+            accel_offset: Vector3::ZERO,
+            accel_scale: Vector3::new(1.0, 1.0, 1.0),
+            orientation: Quaternion::IDENTITY,
+            mag: MagCalibration::default(),
+        }
+    }
+}
+
+impl ImuCalibration {
+    /// mounting rotation of the board (roll, pitch, yaw in radians)
+    pub fn mounted(euler: &Vector3) -> Self {
+        ImuCalibration {
+            orientation: euler.euler_to_quaternion(),
+            ..Default::default()
+        }
+    }
+
+    /// gyro bias (deg/s) at the temperature (°C)
+    pub fn gyro_bias(&self, temperature: Option<f32>) -> Vector3 {
+        match temperature {
+            Some(temperature) => {
+                self.gyro_bias
+                    + self.gyro_temperature_coefficient * (temperature - self.reference_temperature)
+            }
+            None => self.gyro_bias,
+        }
+    }
+
+    /// corrected sample (body frame)
+    pub fn correct(&self, data: &ImuData) -> ImuData {
+        let rotate = |v: Vector3| self.orientation.rotate_vector(v, false);
+        ImuData {
+            gyroscope: data
+                .gyroscope
+                .map(|gyro| rotate(gyro - self.gyro_bias(data.temperature))),
+            accelerometer: data.accelerometer.map(|accel| rotate(self.accel(&accel))),
+            magnetometer: data.magnetometer.map(|mag| rotate(self.mag.correct(&mag))),
+            calibration_status: Some(self.status),
+            ..*data
+        }
+    }
+
+    /// accelerometer sample with the offset and scale corrected (IMU frame)
+    fn accel(&self, accel: &Vector3) -> Vector3 {
+        per_axis(&(*accel - self.accel_offset), &self.accel_scale)
+    }
+
+    /// apply the result of the gyro calibration
+    pub fn set_gyro(&mut self, bias: &Vector3, temperature: Option<f32>) {
+        self.gyro_bias = *bias;
+        self.gyro_temperature_coefficient = Vector3::ZERO;
+        if let Some(temperature) = temperature {
+            self.reference_temperature = temperature;
+        }
+        self.status |= GYRO_CALIBRATED;
+    }
+
+    /// apply the result of the temperature fit
+    pub fn set_gyro_temperature(&mut self, fit: &TemperatureFit) -> Result<(), &'static str> {
+        let (bias, coefficient) = fit.solve(self.reference_temperature)?;
+        self.gyro_bias = bias;
+        self.gyro_temperature_coefficient = coefficient;
+        self.status |= GYRO_CALIBRATED;
+        Ok(())
+    }
+
+    /// apply the result of the six position calibration
+    pub fn set_accel(&mut self, calibration: &AccelCalibration) -> Result<(), &'static str> {
+        let (offset, scale) = calibration.solve()?;
+        self.accel_offset = offset;
+        self.accel_scale = scale;
+        self.status |= ACCEL_CALIBRATED;
+        Ok(())
+    }
+
+    /// apply the result of the ellipsoid fit
+    pub fn set_mag(&mut self, fit: &MagFit) {
+        self.mag = fit.calibration;
+        self.status |= MAG_CALIBRATED;
+    }
+
+    /// trim the orientation per the average accelerometer sample (IMU frame)
+    ///     while the body is level
+    pub fn level(&mut self, accel: &Vector3) -> Result<(), &'static str> {
+        let measured = self
+            .orientation
+            .rotate_vector(self.accel(accel), false)
+            .normalized()
+            .ok_or("no acceleration")?;
+        // the reaction to gravity is up (body frame)
+        let up = Vector3::new(0.0, 0.0, -1.0);
+        let angle = libm::acosf(measured.dot(&up).clamp(-1.0, 1.0));
+        if angle > 45f32.to_radians() {
+            return Err("not level");
+        }
+        let trim = Quaternion::from_axis_angle(&measured.cross(&up), angle);
+        self.orientation = (trim * self.orientation).normalized();
+        self.status |= ORIENTATION_CALIBRATED;
+        Ok(())
+    }
+
+    /// encoding retained as parameters (i.e. flash)
+    pub fn to_bytes(&self) -> [u8; PARAMETERS_SIZE] {
+        let mut bytes = [0; PARAMETERS_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        bytes[4] = self.status;
+        let (bias, coefficient) = (self.gyro_bias, self.gyro_temperature_coefficient);
+        let (offset, scale, q) = (self.accel_offset, self.accel_scale, self.orientation);
+        let (hard, soft) = (self.mag.offset, self.mag.matrix);
+        let values = [
+            bias.x,
+            bias.y,
+            bias.z,
+            coefficient.x,
+            coefficient.y,
+            coefficient.z,
+            self.reference_temperature,
+            offset.x,
+            offset.y,
+            offset.z,
+            scale.x,
+            scale.y,
+            scale.z,
+            q.w,
+            q.x,
+            q.y,
+            q.z,
+            hard.x,
+            hard.y,
+            hard.z,
+            soft[0][0],
+            soft[0][1],
+            soft[0][2],
+            soft[1][0],
+            soft[1][1],
+            soft[1][2],
+            soft[2][0],
+            soft[2][1],
+            soft[2][2],
+        ];
+        for (chunk, value) in bytes[5..].chunks_exact_mut(4).zip(values) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        let crc = crc32(&bytes[..PARAMETERS_SIZE - 4]);
+        bytes[PARAMETERS_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// the retained parameters (if valid)
+    pub fn from_bytes(bytes: &[u8; PARAMETERS_SIZE]) -> Result<Self, &'static str> {
+        let word = |offset: usize| {
+            let mut data = [0; 4];
+            data.copy_from_slice(&bytes[offset..offset + 4]);
+            data
+        };
+        if crc32(&bytes[..PARAMETERS_SIZE - 4]) != u32::from_le_bytes(word(PARAMETERS_SIZE - 4)) {
+            return Err("invalid checksum");
+        }
+        if u32::from_le_bytes(word(0)) != MAGIC {
+            return Err("no calibration");
+        }
+        let v: [f32; 29] = core::array::from_fn(|i| f32::from_le_bytes(word(5 + 4 * i)));
+        Ok(ImuCalibration {
+            status: bytes[4],
+            gyro_bias: Vector3::new(v[0], v[1], v[2]),
+            gyro_temperature_coefficient: Vector3::new(v[3], v[4], v[5]),
+            reference_temperature: v[6],
+            accel_offset: Vector3::new(v[7], v[8], v[9]),
+            accel_scale: Vector3::new(v[10], v[11], v[12]),
+            orientation: Quaternion {
+                w: v[13],
+                x: v[14],
+                y: v[15],
+                z: v[16],
+            },
+            mag: MagCalibration {
+                offset: Vector3::new(v[17], v[18], v[19]),
+                matrix: [
+                    [v[20], v[21], v[22]],
+                    [v[23], v[24], v[25]],
+                    [v[26], v[27], v[28]],
+                ],
+            },
+        })
+    }
+}
+
+/// product of each axis
+fn per_axis(a: &Vector3, b: &Vector3) -> Vector3 {
+    Vector3::new(a.x * b.x, a.y * b.y, a.z * b.z)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibrationConfig {
+    /// samples averaged at rest
+    pub samples: u32,
+    /// limit of the gyro noise at rest (deg/s, 1 sigma)
+    pub max_gyro_deviation: f32,
+    /// limit of the accelerometer noise at rest (m/s², 1 sigma)
+    pub max_accel_deviation: f32,
+    /// span of the temperatures to fit the gyro bias (°C)
+    pub min_temperature_span: f32,
+    /// limit of the off axes of a position (fraction of gravity)
+    pub position_tolerance: f32,
+}
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        CalibrationConfig {
+            samples: 1000,
+            max_gyro_deviation: 0.5,
+            max_accel_deviation: 0.3,
+            min_temperature_span: 10.0,
+            position_tolerance: 0.3,
+        }
+    }
+}
+
+/// average (and deviation) of samples
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RestAverage {
+    count: u32,
+    mean: Vector3,
+    /// sum of the squared differences from the mean (per axis)
+    m2: Vector3,
+}
+
+impl RestAverage {
+    pub fn add(&mut self, sample: &Vector3) {
+        // Welford's online algorithm
+        self.count += 1;
+        let delta = *sample - self.mean;
+        self.mean += delta * (1.0 / self.count as f32);
+        self.m2 += per_axis(&delta, &(*sample - self.mean));
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Vector3 {
+        self.mean
+    }
+
+    /// greatest standard deviation of the axes
+    pub fn deviation(&self) -> f32 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let variance = self.m2.x.max(self.m2.y).max(self.m2.z) / (self.count - 1) as f32;
+        libm::sqrtf(variance)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// gyro bias at rest
+pub struct GyroCalibration {
+    pub config: CalibrationConfig,
+    rest: RestAverage,
+    /// sum of the temperatures (°C)
+    temperature: Option<f32>,
+}
+
+impl GyroCalibration {
+    pub fn new(config: CalibrationConfig) -> Self {
+        GyroCalibration {
+            config,
+            rest: RestAverage::default(),
+            temperature: None,
+        }
+    }
+
+    /// account for the raw sample (deg/s) at the temperature (°C)
+    pub fn add(&mut self, gyro: &Vector3, temperature: Option<f32>) {
+        self.rest.add(gyro);
+        if let Some(temperature) = temperature {
+            *self.temperature.get_or_insert(0.0) += temperature;
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.rest.count() >= self.config.samples
+    }
+
+    /// the bias (deg/s) at the average temperature (°C)
+    pub fn bias(&self) -> Result<(Vector3, Option<f32>), &'static str> {
+        if !self.is_complete() {
+            return Err("insufficient samples");
+        }
+        if self.rest.deviation() > self.config.max_gyro_deviation {
+            return Err("not at rest");
+        }
+        let count = self.rest.count() as f32;
+        Ok((self.rest.mean(), self.temperature.map(|sum| sum / count)))
+    }
+
+    /// restart (i.e. at another temperature)
+    pub fn reset(&mut self) {
+        self.rest.reset();
+        self.temperature = None;
+    }
+}
+
+/// linear fit of the gyro bias over temperature (least squares per axis)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TemperatureFit {
+    count: u32,
+    /// sums of t, t², b and t·b
+    t: f32,
+    tt: f32,
+    b: Vector3,
+    tb: Vector3,
+    min: Option<f32>,
+    max: Option<f32>,
+    /// limit of the span of the temperatures
+    min_span: f32,
+}
+
+impl TemperatureFit {
+    pub fn new(config: &CalibrationConfig) -> Self {
+        TemperatureFit {
+            min_span: config.min_temperature_span,
+            ..Default::default()
+        }
+    }
+
+    /// account for the bias (deg/s) at rest at the temperature (°C)
+    pub fn add(&mut self, temperature: f32, bias: &Vector3) {
+        self.count += 1;
+        self.t += temperature;
+        self.tt += temperature * temperature;
+        self.b += *bias;
+        self.tb += *bias * temperature;
+        self.min = Some(self.min.map_or(temperature, |min| min.min(temperature)));
+        self.max = Some(self.max.map_or(temperature, |max| max.max(temperature)));
+    }
+
+    /// the bias (deg/s) at the reference temperature (°C) and its change per
+    ///     degree
+    pub fn solve(&self, reference: f32) -> Result<(Vector3, Vector3), &'static str> {
+        let span = self.max.zip(self.min).map_or(0.0, |(max, min)| max - min);
+        if self.count < 2 || span < self.min_span {
+            return Err("insufficient temperature span");
+        }
+        let n = self.count as f32;
+        let denominator = n * self.tt - self.t * self.t;
+        let coefficient = (self.tb * n - self.b * self.t) * (1.0 / denominator);
+        let intercept = (self.b - coefficient * self.t) * (1.0 / n);
+        Ok((intercept + coefficient * reference, coefficient))
+    }
+}
+
+/// accelerometer offset and scale from six positions
+pub struct AccelCalibration {
+    pub config: CalibrationConfig,
+    rest: RestAverage,
+    /// average of each position (+x, -x, +y, -y, +z, -z pointing up)
+    positions: [Option<Vector3>; 6],
+}
+
+impl AccelCalibration {
+    pub fn new(config: CalibrationConfig) -> Self {
+        AccelCalibration {
+            config,
+            rest: RestAverage::default(),
+            positions: [None; 6],
+        }
+    }
+
+    /// account for the raw sample (m/s²) of the current position
+    pub fn add(&mut self, accel: &Vector3) {
+        self.rest.add(accel);
+    }
+
+    /// positions captured (+x, -x, +y, -y, +z, -z pointing up)
+    pub fn captured(&self) -> [bool; 6] {
+        self.positions.map(|position| position.is_some())
+    }
+
+    /// retain the average of the current position - returns the position
+    ///     (index of `captured()`)
+    pub fn capture(&mut self) -> Result<usize, &'static str> {
+        let result = self.position();
+        self.rest.reset();
+        let (index, mean) = result?;
+        self.positions[index] = Some(mean);
+        Ok(index)
+    }
+
+    fn position(&self) -> Result<(usize, Vector3), &'static str> {
+        if self.rest.count() < self.config.samples {
+            return Err("insufficient samples");
+        }
+        if self.rest.deviation() > self.config.max_accel_deviation {
+            return Err("not at rest");
+        }
+        let mean = self.rest.mean();
+        let axes = [mean.x, mean.y, mean.z];
+        let (axis, value) = axes
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+            .map(|(axis, value)| (axis, *value))
+            .ok_or("no acceleration")?;
+        let tolerance = self.config.position_tolerance * GRAVITY;
+        let aligned = axes
+            .iter()
+            .enumerate()
+            .all(|(i, v)| i == axis || v.abs() < tolerance);
+        if !aligned || (value.abs() - GRAVITY).abs() > tolerance {
+            return Err("not aligned");
+        }
+        // the reaction to gravity is measured along the axis pointing up
+        let up = value > 0.0;
+        Ok((2 * axis + if up { 0 } else { 1 }, mean))
+    }
+
+    /// offset (m/s²) and scale of each axis
+    pub fn solve(&self) -> Result<(Vector3, Vector3), &'static str> {
+        let mut offset = [0.0; 3];
+        let mut scale = [0.0; 3];
+        for (axis, (offset, scale)) in offset.iter_mut().zip(scale.iter_mut()).enumerate() {
+            let component = |v: Vector3| [v.x, v.y, v.z][axis];
+            let (Some(up), Some(down)) = (self.positions[2 * axis], self.positions[2 * axis + 1])
+            else {
+                return Err("positions missing");
+            };
+            let (up, down) = (component(up), component(down));
+            *offset = (up + down) / 2.0;
+            *scale = 2.0 * GRAVITY / (up - down);
+        }
+        Ok((
+            Vector3::new(offset[0], offset[1], offset[2]),
+            Vector3::new(scale[0], scale[1], scale[2]),
+        ))
+    }
+}
+
+/// correction layer of the IMU
+pub struct CorrectedImu<'a, Imu: ImuReader> {
+    imu: &'a Imu,
+    pub calibration: ImuCalibration,
+}
+
+impl<'a, Imu: ImuReader> CorrectedImu<'a, Imu> {
+    pub fn new(imu: &'a Imu, calibration: ImuCalibration) -> Self {
+        CorrectedImu { imu, calibration }
+    }
+}
+
+impl<Imu: ImuReader> ImuReader for CorrectedImu<'_, Imu> {
+    fn get_data(&self) -> Result<ImuData, &str> {
+        self.imu
+            .get_data()
+            .map(|data| self.calibration.correct(&data))
+    }
+
+    fn stop(&self) -> Result<(), &str> {
+        self.imu.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// deterministic noise within ±amplitude
+    fn noise(i: u32, amplitude: f32) -> Vector3 {
+        let v = |k: u32| {
+            let x = (i.wrapping_mul(2_654_435_761).wrapping_add(k * 97) >> 8) & 0xFFFF;
+            (x as f32 / 65535.0 * 2.0 - 1.0) * amplitude
+        };
+        Vector3::new(v(1), v(2), v(3))
+    }
+
+    fn assert_near(a: Vector3, b: Vector3, tolerance: f32) {
+        assert!((a - b).norm() < tolerance, "{a:?} vs {b:?}");
+    }
+
+    #[test]
+    fn gyro_bias_at_rest() {
+        let config = CalibrationConfig::default();
+        let bias = Vector3::new(0.8, -1.2, 0.3);
+        let mut gyro = GyroCalibration::new(config);
+        assert_eq!(gyro.bias(), Err("insufficient samples"));
+        for i in 0..config.samples {
+            gyro.add(&(bias + noise(i, 0.2)), Some(30.0));
+        }
+        let (estimate, temperature) = gyro.bias().unwrap();
+        assert_near(estimate, bias, 0.02);
+        assert_eq!(temperature, Some(30.0));
+
+        // rotated during the calibration
+        gyro.reset();
+        for i in 0..config.samples {
+            let rotation = if i > config.samples / 2 { 10.0 } else { 0.0 };
+            gyro.add(&(bias + Vector3::new(0.0, 0.0, rotation)), None);
+        }
+        assert_eq!(gyro.bias(), Err("not at rest"));
+    }
+
+    #[test]
+    fn gyro_bias_over_temperature() {
+        let config = CalibrationConfig::default();
+        let coefficient = Vector3::new(0.02, -0.05, 0.01);
+        let bias_at = |t: f32| Vector3::new(0.5, 0.2, -0.4) + coefficient * (t - 25.0);
+        let mut fit = TemperatureFit::new(&config);
+        fit.add(20.0, &bias_at(20.0));
+        fit.add(25.0, &bias_at(25.0));
+        assert_eq!(fit.solve(25.0), Err("insufficient temperature span"));
+        fit.add(45.0, &bias_at(45.0));
+
+        let mut calibration = ImuCalibration::default();
+        calibration.set_gyro_temperature(&fit).unwrap();
+        assert_near(calibration.gyro_temperature_coefficient, coefficient, 1e-4);
+        for t in [0.0, 25.0, 60.0] {
+            assert_near(calibration.gyro_bias(Some(t)), bias_at(t), 1e-3);
+        }
+        let sample = ImuData {
+            gyroscope: Some(bias_at(40.0)),
+            temperature: Some(40.0),
+            ..Default::default()
+        };
+        let corrected = calibration.correct(&sample);
+        assert_near(corrected.gyroscope.unwrap(), Vector3::ZERO, 1e-3);
+        assert_eq!(corrected.calibration_status, Some(GYRO_CALIBRATED));
+    }
+
+    #[test]
+    fn accel_six_positions() {
+        let config = CalibrationConfig::default();
+        let offset = Vector3::new(0.3, -0.2, 0.5);
+        let scale = Vector3::new(1.02, 0.97, 1.05);
+        // raw reading of the (true) specific force
+        let raw = |force: Vector3| {
+            Vector3::new(force.x / scale.x, force.y / scale.y, force.z / scale.z) + offset
+        };
+        let mut accel = AccelCalibration::new(config);
+        assert_eq!(accel.solve(), Err("positions missing"));
+
+        // tilted halfway between positions
+        for i in 0..config.samples {
+            let force = Vector3::new(GRAVITY, 0.0, GRAVITY) * core::f32::consts::FRAC_1_SQRT_2;
+            accel.add(&(raw(force) + noise(i, 0.05)));
+        }
+        assert_eq!(accel.capture(), Err("not aligned"));
+
+        let ups = [
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(-1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, -1.0),
+        ];
+        for (position, up) in ups.iter().enumerate().rev() {
+            for i in 0..config.samples {
+                accel.add(&(raw(*up * GRAVITY) + noise(i, 0.05)));
+            }
+            assert_eq!(accel.capture(), Ok(position));
+        }
+        assert_eq!(accel.captured(), [true; 6]);
+
+        let mut calibration = ImuCalibration::default();
+        calibration.set_accel(&accel).unwrap();
+        assert_near(calibration.accel_offset, offset, 0.01);
+        assert_near(calibration.accel_scale, scale, 0.005);
+        let level = ImuData {
+            accelerometer: Some(raw(Vector3::new(0.0, 0.0, -GRAVITY))),
+            ..Default::default()
+        };
+        let corrected = calibration.correct(&level).accelerometer.unwrap();
+        assert_near(corrected, Vector3::new(0.0, 0.0, -GRAVITY), 0.01);
+    }
+
+    #[test]
+    fn board_orientation() {
+        // board mounted rotated 90 degrees (yaw), slightly tilted
+        let mounting = Vector3::new(0.0, 0.0, core::f32::consts::FRAC_PI_2);
+        let tilt = Vector3::new(0.03, -0.02, 0.0).euler_to_quaternion();
+        let board = tilt * mounting.euler_to_quaternion();
+        // reading of the IMU per the body frame vector
+        let imu = |body: Vector3| board.rotate_vector(body, true);
+
+        let mut calibration = ImuCalibration::mounted(&mounting);
+        let level = Vector3::new(0.0, 0.0, -GRAVITY);
+        calibration.level(&imu(level)).unwrap();
+        assert_eq!(calibration.status, ORIENTATION_CALIBRATED);
+
+        let sample = ImuData {
+            accelerometer: Some(imu(level)),
+            gyroscope: Some(imu(Vector3::new(10.0, 0.0, 0.0))),
+            ..Default::default()
+        };
+        let corrected = calibration.correct(&sample);
+        assert_near(corrected.accelerometer.unwrap(), level, 1e-3);
+        // roll remains roll (within the unobservable yaw of the trim)
+        assert_near(
+            corrected.gyroscope.unwrap(),
+            Vector3::new(10.0, 0.0, 0.0),
+            0.05,
+        );
+
+        assert_eq!(
+            calibration.level(&Vector3::new(GRAVITY, 0.0, 0.0)),
+            Err("not level")
+        );
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let mut calibration = ImuCalibration::mounted(&Vector3::new(0.0, 0.0, 1.0));
+        calibration.set_gyro(&Vector3::new(0.1, 0.2, 0.3), Some(31.0));
+        calibration.accel_offset = Vector3::new(0.3, -0.2, 0.5);
+        calibration.accel_scale = Vector3::new(1.02, 0.97, 1.05);
+        calibration.mag = MagCalibration {
+            offset: Vector3::new(12.0, -30.0, 4.0),
+            matrix: [[1.1, 0.02, 0.0], [0.02, 0.95, -0.01], [0.0, -0.01, 1.0]],
+        };
+        let bytes = calibration.to_bytes();
+        assert_eq!(ImuCalibration::from_bytes(&bytes), Ok(calibration));
+
+        let mut corrupted = bytes;
+        corrupted[20] ^= 0x01;
+        assert_eq!(
+            ImuCalibration::from_bytes(&corrupted),
+            Err("invalid checksum")
+        );
+        assert!(ImuCalibration::from_bytes(&[0; PARAMETERS_SIZE]).is_err());
+    }
+
+    struct Raw(ImuData);
+    impl ImuReader for Raw {
+        fn get_data(&self) -> Result<ImuData, &str> {
+            Ok(self.0)
+        }
+        fn stop(&self) -> Result<(), &str> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn corrects_any_reader() {
+        let raw = Raw(ImuData {
+            gyroscope: Some(Vector3::new(1.0, 1.0, 1.0)),
+            accelerometer: Some(Vector3::new(0.0, 0.0, -GRAVITY)),
+            ..Default::default()
+        });
+        let mut calibration = ImuCalibration::default();
+        calibration.set_gyro(&Vector3::new(1.0, 1.0, 1.0), None);
+        let imu = CorrectedImu::new(&raw, calibration);
+        let data = imu.get_data().unwrap();
+        assert_eq!(data.gyroscope, Some(Vector3::ZERO));
+        assert_eq!(data.accelerometer, raw.0.accelerometer);
+        assert_eq!(data.calibration_status, Some(GYRO_CALIBRATED));
+    }
+}
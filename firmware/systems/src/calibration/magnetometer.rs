@@ -0,0 +1,567 @@
+//! Magnetometer (compass) calibration
+//!
+//! Per the user rotating the vehicle through all orientations, the samples of
+//! an undistorted field lie on a sphere. Nearby ferrous material and currents
+//! distort the samples onto an ellipsoid:
+//!     * hard iron - offset of the center
+//!     * soft iron - scaling (and rotation) of the axes
+//!
+//! The samples (spread apart, within a fixed buffer whose most sampled
+//! directions yield to the others once full) are fitted by linear
+//! least squares to the general ellipsoid
+//!     a x² + b y² + c z² + 2d xy + 2e xz + 2f yz + 2g x + 2h y + 2i z = 1
+//! whose center is the hard iron offset and whose shape (the symmetric square
+//! root of its matrix) maps the samples back onto a sphere.
+//!
+//! Feedback for the user:
+//!     * coverage - fraction of the directions (about the center) sampled
+//!     * residual - RMS deviation of the corrected samples from the sphere
+//!       (fraction of the field), the quality of the fit
+
+use rusty_robot_common::Vector3;
+
+/// capacity of the samples - once full, samples of the less sampled
+///     directions replace those of the most sampled direction
+pub const MAX_SAMPLES: usize = 200;
+
+/// directions (8 azimuths by 4 equal area elevation bands)
+pub const COVERAGE_BINS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagConfig {
+    /// distance between retained samples (µT)
+    pub min_separation: f32,
+    /// samples required to fit
+    pub min_samples: usize,
+    /// fraction of the directions required to fit
+    pub min_coverage: f32,
+    /// limit of the residual (fraction of the field)
+    pub max_residual: f32,
+}
+impl Default for MagConfig {
+    fn default() -> Self {
+        MagConfig {
+            min_separation: 5.0,
+            min_samples: 50,
+            min_coverage: 0.75,
+            max_residual: 0.05,
+        }
+    }
+}
+
+/// correction of the hard and soft iron distortion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagCalibration {
+    /// hard iron (µT)
+    pub offset: Vector3,
+    /// soft iron (row major)
+    pub matrix: [[f32; 3]; 3],
+}
+impl Default for MagCalibration {
+    /// no correction
+    fn default() -> Self {
+        MagCalibration {
+            offset: Vector3::ZERO,
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+impl MagCalibration {
+    pub fn correct(&self, mag: &Vector3) -> Vector3 {
+        let v = *mag - self.offset;
+        let row = |r: &[f32; 3]| r[0] * v.x + r[1] * v.y + r[2] * v.z;
+        Vector3::new(
+            row(&self.matrix[0]),
+            row(&self.matrix[1]),
+            row(&self.matrix[2]),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagFit {
+    pub calibration: MagCalibration,
+    /// strength of the corrected field (µT)
+    pub field: f32,
+    /// RMS deviation of the corrected samples (fraction of the field)
+    pub residual: f32,
+}
+
+pub struct MagCalibrator {
+    pub config: MagConfig,
+    samples: [Vector3; MAX_SAMPLES],
+    count: usize,
+}
+
+impl MagCalibrator {
+    pub fn new(config: MagConfig) -> Self {
+        MagCalibrator {
+            config,
+            samples: [Vector3::ZERO; MAX_SAMPLES],
+            count: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+    }
+
+    pub fn samples(&self) -> &[Vector3] {
+        &self.samples[..self.count]
+    }
+
+    /// account for the raw sample (µT) - retained unless near another sample
+    ///     (or, once full, unless its direction is as sampled as the most)
+    pub fn add(&mut self, mag: &Vector3) -> bool {
+        let separation = self.config.min_separation;
+        if self
+            .samples()
+            .iter()
+            .any(|sample| (*sample - *mag).norm() < separation)
+        {
+            return false;
+        }
+        if self.count < MAX_SAMPLES {
+            self.samples[self.count] = *mag;
+            self.count += 1;
+            return true;
+        }
+        // full (i.e. a slow sweep), so rebalance the directions
+        let Some(center) = self.center() else {
+            return false;
+        };
+        let Some(direction) = (*mag - center).normalized() else {
+            return false;
+        };
+        let bins: [Option<usize>; MAX_SAMPLES] =
+            core::array::from_fn(|i| (self.samples[i] - center).normalized().map(|d| bin(&d)));
+        let mut population = [0; COVERAGE_BINS];
+        for bin in bins.iter().flatten() {
+            population[*bin] += 1;
+        }
+        let (crowded, most) = population
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, count)| **count)
+            .map_or((0, 0), |(bin, count)| (bin, *count));
+        if population[bin(&direction)] + 1 >= most {
+            return false;
+        }
+        match bins.iter().rposition(|bin| *bin == Some(crowded)) {
+            Some(replaced) => {
+                self.samples[replaced] = *mag;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// directions (about the center of the samples) that have been sampled
+    pub fn covered(&self) -> [bool; COVERAGE_BINS] {
+        let mut covered = [false; COVERAGE_BINS];
+        let Some(center) = self.center() else {
+            return covered;
+        };
+        for sample in self.samples() {
+            if let Some(direction) = (*sample - center).normalized() {
+                covered[bin(&direction)] = true;
+            }
+        }
+        covered
+    }
+
+    /// fraction of the directions sampled (0.0 - 1.0)
+    pub fn coverage(&self) -> f32 {
+        let covered = self.covered().iter().filter(|c| **c).count();
+        covered as f32 / COVERAGE_BINS as f32
+    }
+
+    /// center of the best fit sphere (|x|² = 2 c·x + k), unbiased by the
+    ///     directions yet to be sampled
+    fn center(&self) -> Option<Vector3> {
+        let first = *self.samples().first()?;
+        let mut normal = [[0.0f64; 4]; 4];
+        let mut rhs = [0.0f64; 4];
+        for sample in self.samples() {
+            // relative to the first sample (conditioning)
+            let d = *sample - first;
+            let (x, y, z) = (d.x as f64, d.y as f64, d.z as f64);
+            let phi = [2.0 * x, 2.0 * y, 2.0 * z, 1.0];
+            for (row, p) in normal.iter_mut().zip(&phi) {
+                for (value, q) in row.iter_mut().zip(&phi) {
+                    *value += p * q;
+                }
+            }
+            for (value, p) in rhs.iter_mut().zip(&phi) {
+                *value += p * (x * x + y * y + z * z);
+            }
+        }
+        let [x, y, z, _] = solve(normal, rhs)?;
+        Some(first + Vector3::new(x as f32, y as f32, z as f32))
+    }
+
+    /// ellipsoid fit of the samples
+    pub fn fit(&self) -> Result<MagFit, &'static str> {
+        if self.count < self.config.min_samples {
+            return Err("insufficient samples");
+        }
+        if self.coverage() < self.config.min_coverage {
+            return Err("insufficient coverage");
+        }
+
+        // normalized (conditioning of the normal equations)
+        let n = self.count as f64;
+        let mean = self.samples().iter().fold([0.0f64; 3], |mut sum, s| {
+            for (sum, v) in sum.iter_mut().zip([s.x, s.y, s.z]) {
+                *sum += v as f64 / n;
+            }
+            sum
+        });
+        let scale = libm::sqrt(
+            self.samples()
+                .iter()
+                .map(|s| {
+                    let d = [
+                        s.x as f64 - mean[0],
+                        s.y as f64 - mean[1],
+                        s.z as f64 - mean[2],
+                    ];
+                    d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+                })
+                .sum::<f64>()
+                / n,
+        );
+        if scale <= f64::EPSILON {
+            return Err("singular");
+        }
+        let normalized = |s: &Vector3| {
+            [
+                (s.x as f64 - mean[0]) / scale,
+                (s.y as f64 - mean[1]) / scale,
+                (s.z as f64 - mean[2]) / scale,
+            ]
+        };
+
+        // least squares of the general ellipsoid (normal equations)
+        let mut normal = [[0.0f64; 9]; 9];
+        let mut rhs = [0.0f64; 9];
+        for sample in self.samples() {
+            let [x, y, z] = normalized(sample);
+            let phi = [
+                x * x,
+                y * y,
+                z * z,
+                2.0 * x * y,
+                2.0 * x * z,
+                2.0 * y * z,
+                2.0 * x,
+                2.0 * y,
+                2.0 * z,
+            ];
+            for (row, p) in normal.iter_mut().zip(&phi) {
+                for (value, q) in row.iter_mut().zip(&phi) {
+                    *value += p * q;
+                }
+            }
+            for (value, p) in rhs.iter_mut().zip(&phi) {
+                *value += p;
+            }
+        }
+        let [a, b, c, d, e, f, g, h, i] = solve(normal, rhs).ok_or("singular")?;
+
+        // center = -A⁻¹ [g h i]
+        let matrix = [[a, d, e], [d, b, f], [e, f, c]];
+        let inverse = invert(&matrix).ok_or("singular")?;
+        let center = mul(&inverse, &[-g, -h, -i]);
+        // (x - center)ᵀ A (x - center) = 1 + centerᵀ A center
+        let k = 1.0 + dot(&center, &mul(&matrix, &center));
+        if k <= 0.0 {
+            return Err("not an ellipsoid");
+        }
+        let shape = matrix.map(|row| row.map(|v| v / k));
+        let (values, vectors) = eigen(shape);
+        if values.iter().any(|v| *v <= 0.0) {
+            return Err("not an ellipsoid");
+        }
+
+        // radii (normalized) are 1/√λ, corrected onto the sphere of their
+        //     geometric mean
+        let radius = libm::cbrt(values.iter().map(|v| 1.0 / libm::sqrt(*v)).product());
+        // W = r V √Λ Vᵀ (symmetric, so the axes aren't rotated)
+        let mut soft = [[0.0f64; 3]; 3];
+        for (r, row) in soft.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|k| vectors[r][k] * libm::sqrt(values[k]) * vectors[c][k])
+                    .sum::<f64>()
+                    * radius;
+            }
+        }
+
+        let calibration = MagCalibration {
+            offset: Vector3::new(
+                (mean[0] + center[0] * scale) as f32,
+                (mean[1] + center[1] * scale) as f32,
+                (mean[2] + center[2] * scale) as f32,
+            ),
+            matrix: soft.map(|row| row.map(|v| v as f32)),
+        };
+        let field = (radius * scale) as f32;
+        let residual = libm::sqrtf(
+            self.samples()
+                .iter()
+                .map(|s| {
+                    let deviation = calibration.correct(s).norm() / field - 1.0;
+                    deviation * deviation
+                })
+                .sum::<f32>()
+                / self.count as f32,
+        );
+        if residual > self.config.max_residual {
+            return Err("poor fit");
+        }
+        Ok(MagFit {
+            calibration,
+            field,
+            residual,
+        })
+    }
+}
+
+/// coverage bin of the direction
+fn bin(direction: &Vector3) -> usize {
+    let azimuth = libm::atan2f(direction.y, direction.x) + core::f32::consts::PI;
+    let sector = ((azimuth / (2.0 * core::f32::consts::PI) * 8.0) as usize).min(7);
+    // equal area bands (per the height on the sphere)
+    let band = (((direction.z + 1.0) / 2.0 * 4.0) as usize).min(3);
+    band * 8 + sector
+}
+
+/// solution of the linear equations (Gaussian elimination, partial pivoting)
+fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for col in 0..N {
+        let pivot = (col..N).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..N {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col];
+            for (value, p) in a[row].iter_mut().zip(&pivot_row).skip(col) {
+                *value -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|c| a[row][c] * x[c]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let mut inverse = [[0.0; 3]; 3];
+    for (c, unit) in [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]
+        .into_iter()
+        .enumerate()
+    {
+        let column = solve(*m, unit)?;
+        for (row, value) in inverse.iter_mut().zip(column) {
+            row[c] = value;
+        }
+    }
+    Some(inverse)
+}
+
+fn mul(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    m.map(|row| dot(&row, v))
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// eigenvalues and eigenvectors (columns) of the symmetric matrix (Jacobi
+///     rotations)
+fn eigen(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..50 {
+        let off = a[0][1] * a[0][1] + a[0][2] * a[0][2] + a[1][2] * a[1][2];
+        if off < 1e-24 {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-30 {
+                continue;
+            }
+            // rotation zeroing a[p][q]
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + libm::sqrt(theta * theta + 1.0));
+            let c = 1.0 / libm::sqrt(t * t + 1.0);
+            let s = t * c;
+            // A' = Jᵀ A J, V' = V J
+            let rotate = |row: &mut [f64; 3]| {
+                let (rp, rq) = (row[p], row[q]);
+                row[p] = c * rp - s * rq;
+                row[q] = s * rp + c * rq;
+            };
+            a.iter_mut().for_each(rotate);
+            let (ap, aq) = (a[p], a[q]);
+            a[p] = core::array::from_fn(|k| c * ap[k] - s * aq[k]);
+            a[q] = core::array::from_fn(|k| s * ap[k] + c * aq[k]);
+            v.iter_mut().for_each(rotate);
+        }
+    }
+    ([a[0][0], a[1][1], a[2][2]], v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// earth field (µT)
+    const FIELD: f32 = 50.0;
+
+    /// soft iron (symmetric) and hard iron distortion
+    const SOFT: [[f32; 3]; 3] = [[1.15, 0.08, -0.05], [0.08, 0.9, 0.04], [-0.05, 0.04, 1.02]];
+    const HARD: Vector3 = Vector3::new(25.0, -40.0, 12.0);
+
+    fn distort(field: &Vector3) -> Vector3 {
+        let row = |r: &[f32; 3]| r[0] * field.x + r[1] * field.y + r[2] * field.z;
+        Vector3::new(row(&SOFT[0]), row(&SOFT[1]), row(&SOFT[2])) + HARD
+    }
+
+    /// directions spread over the sphere (golden spiral), optionally limited
+    ///     to the upper hemisphere
+    fn direction(i: usize, count: usize, hemisphere: bool) -> Vector3 {
+        let golden = core::f32::consts::PI * (3.0 - libm::sqrtf(5.0));
+        let fraction = (i as f32 + 0.5) / count as f32;
+        let z = if hemisphere {
+            -fraction
+        } else {
+            1.0 - 2.0 * fraction
+        };
+        let r = libm::sqrtf(1.0 - z * z);
+        let theta = golden * i as f32;
+        Vector3::new(r * libm::cosf(theta), r * libm::sinf(theta), z)
+    }
+
+    /// deterministic noise within ±amplitude
+    fn noise(i: usize, amplitude: f32) -> Vector3 {
+        let v = |k: usize| {
+            let x = ((i * 2_654_435_761 + k * 40_503) >> 7) & 0xFFFF;
+            (x as f32 / 65535.0 * 2.0 - 1.0) * amplitude
+        };
+        Vector3::new(v(1), v(2), v(3))
+    }
+
+    fn rotated(calibrator: &mut MagCalibrator, count: usize, hemisphere: bool) {
+        for i in 0..count {
+            let sample = distort(&(direction(i, count, hemisphere) * FIELD)) + noise(i, 0.5);
+            calibrator.add(&sample);
+        }
+    }
+
+    #[test]
+    fn fits_distorted_field() {
+        let mut calibrator = MagCalibrator::new(MagConfig::default());
+        assert_eq!(calibrator.fit(), Err("insufficient samples"));
+        rotated(&mut calibrator, 180, false);
+        assert!(calibrator.samples().len() >= 150);
+        assert_eq!(calibrator.coverage(), 1.0);
+
+        let fit = calibrator.fit().unwrap();
+        let calibration = fit.calibration;
+        assert!((calibration.offset - HARD).norm() < 0.5, "{calibration:?}");
+        assert!(fit.residual < 0.01, "residual {}", fit.residual);
+
+        // the corrected samples are on the sphere
+        for i in 0..50 {
+            let truth = direction(i, 50, false) * FIELD;
+            let corrected = calibration.correct(&distort(&truth));
+            assert!((corrected.norm() - fit.field).abs() < 0.02 * fit.field);
+            // without rotation of the field
+            let angle =
+                libm::acosf((corrected.dot(&truth) / (corrected.norm() * FIELD)).clamp(-1.0, 1.0));
+            assert!(angle.to_degrees() < 1.5, "{} deg", angle.to_degrees());
+        }
+    }
+
+    #[test]
+    fn requires_coverage() {
+        let mut calibrator = MagCalibrator::new(MagConfig::default());
+        rotated(&mut calibrator, 100, true);
+        let coverage = calibrator.coverage();
+        assert!(coverage > 0.4 && coverage < 0.6, "coverage {coverage}");
+        assert_eq!(calibrator.fit(), Err("insufficient coverage"));
+        // the feedback directs the user toward the remaining (downward) directions
+        assert!(calibrator.covered()[24..].iter().all(|c| !c));
+
+        rotated(&mut calibrator, 100, false);
+        assert!(calibrator.fit().is_ok());
+    }
+
+    #[test]
+    fn completes_slow_sweep() {
+        let mut calibrator = MagCalibrator::new(MagConfig::default());
+        // lingering over the upper hemisphere fills the samples
+        rotated(&mut calibrator, 300, true);
+        assert_eq!(calibrator.samples().len(), MAX_SAMPLES);
+        assert_eq!(calibrator.fit(), Err("insufficient coverage"));
+        assert!(calibrator.covered()[24..].iter().all(|c| !c));
+
+        // the remaining directions replace the most sampled
+        rotated(&mut calibrator, 200, false);
+        assert_eq!(calibrator.samples().len(), MAX_SAMPLES);
+        let coverage = calibrator.coverage();
+        assert!(coverage > 0.9, "coverage {coverage}");
+        let fit = calibrator.fit().unwrap();
+        let offset = fit.calibration.offset;
+        assert!((offset - HARD).norm() < 1.0, "{offset:?}");
+    }
+
+    #[test]
+    fn reports_fit_quality() {
+        let config = MagConfig::default();
+        let mut calibrator = MagCalibrator::new(config);
+        // spread apart samples only
+        let sample = distort(&Vector3::new(FIELD, 0.0, 0.0));
+        assert!(calibrator.add(&sample));
+        assert!(!calibrator.add(&(sample + Vector3::new(1.0, 0.0, 0.0))));
+
+        // disturbed (i.e. near a motor) samples are not an ellipsoid
+        calibrator.reset();
+        for i in 0..180 {
+            let disturbance = if i % 3 == 0 {
+                noise(i, 15.0)
+            } else {
+                Vector3::ZERO
+            };
+            let sample = distort(&(direction(i, 180, false) * FIELD)) + disturbance;
+            calibrator.add(&sample);
+        }
+        match calibrator.fit() {
+            Err(e) => assert!(e == "poor fit" || e == "not an ellipsoid", "{e}"),
+            Ok(fit) => panic!("residual {}", fit.residual),
+        }
+    }
+
+    #[test]
+    fn eigen_decomposition() {
+        let a = [[4.0, 1.0, 0.5], [1.0, 3.0, 0.2], [0.5, 0.2, 2.0]];
+        let (values, vectors) = eigen(a);
+        for (k, value) in values.iter().enumerate() {
+            let v = [vectors[0][k], vectors[1][k], vectors[2][k]];
+            let av = mul(&a, &v);
+            for (av, v) in av.iter().zip(&v) {
+                assert!((av - value * v).abs() < 1e-9);
+            }
+        }
+        let trace: f64 = values.iter().sum();
+        assert!((trace - 9.0).abs() < 1e-9);
+    }
+}
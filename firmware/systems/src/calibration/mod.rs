@@ -0,0 +1,4 @@
+// provide gyroscope and accelerometer calibration (and correction)
+pub mod imu;
+// provide magnetometer calibration (ellipsoid fit)
+pub mod magnetometer;
@@ -0,0 +1,372 @@
+//! Attitude estimation (complementary filters)
+//!
+//! Integrates the gyroscope for the attitude, correcting the drift of the
+//! integration using the accelerometer (gravity provides roll/pitch) and
+//! optionally the magnetometer (magnetic north provides yaw).
+//!     * [Mahony] - PI feedback of the reference vector errors, where the
+//!       integral is the gyroscope bias
+//!     * [Madgwick] - gradient descent towards the reference vectors, where the
+//!       gyroscope bias is estimated from the corrections
+//!
+//! Attitude is the rotation of the body frame (FRD) into the world frame (NED),
+//! so at rest the accelerometer measures (0, 0, -g) when level.
+//!
+//! [Mahony]: https://hal.science/hal-00488376/document
+//! [Madgwick]: https://x-io.co.uk/downloads/madgwick_internal_report.pdf
+
+use rusty_robot_common::{Quaternion, Vector3};
+use rusty_robot_drivers::imu_traits::ImuData;
+
+/// standard gravity (m/s²)
+pub const GRAVITY: f32 = 9.80665;
+
+/// direction of gravity (NED)
+const DOWN: Vector3 = Vector3::new(0.0, 0.0, 1.0);
+
+/// accelerations beyond gravity by more than this fraction aren't used for
+/// correction (i.e. while maneuvering)
+const ACCEL_REJECTION: f32 = 0.25;
+
+/// attitude error (rad) beyond which the gyro bias isn't estimated
+const BIAS_CONVERGENCE: f32 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    Mahony {
+        /// proportional gain (convergence rate of attitude)
+        kp: f32,
+        /// integral gain (convergence rate of gyro bias)
+        ki: f32,
+    },
+    Madgwick {
+        /// gradient descent gain (rad/s) - trust of accel/mag over gyro
+        beta: f32,
+        /// gyro bias drift gain (rad/s²)
+        zeta: f32,
+    },
+}
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Mahony { kp: 1.0, ki: 0.05 }
+    }
+}
+
+pub struct AttitudeEstimator {
+    pub filter: Filter,
+    /// use the magnetometer (if provided) for yaw
+    pub use_magnetometer: bool,
+    attitude: Quaternion,
+    /// estimated gyro bias (rad/s)
+    gyro_bias: Vector3,
+    initialized: bool,
+}
+
+impl AttitudeEstimator {
+    pub fn new(filter: Filter) -> Self {
+        AttitudeEstimator {
+            filter,
+            use_magnetometer: true,
+            attitude: Quaternion::IDENTITY,
+            gyro_bias: Vector3::ZERO,
+            initialized: false,
+        }
+    }
+
+    /// estimated attitude (body to world)
+    pub fn attitude(&self) -> Quaternion {
+        self.attitude
+    }
+
+    /// estimated gyroscope bias (deg/s)
+    pub fn gyro_bias(&self) -> Vector3 {
+        self.gyro_bias * (180.0 / core::f32::consts::PI)
+    }
+
+    /// restart estimation (from the next sample)
+    pub fn reset(&mut self) {
+        self.attitude = Quaternion::IDENTITY;
+        self.gyro_bias = Vector3::ZERO;
+        self.initialized = false;
+    }
+
+    /// restart estimation from a known attitude and gyro bias (deg/s)
+    pub fn restore(&mut self, attitude: Quaternion, gyro_bias: Vector3) {
+        self.attitude = attitude.normalized();
+        self.gyro_bias = gyro_bias * (core::f32::consts::PI / 180.0);
+        self.initialized = true;
+    }
+
+    /// account for an IMU sample taken `dt` seconds after the previous
+    ///     provides the attitude as the quaternion and euler (deg), and the
+    ///     gravity and linear acceleration (body frame) of the data
+    pub fn update(&mut self, data: &mut ImuData, dt: f32) -> Result<Quaternion, &'static str> {
+        let gyro = data.gyroscope.ok_or("no gyroscope")? * (core::f32::consts::PI / 180.0);
+        // measured direction of gravity (accelerometer measures the reaction)
+        let down = data
+            .accelerometer
+            .filter(|a| ((a.norm() / GRAVITY) - 1.0).abs() < ACCEL_REJECTION)
+            .and_then(|a| (-a).normalized());
+        let mag = data
+            .magnetometer
+            .filter(|_| self.use_magnetometer)
+            .and_then(|m| m.normalized());
+
+        if !self.initialized {
+            // start from the reference vectors (rather than converge to them)
+            if let Some(down) = down {
+                self.attitude = Self::align(&down, mag.as_ref());
+                self.initialized = true;
+            }
+        } else {
+            self.attitude = match self.filter {
+                Filter::Mahony { kp, ki } => self.mahony(gyro, down, mag, dt, kp, ki),
+                Filter::Madgwick { beta, zeta } => self.madgwick(gyro, down, mag, dt, beta, zeta),
+            };
+        }
+
+        data.quaternion = Some(self.attitude);
+        data.euler = Some(self.attitude.to_euler() * (180.0 / core::f32::consts::PI));
+        let gravity = self.attitude.rotate_vector(DOWN * GRAVITY, true);
+        data.gravity = Some(gravity);
+        // the accelerometer measures the reaction to gravity
+        data.linear_acceleration = data.accelerometer.map(|a| a + gravity);
+        Ok(self.attitude)
+    }
+
+    /// attitude of the reference vectors (body frame)
+    pub(crate) fn align(down: &Vector3, mag: Option<&Vector3>) -> Quaternion {
+        // down = (-sin(pitch), sin(roll)cos(pitch), cos(roll)cos(pitch))
+        let roll = libm::atan2f(down.y, down.z);
+        let pitch = libm::atan2f(-down.x, libm::sqrtf(down.y * down.y + down.z * down.z));
+        let level = Vector3::new(roll, pitch, 0.0).euler_to_quaternion();
+        let yaw = match mag {
+            Some(mag) => {
+                // heading of the magnetic field levelled into the world frame
+                let h = level.rotate_vector(*mag, false);
+                -libm::atan2f(h.y, h.x)
+            }
+            None => 0.0,
+        };
+        Vector3::new(roll, pitch, yaw).euler_to_quaternion()
+    }
+
+    /// direction of magnetic north in the body frame per the attitude
+    ///     (the measured field with its declination removed)
+    fn north(q: &Quaternion, mag: &Vector3) -> Vector3 {
+        let h = q.rotate_vector(*mag, false);
+        let b = Vector3::new(libm::sqrtf(h.x * h.x + h.y * h.y), 0.0, h.z);
+        q.rotate_vector(b, true)
+    }
+
+    /// q̇ = ½ q ⊗ (0, ω)
+    fn integrate(q: &Quaternion, rate: &Vector3, dt: f32) -> Quaternion {
+        let omega = Quaternion {
+            w: 0.0,
+            x: rate.x,
+            y: rate.y,
+            z: rate.z,
+        };
+        *q + (*q * omega) * (0.5 * dt)
+    }
+
+    fn mahony(
+        &mut self,
+        gyro: Vector3,
+        down: Option<Vector3>,
+        mag: Option<Vector3>,
+        dt: f32,
+        kp: f32,
+        ki: f32,
+    ) -> Quaternion {
+        let q = self.attitude;
+        let estimated_down = q.rotate_vector(DOWN, true);
+        let mut error = Vector3::ZERO;
+        if let Some(down) = down {
+            error += down.cross(&estimated_down);
+        }
+        if let Some(mag) = mag {
+            // only correct yaw (so magnetic disturbances don't affect roll/pitch)
+            let mag_error = mag.cross(&Self::north(&q, &mag));
+            error += estimated_down * mag_error.dot(&estimated_down);
+        }
+        // integral of the error opposes the bias
+        //  (only once converged, so that large initial errors don't wind up)
+        if error.norm() < BIAS_CONVERGENCE {
+            self.gyro_bias -= error * (ki * dt);
+        }
+        let rate = gyro - self.gyro_bias + error * kp;
+        Self::integrate(&q, &rate, dt).normalized()
+    }
+
+    fn madgwick(
+        &mut self,
+        gyro: Vector3,
+        down: Option<Vector3>,
+        mag: Option<Vector3>,
+        dt: f32,
+        beta: f32,
+        zeta: f32,
+    ) -> Quaternion {
+        let q = self.attitude;
+        let (q0, q1, q2, q3) = (q.w, q.x, q.y, q.z);
+        // gradient of the objective function (Jᵀf)
+        let mut s = [0.0_f32; 4];
+        if let Some(a) = down {
+            let f1 = 2.0 * (q1 * q3 - q0 * q2) - a.x;
+            let f2 = 2.0 * (q0 * q1 + q2 * q3) - a.y;
+            let f3 = 2.0 * (0.5 - q1 * q1 - q2 * q2) - a.z;
+            s[0] += -2.0 * q2 * f1 + 2.0 * q1 * f2;
+            s[1] += 2.0 * q3 * f1 + 2.0 * q0 * f2 - 4.0 * q1 * f3;
+            s[2] += -2.0 * q0 * f1 + 2.0 * q3 * f2 - 4.0 * q2 * f3;
+            s[3] += 2.0 * q1 * f1 + 2.0 * q2 * f2;
+        }
+        if let Some(m) = mag {
+            let h = q.rotate_vector(m, false);
+            let (bx, bz) = (libm::sqrtf(h.x * h.x + h.y * h.y), h.z);
+            let f4 = 2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - m.x;
+            let f5 = 2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - m.y;
+            let f6 = 2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - m.z;
+            s[0] +=
+                -2.0 * bz * q2 * f4 + (-2.0 * bx * q3 + 2.0 * bz * q1) * f5 + 2.0 * bx * q2 * f6;
+            s[1] += 2.0 * bz * q3 * f4
+                + (2.0 * bx * q2 + 2.0 * bz * q0) * f5
+                + (2.0 * bx * q3 - 4.0 * bz * q1) * f6;
+            s[2] += (-4.0 * bx * q2 - 2.0 * bz * q0) * f4
+                + (2.0 * bx * q1 + 2.0 * bz * q3) * f5
+                + (2.0 * bx * q0 - 4.0 * bz * q2) * f6;
+            s[3] += (-4.0 * bx * q3 + 2.0 * bz * q1) * f4
+                + (-2.0 * bx * q0 + 2.0 * bz * q2) * f5
+                + 2.0 * bx * q1 * f6;
+        }
+        let step = Quaternion {
+            w: s[0],
+            x: s[1],
+            y: s[2],
+            z: s[3],
+        };
+        let norm = step.norm();
+        if norm <= f32::EPSILON {
+            return Self::integrate(&q, &(gyro - self.gyro_bias), dt).normalized();
+        }
+        let step = step * (1.0 / norm);
+
+        // the direction of the correction (body rates) accumulates as the bias
+        let correction = q.conjugate() * step;
+        self.gyro_bias +=
+            Vector3::new(correction.x, correction.y, correction.z) * (2.0 * zeta * dt);
+
+        let rate = gyro - self.gyro_bias;
+        (Self::integrate(&q, &rate, dt) + step * (-beta * dt)).normalized()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+    /// magnetic field (NED, µT) - north with inclination
+    const FIELD: Vector3 = Vector3::new(20.0, 0.0, 45.0);
+
+    /// sample of a stationary IMU at the attitude
+    fn sample(attitude: &Quaternion, rate: Vector3, bias: Vector3) -> ImuData {
+        ImuData {
+            accelerometer: Some(attitude.rotate_vector(DOWN * -GRAVITY, true)),
+            gyroscope: Some((rate + bias) * (180.0 / core::f32::consts::PI)),
+            magnetometer: Some(attitude.rotate_vector(FIELD, true)),
+            ..Default::default()
+        }
+    }
+
+    fn degrees(radians: f32) -> f32 {
+        radians * 180.0 / core::f32::consts::PI
+    }
+
+    const FILTERS: [Filter; 2] = [
+        Filter::Mahony { kp: 2.0, ki: 0.1 },
+        Filter::Madgwick {
+            beta: 0.2,
+            zeta: 0.02,
+        },
+    ];
+
+    #[test]
+    fn aligns_to_reference_vectors() {
+        let truth = Vector3::new(0.3, -0.2, 2.5).euler_to_quaternion();
+        let mut estimator = AttitudeEstimator::new(Filter::default());
+        let mut data = sample(&truth, Vector3::ZERO, Vector3::ZERO);
+        let estimate = estimator.update(&mut data, DT).unwrap();
+        assert!(degrees(estimate.angle_to(&truth)) < 0.1);
+        let euler = data.euler.unwrap();
+        assert!((euler.z - degrees(2.5)).abs() < 0.1, "{euler:?}");
+        // at rest, the accelerometer only measures (the reaction to) gravity
+        let gravity = data.gravity.unwrap();
+        assert!(
+            (gravity + data.accelerometer.unwrap()).norm() < 0.01,
+            "{gravity:?}"
+        );
+        assert!(data.linear_acceleration.unwrap().norm() < 0.01);
+
+        // accelerating forward (body frame)
+        let mut data = sample(&truth, Vector3::ZERO, Vector3::ZERO);
+        let forward = Vector3::new(1.5, 0.0, 0.0);
+        data.accelerometer = data.accelerometer.map(|a| a + forward);
+        estimator.update(&mut data, DT).unwrap();
+        let linear = data.linear_acceleration.unwrap();
+        assert!((linear - forward).norm() < 0.01, "{linear:?}");
+    }
+
+    #[test]
+    fn converges_to_attitude() {
+        for filter in FILTERS {
+            let truth = Vector3::new(-0.4, 0.3, -1.0).euler_to_quaternion();
+            let mut estimator = AttitudeEstimator::new(filter);
+            // start level facing north
+            estimator.restore(Quaternion::IDENTITY, Vector3::ZERO);
+            for _ in 0..60_000 {
+                let mut data = sample(&truth, Vector3::ZERO, Vector3::ZERO);
+                estimator.update(&mut data, DT).unwrap();
+            }
+            let error = degrees(estimator.attitude().angle_to(&truth));
+            assert!(error < 1.0, "{filter:?} error {error} deg");
+            let mut data = sample(&truth, Vector3::ZERO, Vector3::ZERO);
+            estimator.update(&mut data, DT).unwrap();
+            let gravity = data.gravity.unwrap();
+            let expected = truth.rotate_vector(DOWN * GRAVITY, true);
+            assert!(
+                (gravity - expected).norm() < 0.2,
+                "{filter:?} gravity {gravity:?}"
+            );
+            assert!(data.linear_acceleration.unwrap().norm() < 0.2);
+        }
+    }
+
+    #[test]
+    fn tracks_rotation_with_gyro_bias() {
+        for filter in FILTERS {
+            let bias = Vector3::new(0.02, -0.015, 0.01);
+            let rate = Vector3::new(0.5, -0.3, 1.0);
+            let mut truth = Vector3::new(0.1, 0.1, 0.0).euler_to_quaternion();
+            let mut estimator = AttitudeEstimator::new(filter);
+            let mut max_error: f32 = 0.0;
+            for i in 0..60_000 {
+                truth = AttitudeEstimator::integrate(&truth, &rate, DT).normalized();
+                let mut data = sample(&truth, rate, bias);
+                estimator.update(&mut data, DT).unwrap();
+                // after convergence
+                if i > 30_000 {
+                    max_error = max_error.max(degrees(estimator.attitude().angle_to(&truth)));
+                }
+            }
+            assert!(max_error < 2.0, "{filter:?} error {max_error} deg");
+            let bias_error = (estimator.gyro_bias() - bias * degrees(1.0)).norm();
+            assert!(bias_error < 0.2, "{filter:?} bias error {bias_error} deg/s");
+        }
+    }
+
+    #[test]
+    fn requires_gyroscope() {
+        let mut estimator = AttitudeEstimator::new(Filter::default());
+        assert!(estimator.update(&mut ImuData::default(), DT).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Battery state
+//!
+//! Per the measurements of the pack (voltage and current):
+//!     * cells - detected from the (unloaded) voltage of the first measurement
+//!     * consumed charge - integral of the current (mAh)
+//!     * state of charge - per the resting cell voltage, compensated for the
+//!       sag (the drop across the internal resistance under load). With a
+//!       current sensor, the charge is counted from the initial state (coulomb
+//!       counting), as the voltage is ambiguous within the flat of the
+//!       discharge curve.
+//!     * remaining flight time - the usable charge (above the reserve) at the
+//!       average current. Without a current sensor, the average current is
+//!       that of the decline of the (voltage) state of charge since the
+//!       discharge began, which is conservative as the sag is uncompensated.
+
+use log::*;
+use rusty_robot_drivers::battery::BatteryData;
+
+/// state of charge per the resting voltage of a LiPo cell
+const DISCHARGE_CURVE: [(f32, f32); 12] = [
+    (3.30, 0.0),
+    (3.61, 0.05),
+    (3.69, 0.10),
+    (3.73, 0.20),
+    (3.77, 0.30),
+    (3.80, 0.40),
+    (3.84, 0.50),
+    (3.87, 0.60),
+    (3.95, 0.70),
+    (4.02, 0.80),
+    (4.11, 0.90),
+    (4.20, 1.0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatteryConfig {
+    /// capacity of the pack (mAh)
+    pub capacity: f32,
+    /// voltage of a fully charged cell (V) - counts the cells
+    pub max_cell_voltage: f32,
+    /// internal resistance of a cell (ohms)
+    pub cell_resistance: f32,
+    /// charge kept in reserve (0.0 - 1.0)
+    pub reserve: f32,
+    /// time constant of the average current (seconds)
+    pub current_time_constant: f32,
+}
+impl Default for BatteryConfig {
+    fn default() -> Self {
+        BatteryConfig {
+            capacity: 1500.0,
+            max_cell_voltage: 4.35,
+            cell_resistance: 0.01,
+            reserve: 0.2,
+            current_time_constant: 10.0,
+        }
+    }
+}
+
+pub struct Battery {
+    pub config: BatteryConfig,
+    /// count of series cells (once detected)
+    cells: Option<u8>,
+    /// latest measurement
+    latest: BatteryData,
+    /// integral of the current (mAh)
+    consumed: f32,
+    /// low passed current (A)
+    average_current: Option<f32>,
+    /// state of charge upon detection of the cells
+    initial: f32,
+    /// time since the state of charge first declined (without a current
+    ///     sensor)
+    discharge_time: f32,
+}
+
+impl Battery {
+    pub fn new(config: BatteryConfig) -> Self {
+        Battery {
+            config,
+            cells: None,
+            latest: BatteryData::default(),
+            consumed: 0.0,
+            average_current: None,
+            initial: 0.0,
+            discharge_time: 0.0,
+        }
+    }
+
+    /// account for the measurement `dt` seconds after the previous
+    pub fn update(&mut self, data: &BatteryData, dt: f32) -> Result<(), &'static str> {
+        if data.voltage < 1.0 {
+            return Err("no battery");
+        }
+        self.latest = *data;
+        if self.cells.is_none() {
+            let cells = libm::ceilf(data.voltage / self.config.max_cell_voltage).max(1.0) as u8;
+            self.cells = Some(cells);
+            self.initial = self.cell_voltage().map_or(0.0, state_of_charge);
+            info!("{cells}S battery [{:.0}% charged]", self.initial * 100.0);
+        }
+        match data.current {
+            Some(current) => {
+                // A·s to mAh
+                self.consumed += current.max(0.0) * dt / 3.6;
+                let average = self.average_current.get_or_insert(current);
+                *average +=
+                    (current - *average) * (dt / self.config.current_time_constant).min(1.0);
+            }
+            None => {
+                let declined = (self.initial - self.state_of_charge().unwrap_or(self.initial))
+                    * self.config.capacity;
+                if declined > 0.0 || self.discharge_time > 0.0 {
+                    self.discharge_time += dt;
+                }
+                // once the decline spans the averaging time
+                if self.discharge_time >= self.config.current_time_constant {
+                    // mAh to A·s
+                    self.average_current = Some(declined.max(0.0) * 3.6 / self.discharge_time);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cells(&self) -> Option<u8> {
+        self.cells
+    }
+
+    /// integral of the current (mAh)
+    pub fn consumed(&self) -> f32 {
+        self.consumed
+    }
+
+    /// average current (A), estimated per the voltage without a current sensor
+    pub fn average_current(&self) -> Option<f32> {
+        self.average_current
+    }
+
+    /// resting (sag compensated) voltage per cell
+    pub fn cell_voltage(&self) -> Option<f32> {
+        let cells = self.cells? as f32;
+        let sag = self.latest.current.unwrap_or(0.0).max(0.0) * self.config.cell_resistance;
+        Some(self.latest.voltage / cells + sag)
+    }
+
+    /// state of charge (0.0 - 1.0)
+    pub fn state_of_charge(&self) -> Option<f32> {
+        let voltage = self.cell_voltage()?;
+        Some(match self.latest.current {
+            Some(_) => (self.initial - self.consumed / self.config.capacity).clamp(0.0, 1.0),
+            None => state_of_charge(voltage),
+        })
+    }
+
+    /// flight time (seconds) until the reserve, at the average current
+    pub fn remaining_time(&self) -> Option<f32> {
+        let current = self.average_current.filter(|current| *current > 0.1)?;
+        let usable =
+            (self.state_of_charge()? - self.config.reserve).max(0.0) * self.config.capacity;
+        // mAh to A·s
+        Some(usable * 3.6 / current)
+    }
+}
+
+/// state of charge (0.0 - 1.0) per the resting cell voltage
+fn state_of_charge(cell_voltage: f32) -> f32 {
+    let (first, last) = (
+        DISCHARGE_CURVE[0],
+        DISCHARGE_CURVE[DISCHARGE_CURVE.len() - 1],
+    );
+    if cell_voltage <= first.0 {
+        return first.1;
+    }
+    if cell_voltage >= last.0 {
+        return last.1;
+    }
+    DISCHARGE_CURVE
+        .windows(2)
+        .find(|points| cell_voltage < points[1].0)
+        .map_or(last.1, |points| {
+            let ((v0, s0), (v1, s1)) = (points[0], points[1]);
+            s0 + (s1 - s0) * (cell_voltage - v0) / (v1 - v0)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// open circuit voltage of a cell at the state of charge
+    fn open_circuit(soc: f32) -> f32 {
+        DISCHARGE_CURVE
+            .windows(2)
+            .find(|points| soc <= points[1].1)
+            .map(|points| {
+                let ((v0, s0), (v1, s1)) = (points[0], points[1]);
+                v0 + (v1 - v0) * (soc - s0) / (s1 - s0)
+            })
+            .unwrap()
+    }
+
+    /// pack of the cells discharged to the state of charge under the current
+    fn measure(config: &BatteryConfig, cells: f32, soc: f32, current: f32) -> BatteryData {
+        BatteryData {
+            voltage: cells * (open_circuit(soc) - current * config.cell_resistance),
+            current: Some(current),
+        }
+    }
+
+    #[test]
+    fn detects_cells() {
+        for (voltage, cells) in [(4.1, 1), (8.4, 2), (12.6, 3), (14.8, 4), (25.2, 6)] {
+            let mut battery = Battery::new(BatteryConfig::default());
+            battery
+                .update(
+                    &BatteryData {
+                        voltage,
+                        current: None,
+                    },
+                    0.0,
+                )
+                .unwrap();
+            assert_eq!(battery.cells(), Some(cells), "{voltage}V");
+        }
+        let mut battery = Battery::new(BatteryConfig::default());
+        assert_eq!(
+            battery.update(&BatteryData::default(), 0.0),
+            Err("no battery")
+        );
+    }
+
+    #[test]
+    fn compensates_sag() {
+        let config = BatteryConfig::default();
+        let mut battery = Battery::new(config);
+        battery
+            .update(&measure(&config, 4.0, 0.9, 0.5), 0.0)
+            .unwrap();
+        // full throttle punch out
+        let loaded = measure(&config, 4.0, 0.9, 60.0);
+        battery.update(&loaded, 0.01).unwrap();
+        assert!(loaded.voltage / 4.0 < 3.6);
+        let voltage = battery.cell_voltage().unwrap();
+        assert!((voltage - open_circuit(0.9)).abs() < 1e-3, "{voltage}V");
+
+        // without a current sensor, per the voltage
+        let mut battery = Battery::new(config);
+        let voltage = 4.0 * open_circuit(0.5);
+        battery
+            .update(
+                &BatteryData {
+                    voltage,
+                    current: None,
+                },
+                0.0,
+            )
+            .unwrap();
+        assert!((battery.state_of_charge().unwrap() - 0.5).abs() < 1e-3);
+        assert_eq!(battery.remaining_time(), None);
+    }
+
+    #[test]
+    fn estimates_remaining_time_per_voltage() {
+        let config = BatteryConfig::default();
+        let mut battery = Battery::new(config);
+        // resting voltages (without a current sensor) of a 15A discharge
+        let resting = |soc: f32| BatteryData {
+            voltage: 4.0 * open_circuit(soc),
+            current: None,
+        };
+        battery.update(&resting(0.9), 0.0).unwrap();
+        let dt = 0.1;
+        let mut soc = 0.9;
+        for _ in 0..600 {
+            soc -= 15.0 * dt / 3.6 / config.capacity;
+            battery.update(&resting(soc), dt).unwrap();
+        }
+        let current = battery.average_current().unwrap();
+        assert!((current - 15.0).abs() < 1.0, "{current}A");
+        let expected = (soc - config.reserve) * config.capacity * 3.6 / 15.0;
+        let remaining = battery.remaining_time().unwrap();
+        assert!(
+            (remaining - expected).abs() < 0.1 * expected,
+            "{remaining}s vs {expected}s"
+        );
+    }
+
+    #[test]
+    fn counts_charge() {
+        let config = BatteryConfig::default();
+        let mut battery = Battery::new(config);
+        battery
+            .update(&measure(&config, 4.0, 1.0, 0.5), 0.0)
+            .unwrap();
+        assert!((battery.state_of_charge().unwrap() - 1.0).abs() < 1e-3);
+
+        // hover at 15A for a minute (250mAh)
+        let dt = 0.1;
+        let mut soc = 1.0;
+        for _ in 0..600 {
+            soc -= 15.0 * dt / 3.6 / config.capacity;
+            battery
+                .update(&measure(&config, 4.0, soc, 15.0), dt)
+                .unwrap();
+        }
+        assert!((battery.consumed() - 250.0).abs() < 1.0);
+        let estimated = battery.state_of_charge().unwrap();
+        assert!((estimated - soc).abs() < 0.01, "{estimated} vs {soc}");
+
+        // the usable charge (above the reserve) at the average current
+        let current = battery.average_current().unwrap();
+        assert!((current - 15.0).abs() < 0.1);
+        let expected = (soc - config.reserve) * config.capacity * 3.6 / 15.0;
+        let remaining = battery.remaining_time().unwrap();
+        assert!(
+            (remaining - expected).abs() < 5.0,
+            "{remaining}s vs {expected}s"
+        );
+    }
+}
@@ -0,0 +1,881 @@
+//! Navigation estimation (error-state extended Kalman filter)
+//!
+//! Estimates attitude, velocity and position (NED relative to the first GPS
+//! fix) along with the gyroscope and accelerometer biases.
+//!     * IMU samples propagate the state (strapdown integration) at high rate,
+//!       where the uncertainty is propagated at a decimated rate (per the
+//!       accumulated IMU deltas)
+//!     * GPS and barometer measurements correct the state as they arrive,
+//!       where the magnetometer (sampled along with the IMU) corrects the
+//!       heading at a decimated rate
+//!
+//! Measurements are checked against the predicted uncertainty (innovation
+//! gating) so that outliers (i.e. GPS multipath) are rejected, where the
+//! latest checks of each source are reported as the `Health`.
+//!
+//! The filter estimates the error of the state (rather than the state), so
+//! that attitude error can be linearized as a small rotation - see
+//! [Quaternion kinematics for the error-state Kalman filter](https://arxiv.org/abs/1711.02508)
+
+use log::*;
+use rusty_robot_common::{Quaternion, Vector3};
+use rusty_robot_drivers::imu_traits::ImuData;
+use rusty_robot_drivers::nmea::Nmea;
+
+use super::attitude::{AttitudeEstimator, GRAVITY};
+use super::geodetic::GeoPoint;
+
+// error state indices
+const ATTITUDE: usize = 0;
+const VELOCITY: usize = 3;
+const POSITION: usize = 6;
+const GYRO_BIAS: usize = 9;
+const ACCEL_BIAS: usize = 12;
+const STATES: usize = 15;
+
+type Matrix = [[f32; STATES]; STATES];
+type Matrix3 = [[f32; 3]; 3];
+
+const KNOTS_TO_MPS: f32 = 0.514_444;
+
+/// consecutive rejections of a source after which the source is unhealthy
+pub const MAX_REJECTIONS: u32 = 10;
+
+/// uncertainty of the sensors (1 sigma)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EkfConfig {
+    /// gyroscope noise (rad/s/√Hz)
+    pub gyro_noise: f32,
+    /// accelerometer noise (m/s²/√Hz)
+    pub accel_noise: f32,
+    /// gyroscope bias random walk (rad/s²/√Hz)
+    pub gyro_bias_noise: f32,
+    /// accelerometer bias random walk (m/s³/√Hz)
+    pub accel_bias_noise: f32,
+    /// GPS horizontal position (m) - scaled by HDOP
+    pub gps_position_noise: f32,
+    /// GPS vertical position (m) - scaled by HDOP
+    pub gps_altitude_noise: f32,
+    /// GPS velocity (m/s)
+    pub gps_velocity_noise: f32,
+    /// barometric altitude (m)
+    pub baro_noise: f32,
+    /// magnetometer heading (rad)
+    pub heading_noise: f32,
+    /// rate (Hz) of the magnetometer fusion
+    pub heading_rate_hz: f32,
+    /// declination of magnetic north (rad, east positive)
+    pub declination: f32,
+    /// innovations beyond this many standard deviations are rejected
+    pub gate: f32,
+    /// rate (Hz) of the covariance propagation
+    pub covariance_rate_hz: f32,
+}
+impl Default for EkfConfig {
+    fn default() -> Self {
+        EkfConfig {
+            gyro_noise: 0.015,
+            accel_noise: 0.35,
+            gyro_bias_noise: 0.001,
+            accel_bias_noise: 0.003,
+            gps_position_noise: 0.5,
+            gps_altitude_noise: 1.5,
+            gps_velocity_noise: 0.3,
+            baro_noise: 2.0,
+            heading_noise: 0.3,
+            heading_rate_hz: 10.0,
+            declination: 0.0,
+            gate: 5.0,
+            covariance_rate_hz: 200.0,
+        }
+    }
+}
+
+/// estimated navigation state
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NavState {
+    /// body (FRD) to world (NED)
+    pub attitude: Quaternion,
+    /// NED (m/s)
+    pub velocity: Vector3,
+    /// NED (m) relative to the origin
+    pub position: Vector3,
+    /// deg/s
+    pub gyro_bias: Vector3,
+    /// m/s²
+    pub accel_bias: Vector3,
+}
+
+/// innovation checks of a measurement source
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SourceHealth {
+    /// innovation relative to the gate of the latest measurement (> 1.0 rejected)
+    pub test_ratio: f32,
+    /// consecutive rejected measurements
+    pub rejections: u32,
+    /// total fused measurements
+    pub fused: u32,
+}
+impl SourceHealth {
+    fn record(&mut self, result: Result<f32, f32>) {
+        match result {
+            Ok(ratio) => {
+                self.test_ratio = ratio;
+                self.rejections = 0;
+                self.fused += 1;
+            }
+            Err(ratio) => {
+                self.test_ratio = ratio;
+                self.rejections += 1;
+            }
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.rejections < MAX_REJECTIONS
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Health {
+    pub gps_position: SourceHealth,
+    pub gps_velocity: SourceHealth,
+    pub baro: SourceHealth,
+    pub heading: SourceHealth,
+    /// state or covariance are no longer valid (requires a reset)
+    pub diverged: bool,
+}
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        !self.diverged
+            && self.gps_position.is_healthy()
+            && self.gps_velocity.is_healthy()
+            && self.baro.is_healthy()
+            && self.heading.is_healthy()
+    }
+}
+
+pub struct Ekf {
+    pub config: EkfConfig,
+    attitude: Quaternion,
+    velocity: Vector3,
+    position: Vector3,
+    /// rad/s
+    gyro_bias: Vector3,
+    accel_bias: Vector3,
+    covariance: Matrix,
+    /// IMU deltas (time, angle, velocity) since the covariance propagation
+    delta_time: f32,
+    delta_angle: Vector3,
+    delta_velocity: Vector3,
+    /// attitude initialized from gravity
+    aligned: bool,
+    /// position of the navigation origin
+    origin: Option<GeoPoint>,
+    /// barometric altitude of the navigation origin
+    baro_origin: Option<f32>,
+    health: Health,
+}
+
+impl Ekf {
+    pub fn new(config: EkfConfig) -> Self {
+        let mut ekf = Ekf {
+            config,
+            attitude: Quaternion::IDENTITY,
+            velocity: Vector3::ZERO,
+            position: Vector3::ZERO,
+            gyro_bias: Vector3::ZERO,
+            accel_bias: Vector3::ZERO,
+            covariance: [[0.0; STATES]; STATES],
+            delta_time: 0.0,
+            delta_angle: Vector3::ZERO,
+            delta_velocity: Vector3::ZERO,
+            aligned: false,
+            origin: None,
+            baro_origin: None,
+            health: Health::default(),
+        };
+        ekf.reset();
+        ekf
+    }
+
+    /// restart estimation (realigns from the next IMU sample)
+    ///     the origin is retained so that positions remain comparable
+    pub fn reset(&mut self) {
+        self.attitude = Quaternion::IDENTITY;
+        self.velocity = Vector3::ZERO;
+        self.position = Vector3::ZERO;
+        self.gyro_bias = Vector3::ZERO;
+        self.accel_bias = Vector3::ZERO;
+        self.covariance = [[0.0; STATES]; STATES];
+        let initial = [
+            (ATTITUDE, 0.1),
+            (ATTITUDE + 1, 0.1),
+            (ATTITUDE + 2, 0.5),
+            (VELOCITY, 5.0),
+            (VELOCITY + 1, 5.0),
+            (VELOCITY + 2, 5.0),
+            (POSITION, 10.0),
+            (POSITION + 1, 10.0),
+            (POSITION + 2, 10.0),
+            (GYRO_BIAS, 0.05),
+            (GYRO_BIAS + 1, 0.05),
+            (GYRO_BIAS + 2, 0.05),
+            (ACCEL_BIAS, 0.5),
+            (ACCEL_BIAS + 1, 0.5),
+            (ACCEL_BIAS + 2, 0.5),
+        ];
+        for (i, sigma) in initial {
+            self.covariance[i][i] = sigma * sigma;
+        }
+        self.delta_time = 0.0;
+        self.delta_angle = Vector3::ZERO;
+        self.delta_velocity = Vector3::ZERO;
+        self.aligned = false;
+        self.baro_origin = None;
+        self.health = Health::default();
+    }
+
+    /// resume estimation from a prior state (i.e. retained across a reset)
+    ///     where the uncertainty is that of a restart
+    pub fn restore(&mut self, origin: Option<GeoPoint>, state: &NavState) {
+        self.reset();
+        self.attitude = state.attitude.normalized();
+        self.velocity = state.velocity;
+        self.position = state.position;
+        self.gyro_bias = state.gyro_bias * (core::f32::consts::PI / 180.0);
+        self.accel_bias = state.accel_bias;
+        self.aligned = true;
+        self.origin = origin;
+    }
+
+    pub fn state(&self) -> NavState {
+        NavState {
+            attitude: self.attitude,
+            velocity: self.velocity,
+            position: self.position,
+            gyro_bias: self.gyro_bias * (180.0 / core::f32::consts::PI),
+            accel_bias: self.accel_bias,
+        }
+    }
+
+    pub fn health(&self) -> Health {
+        self.health
+    }
+
+    /// position of the NED origin (once a GPS fix has been fused)
+    pub fn origin(&self) -> Option<GeoPoint> {
+        self.origin
+    }
+
+    /// uncertainty (1 sigma) of the position (m)
+    pub fn position_uncertainty(&self) -> Vector3 {
+        let p = &self.covariance;
+        Vector3::new(
+            libm::sqrtf(p[POSITION][POSITION]),
+            libm::sqrtf(p[POSITION + 1][POSITION + 1]),
+            libm::sqrtf(p[POSITION + 2][POSITION + 2]),
+        )
+    }
+
+    /// propagate the state by an IMU sample taken `dt` seconds after the previous
+    pub fn predict(&mut self, imu: &ImuData, dt: f32) -> Result<(), &'static str> {
+        let gyro = imu.gyroscope.ok_or("no gyroscope")? * (core::f32::consts::PI / 180.0);
+        let accel = imu.accelerometer.ok_or("no accelerometer")?;
+        if !self.aligned {
+            let down = (-accel).normalized().ok_or("no gravity")?;
+            let mag = imu.magnetometer.and_then(|m| m.normalized());
+            self.attitude = AttitudeEstimator::align(&down, mag.as_ref());
+            self.aligned = true;
+            return Ok(());
+        }
+
+        // strapdown integration of the nominal state
+        let rate = gyro - self.gyro_bias;
+        let specific_force = accel - self.accel_bias;
+        let accel_world =
+            self.attitude.rotate_vector(specific_force, false) + Vector3::new(0.0, 0.0, GRAVITY);
+        self.position += self.velocity * dt + accel_world * (0.5 * dt * dt);
+        self.velocity += accel_world * dt;
+        self.attitude =
+            (self.attitude * Quaternion::from_rotation_vector(&(rate * dt))).normalized();
+
+        self.delta_time += dt;
+        self.delta_angle += rate * dt;
+        self.delta_velocity += specific_force * dt;
+        if self.delta_time * self.config.covariance_rate_hz >= 1.0 {
+            self.propagate_covariance();
+        }
+        self.check_divergence()
+    }
+
+    /// propagate the error covariance (P = F P Fᵀ + Q) by the accumulated deltas
+    fn propagate_covariance(&mut self) {
+        let dt = self.delta_time;
+        if dt <= 0.0 {
+            return;
+        }
+        let r = rotation_matrix(&self.attitude);
+        let mut f = identity();
+        add_block(&mut f, ATTITUDE, ATTITUDE, &skew(&self.delta_angle), -1.0);
+        add_block(&mut f, ATTITUDE, GYRO_BIAS, &IDENTITY3, -dt);
+        add_block(
+            &mut f,
+            VELOCITY,
+            ATTITUDE,
+            &mul3(&r, &skew(&self.delta_velocity)),
+            -1.0,
+        );
+        add_block(&mut f, VELOCITY, ACCEL_BIAS, &r, -dt);
+        add_block(&mut f, POSITION, VELOCITY, &IDENTITY3, dt);
+        self.covariance = mul_transpose(&mul(&f, &self.covariance), &f);
+
+        let config = &self.config;
+        let noise = [
+            (ATTITUDE, config.gyro_noise),
+            (VELOCITY, config.accel_noise),
+            (GYRO_BIAS, config.gyro_bias_noise),
+            (ACCEL_BIAS, config.accel_bias_noise),
+        ];
+        for (block, sigma) in noise {
+            for i in block..block + 3 {
+                self.covariance[i][i] += sigma * sigma * dt;
+            }
+        }
+        self.delta_time = 0.0;
+        self.delta_angle = Vector3::ZERO;
+        self.delta_velocity = Vector3::ZERO;
+    }
+
+    /// correct the state by a GPS fix (position and if available, ground velocity)
+    pub fn fuse_gps(&mut self, gps: &Nmea) -> Result<(), &'static str> {
+        let point = GeoPoint::from_nmea(gps).ok_or("no fix")?;
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None => {
+                // origin which places the fix at the current estimate
+                let origin = GeoPoint::from_ned(&point, &-self.position);
+                info!("navigation origin {:?}", origin);
+                self.origin = Some(origin);
+                origin
+            }
+        };
+        let scale = gps.hdop.unwrap_or(1.0).max(1.0);
+        let horizontal = square(self.config.gps_position_noise * scale);
+        let vertical = square(self.config.gps_altitude_noise * scale);
+        let ned = point.to_ned(&origin);
+        let result = self.fuse_direct(&[
+            (POSITION, ned.x, horizontal),
+            (POSITION + 1, ned.y, horizontal),
+            (POSITION + 2, ned.z, vertical),
+        ]);
+        self.health.gps_position.record(result);
+        let position = result.map(|_| ()).map_err(|_| "gps position rejected");
+
+        if let (Some(speed), Some(course)) = (gps.speed_over_ground, gps.true_course) {
+            let speed = speed * KNOTS_TO_MPS;
+            let course = course.to_radians();
+            let variance = square(self.config.gps_velocity_noise);
+            let result = self.fuse_direct(&[
+                (VELOCITY, speed * libm::cosf(course), variance),
+                (VELOCITY + 1, speed * libm::sinf(course), variance),
+            ]);
+            self.health.gps_velocity.record(result);
+            result.map_err(|_| "gps velocity rejected")?;
+        }
+        position?;
+        self.check_divergence()
+    }
+
+    /// correct the state by a barometric altitude (m)
+    ///     the first altitude is the reference of the origin (baro offset)
+    pub fn fuse_baro(&mut self, altitude: f32) -> Result<(), &'static str> {
+        let baro_origin = *self.baro_origin.get_or_insert(altitude + self.position.z);
+        let variance = square(self.config.baro_noise);
+        let result = self.fuse_direct(&[(POSITION + 2, baro_origin - altitude, variance)]);
+        self.health.baro.record(result);
+        result.map_err(|_| "baro rejected")?;
+        self.check_divergence()
+    }
+
+    /// correct the heading by the magnetic field (body frame)
+    pub fn fuse_magnetometer(&mut self, mag: &Vector3) -> Result<(), &'static str> {
+        if !self.aligned {
+            return Err("not aligned");
+        }
+        // field in the world frame per the estimated attitude
+        let h = self.attitude.rotate_vector(*mag, false);
+        if libm::sqrtf(h.x * h.x + h.y * h.y) < 0.1 * mag.norm() {
+            return Err("field too vertical for heading");
+        }
+        let innovation = wrap_pi(self.config.declination - libm::atan2f(h.y, h.x));
+        self.propagate_covariance();
+        // heading is the rotation about world down (of the body frame error)
+        let r = rotation_matrix(&self.attitude);
+        let mut row = [0.0; STATES];
+        row[ATTITUDE..ATTITUDE + 3].copy_from_slice(&r[2]);
+        let variance = square(self.config.heading_noise);
+        let result = match self.test_ratio(&row, innovation, variance) {
+            ratio if ratio <= 1.0 => {
+                self.fuse(&row, innovation, variance);
+                Ok(ratio)
+            }
+            ratio => Err(ratio),
+        };
+        self.health.heading.record(result);
+        result.map_err(|_| "heading rejected")?;
+        self.check_divergence()
+    }
+
+    /// fuse direct measurements of states - (state, measurement, variance)
+    ///     measurements are rejected together if any fails the gate
+    fn fuse_direct(&mut self, measurements: &[(usize, f32, f32)]) -> Result<f32, f32> {
+        // uncertainty as of the measurement
+        self.propagate_covariance();
+        let mut ratio: f32 = 0.0;
+        for (state, measurement, variance) in measurements {
+            let innovation = measurement - self.nominal(*state);
+            ratio = ratio.max(self.test_ratio(&unit(*state), innovation, *variance));
+        }
+        if ratio > 1.0 {
+            return Err(ratio);
+        }
+        for (state, measurement, variance) in measurements {
+            // innovation of the state as corrected by the prior measurements
+            let innovation = measurement - self.nominal(*state);
+            self.fuse(&unit(*state), innovation, *variance);
+        }
+        Ok(ratio)
+    }
+
+    /// nominal value of a (velocity or position) state
+    fn nominal(&self, state: usize) -> f32 {
+        let v = match state {
+            VELOCITY..POSITION => &self.velocity,
+            _ => &self.position,
+        };
+        match state % 3 {
+            0 => v.x,
+            1 => v.y,
+            _ => v.z,
+        }
+    }
+
+    /// innovation relative to the gate
+    fn test_ratio(&self, row: &[f32; STATES], innovation: f32, variance: f32) -> f32 {
+        let pht = mul_vector(&self.covariance, row);
+        let s = dot(row, &pht) + variance;
+        square(innovation) / (square(self.config.gate) * s)
+    }
+
+    /// Kalman update of a scalar measurement (with observation row)
+    fn fuse(&mut self, row: &[f32; STATES], innovation: f32, variance: f32) {
+        let pht = mul_vector(&self.covariance, row);
+        let s = dot(row, &pht) + variance;
+        let gain = pht.map(|v| v / s);
+        for (i, k) in gain.iter().enumerate() {
+            for (j, p) in pht.iter().enumerate() {
+                self.covariance[i][j] -= k * p;
+            }
+        }
+        symmetrize(&mut self.covariance);
+
+        // inject the error into the nominal state
+        let error = gain.map(|k| k * innovation);
+        let block = |i: usize| Vector3::new(error[i], error[i + 1], error[i + 2]);
+        self.attitude =
+            (self.attitude * Quaternion::from_rotation_vector(&block(ATTITUDE))).normalized();
+        self.velocity += block(VELOCITY);
+        self.position += block(POSITION);
+        self.gyro_bias += block(GYRO_BIAS);
+        self.accel_bias += block(ACCEL_BIAS);
+    }
+
+    fn check_divergence(&mut self) -> Result<(), &'static str> {
+        let state_valid = [
+            self.velocity,
+            self.position,
+            self.gyro_bias,
+            self.accel_bias,
+        ]
+        .iter()
+        .all(|v| v.x.is_finite() && v.y.is_finite() && v.z.is_finite())
+            && self.attitude.w.is_finite();
+        let covariance_valid = (0..STATES).all(|i| {
+            let v = self.covariance[i][i];
+            v.is_finite() && v >= 0.0
+        });
+        if !(state_valid && covariance_valid) {
+            if !self.health.diverged {
+                error!("navigation estimate diverged");
+            }
+            self.health.diverged = true;
+            return Err("diverged");
+        }
+        Ok(())
+    }
+}
+
+fn square(v: f32) -> f32 {
+    v * v
+}
+
+/// angle within [-π, π]
+fn wrap_pi(angle: f32) -> f32 {
+    use core::f32::consts::PI;
+    let wrapped = (angle + PI) % (2.0 * PI);
+    if wrapped < 0.0 {
+        wrapped + PI
+    } else {
+        wrapped - PI
+    }
+}
+
+const IDENTITY3: Matrix3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+fn identity() -> Matrix {
+    let mut m = [[0.0; STATES]; STATES];
+    for (i, row) in m.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    m
+}
+
+fn unit(state: usize) -> [f32; STATES] {
+    let mut row = [0.0; STATES];
+    row[state] = 1.0;
+    row
+}
+
+/// rotation matrix of the quaternion (body to world)
+fn rotation_matrix(q: &Quaternion) -> Matrix3 {
+    let (w, x, y, z) = (q.w, q.x, q.y, q.z);
+    [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y - w * z),
+            2.0 * (x * z + w * y),
+        ],
+        [
+            2.0 * (x * y + w * z),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z - w * x),
+        ],
+        [
+            2.0 * (x * z - w * y),
+            2.0 * (y * z + w * x),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ]
+}
+
+/// cross product matrix ([v]× u = v × u)
+fn skew(v: &Vector3) -> Matrix3 {
+    [[0.0, -v.z, v.y], [v.z, 0.0, -v.x], [-v.y, v.x, 0.0]]
+}
+
+fn mul3(a: &Matrix3, b: &Matrix3) -> Matrix3 {
+    let mut m = [[0.0; 3]; 3];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+/// m[row.., col..] += scale * block
+fn add_block(m: &mut Matrix, row: usize, col: usize, block: &Matrix3, scale: f32) {
+    for i in 0..3 {
+        for j in 0..3 {
+            m[row + i][col + j] += scale * block[i][j];
+        }
+    }
+}
+
+fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; STATES]; STATES];
+    for i in 0..STATES {
+        for k in 0..STATES {
+            // transition matrices are sparse
+            let a_ik = a[i][k];
+            if a_ik == 0.0 {
+                continue;
+            }
+            for j in 0..STATES {
+                m[i][j] += a_ik * b[k][j];
+            }
+        }
+    }
+    m
+}
+
+/// a bᵀ
+fn mul_transpose(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; STATES]; STATES];
+    for i in 0..STATES {
+        for j in 0..STATES {
+            m[i][j] = dot(&a[i], &b[j]);
+        }
+    }
+    m
+}
+
+fn mul_vector(m: &Matrix, v: &[f32; STATES]) -> [f32; STATES] {
+    m.map(|row| dot(&row, v))
+}
+
+fn dot(a: &[f32; STATES], b: &[f32; STATES]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+fn symmetrize(m: &mut Matrix) {
+    for i in 1..STATES {
+        // rows above i (upper) and row i (lower)
+        let (upper, lower) = m.split_at_mut(i);
+        for (j, row) in upper.iter_mut().enumerate() {
+            let v = 0.5 * (row[i] + lower[0][j]);
+            row[i] = v;
+            lower[0][j] = v;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMU_RATE_HZ: usize = 250;
+    const DT: f32 = 1.0 / (IMU_RATE_HZ as f32);
+    /// magnetic field (NED, µT)
+    const FIELD: Vector3 = Vector3::new(20.0, 0.0, 45.0);
+    const BASE: GeoPoint = GeoPoint {
+        latitude: 47.6,
+        longitude: -122.3,
+        altitude: 100.0,
+    };
+
+    /// deterministic noise (approximately normal, 1 sigma)
+    struct Noise(u32);
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            let mut sum = 0.0;
+            for _ in 0..3 {
+                self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                sum += (self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5;
+            }
+            // sum of 3 uniform [-0.5, 0.5] has a variance of 0.25
+            sum * 2.0
+        }
+        fn vector(&mut self, sigma: f32) -> Vector3 {
+            Vector3::new(self.next(), self.next(), self.next()) * sigma
+        }
+    }
+
+    /// flight truth at time t
+    struct Truth {
+        attitude: Quaternion,
+        rate: Vector3,
+        position: Vector3,
+        velocity: Vector3,
+        acceleration: Vector3,
+    }
+
+    /// circle (radius 20m) while climbing/descending and yawing back and forth
+    fn circle(t: f32) -> Truth {
+        const RADIUS: f32 = 20.0;
+        const W: f32 = 0.3;
+        let (s, c) = (libm::sinf(W * t), libm::cosf(W * t));
+        let (sz, cz) = (libm::sinf(0.2 * t), libm::cosf(0.2 * t));
+        let (sy, cy) = (libm::sinf(0.15 * t), libm::cosf(0.15 * t));
+        Truth {
+            attitude: Vector3::new(0.0, 0.0, 1.5 * sy).euler_to_quaternion(),
+            rate: Vector3::new(0.0, 0.0, 1.5 * 0.15 * cy),
+            position: Vector3::new(RADIUS * s, RADIUS * (1.0 - c), -5.0 * sz),
+            velocity: Vector3::new(RADIUS * W * c, RADIUS * W * s, -5.0 * 0.2 * cz),
+            acceleration: Vector3::new(-RADIUS * W * W * s, RADIUS * W * W * c, 5.0 * 0.04 * sz),
+        }
+    }
+
+    fn imu(truth: &Truth, noise: &mut Noise, gyro_bias: Vector3, accel_bias: Vector3) -> ImuData {
+        let specific_force = truth
+            .attitude
+            .rotate_vector(truth.acceleration - Vector3::new(0.0, 0.0, GRAVITY), true);
+        ImuData {
+            accelerometer: Some(specific_force + accel_bias + noise.vector(0.05)),
+            gyroscope: Some(
+                (truth.rate + gyro_bias + noise.vector(0.002)) * (180.0 / core::f32::consts::PI),
+            ),
+            magnetometer: Some(truth.attitude.rotate_vector(FIELD, true)),
+            ..Default::default()
+        }
+    }
+
+    fn gps(position: &Vector3, noise: &mut Noise) -> Nmea {
+        let point = GeoPoint::from_ned(&BASE, &(*position + noise.vector(0.3)));
+        let mut nmea = Nmea::default();
+        nmea.latitude = Some(point.latitude);
+        nmea.longitude = Some(point.longitude);
+        nmea.altitude = Some(point.altitude);
+        nmea
+    }
+
+    fn gps_with_velocity(truth: &Truth, noise: &mut Noise) -> Nmea {
+        let mut nmea = gps(&truth.position, noise);
+        let velocity = truth.velocity + noise.vector(0.1);
+        let speed = libm::sqrtf(velocity.x * velocity.x + velocity.y * velocity.y);
+        nmea.speed_over_ground = Some(speed / KNOTS_TO_MPS);
+        nmea.true_course = Some(libm::atan2f(velocity.y, velocity.x).to_degrees());
+        nmea
+    }
+
+    /// fly the trajectory returning the maximum (position, velocity, attitude)
+    /// errors of the last half
+    fn fly(ekf: &mut Ekf, seconds: usize, trajectory: fn(f32) -> Truth) -> (f32, f32, f32) {
+        let mut noise = Noise(1);
+        let gyro_bias = Vector3::new(0.01, -0.02, 0.005);
+        let accel_bias = Vector3::new(0.05, -0.1, 0.2);
+        let mut errors = (0.0_f32, 0.0_f32, 0.0_f32);
+        for i in 0..(seconds * IMU_RATE_HZ) {
+            let t = i as f32 * DT;
+            let truth = trajectory(t);
+            let imu = imu(&truth, &mut noise, gyro_bias, accel_bias);
+            ekf.predict(&imu, DT).unwrap();
+            if i % (IMU_RATE_HZ / 10) == 0 {
+                let _ = ekf.fuse_magnetometer(&imu.magnetometer.unwrap());
+            }
+            if i % (IMU_RATE_HZ / 5) == 0 {
+                let _ = ekf.fuse_gps(&gps_with_velocity(&truth, &mut noise));
+            }
+            if i % (IMU_RATE_HZ / 25) == 0 {
+                let altitude = BASE.altitude - truth.position.z + noise.next() * 0.5;
+                let _ = ekf.fuse_baro(altitude);
+            }
+            if i > seconds * IMU_RATE_HZ / 2 {
+                let state = ekf.state();
+                // truth relative to the origin of the estimate
+                let position = truth.position + BASE.to_ned(&ekf.origin().unwrap());
+                errors.0 = errors.0.max((state.position - position).norm());
+                errors.1 = errors.1.max((state.velocity - truth.velocity).norm());
+                errors.2 = errors
+                    .2
+                    .max(state.attitude.angle_to(&truth.attitude).to_degrees());
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn estimates_while_stationary() {
+        let mut ekf = Ekf::new(EkfConfig::default());
+        let (position, velocity, attitude) = fly(&mut ekf, 30, |_| Truth {
+            attitude: Quaternion::IDENTITY,
+            rate: Vector3::ZERO,
+            position: Vector3::ZERO,
+            velocity: Vector3::ZERO,
+            acceleration: Vector3::ZERO,
+        });
+        assert!(position < 1.0, "position error {position}");
+        assert!(velocity < 0.2, "velocity error {velocity}");
+        // horizontal accel bias is indistinguishable from tilt while stationary
+        assert!(attitude < 2.0, "attitude error {attitude}");
+        assert!(ekf.health().is_healthy(), "{:?}", ekf.health());
+        assert!((ekf.origin().unwrap().latitude - BASE.latitude).abs() < 1e-5);
+    }
+
+    #[test]
+    fn tracks_flight() {
+        let mut ekf = Ekf::new(EkfConfig::default());
+        let (position, velocity, attitude) = fly(&mut ekf, 60, circle);
+        assert!(position < 1.0, "position error {position}");
+        assert!(velocity < 0.75, "velocity error {velocity}");
+        assert!(attitude < 2.0, "attitude error {attitude}");
+        assert!(ekf.health().is_healthy(), "{:?}", ekf.health());
+        // biases are observable while maneuvering
+        let bias = ekf.state().gyro_bias - Vector3::new(0.01, -0.02, 0.005) * 57.29578;
+        assert!(bias.norm() < 0.2, "gyro bias error {bias:?}");
+        let bias = ekf.state().accel_bias - Vector3::new(0.05, -0.1, 0.2);
+        assert!(bias.norm() < 0.05, "accel bias error {bias:?}");
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let mut ekf = Ekf::new(EkfConfig::default());
+        fly(&mut ekf, 10, |_| Truth {
+            attitude: Quaternion::IDENTITY,
+            rate: Vector3::ZERO,
+            position: Vector3::ZERO,
+            velocity: Vector3::ZERO,
+            acceleration: Vector3::ZERO,
+        });
+        let before = ekf.state().position;
+        let mut noise = Noise(2);
+        let jump = Vector3::new(100.0, 0.0, 0.0);
+        for _ in 0..MAX_REJECTIONS {
+            assert!(ekf.health().gps_position.is_healthy());
+            assert_eq!(
+                ekf.fuse_gps(&gps(&jump, &mut noise)),
+                Err("gps position rejected")
+            );
+        }
+        let health = ekf.health();
+        assert!(health.gps_position.test_ratio > 1.0);
+        assert!(!health.is_healthy());
+        assert_eq!(ekf.state().position, before, "outlier fused");
+
+        // recovers upon a valid fix
+        ekf.fuse_gps(&gps(&Vector3::ZERO, &mut noise)).unwrap();
+        assert!(ekf.health().is_healthy());
+    }
+
+    #[test]
+    fn decimates_covariance_propagation() {
+        let level = ImuData {
+            accelerometer: Some(Vector3::new(0.0, 0.0, -GRAVITY)),
+            gyroscope: Some(Vector3::ZERO),
+            ..Default::default()
+        };
+        // (exactly representable periods)
+        let config = EkfConfig {
+            covariance_rate_hz: 256.0,
+            ..Default::default()
+        };
+        let period = 1.0 / config.covariance_rate_hz;
+        // a fast IMU accumulates deltas until the period
+        let mut fast = Ekf::new(config);
+        fast.predict(&level, 0.0).unwrap();
+        let initial = fast.position_uncertainty();
+        for _ in 0..31 {
+            fast.predict(&level, period / 32.0).unwrap();
+        }
+        assert_eq!(fast.position_uncertainty(), initial);
+        fast.predict(&level, period / 32.0).unwrap();
+        assert!(fast.position_uncertainty().x > initial.x);
+
+        // equivalent to propagating at the period
+        let mut slow = Ekf::new(config);
+        slow.predict(&level, 0.0).unwrap();
+        slow.predict(&level, period).unwrap();
+        let difference = fast.position_uncertainty() - slow.position_uncertainty();
+        assert!(difference.norm() < 1e-4, "{difference:?}");
+    }
+
+    #[test]
+    fn detects_divergence() {
+        let mut ekf = Ekf::new(EkfConfig::default());
+        let level = ImuData {
+            accelerometer: Some(Vector3::new(0.0, 0.0, -GRAVITY)),
+            gyroscope: Some(Vector3::ZERO),
+            ..Default::default()
+        };
+        ekf.predict(&level, DT).unwrap();
+        let invalid = ImuData {
+            accelerometer: Some(Vector3::new(f32::NAN, 0.0, -GRAVITY)),
+            ..level
+        };
+        assert_eq!(ekf.predict(&invalid, DT), Err("diverged"));
+        assert!(ekf.health().diverged);
+        ekf.reset();
+        assert!(ekf.health().is_healthy());
+    }
+}
@@ -0,0 +1,76 @@
+//! Geodetic positions
+//!
+//! Positions are navigated as NED (north, east, down) meters relative to an
+//! origin (i.e. the first GPS fix). Uses a local flat earth approximation,
+//! which is accurate within the range of a small vehicle (a few kilometers).
+
+use rusty_robot_common::Vector3;
+use rusty_robot_drivers::nmea::Nmea;
+
+/// mean radius of the earth (meters)
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GeoPoint {
+    /// degrees
+    pub latitude: f64,
+    /// degrees
+    pub longitude: f64,
+    /// meters (above mean sea level)
+    pub altitude: f32,
+}
+
+impl GeoPoint {
+    /// position of a valid GPS fix
+    pub fn from_nmea(gps: &Nmea) -> Option<Self> {
+        if gps.fix_type.is_some_and(|fix| !fix.is_valid()) {
+            return None;
+        }
+        Some(GeoPoint {
+            latitude: gps.latitude?,
+            longitude: gps.longitude?,
+            altitude: gps.altitude?,
+        })
+    }
+
+    /// NED (meters) of this point relative to the origin
+    pub fn to_ned(&self, origin: &GeoPoint) -> Vector3 {
+        let north = (self.latitude - origin.latitude).to_radians() * EARTH_RADIUS;
+        let east = (self.longitude - origin.longitude).to_radians()
+            * EARTH_RADIUS
+            * libm::cos(origin.latitude.to_radians());
+        Vector3::new(north as f32, east as f32, origin.altitude - self.altitude)
+    }
+
+    /// point at the NED (meters) relative to the origin
+    pub fn from_ned(origin: &GeoPoint, ned: &Vector3) -> Self {
+        let latitude = origin.latitude + (ned.x as f64 / EARTH_RADIUS).to_degrees();
+        let longitude = origin.longitude
+            + (ned.y as f64 / (EARTH_RADIUS * libm::cos(origin.latitude.to_radians())))
+                .to_degrees();
+        GeoPoint {
+            latitude,
+            longitude,
+            altitude: origin.altitude - ned.z,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_ned() {
+        let origin = GeoPoint {
+            latitude: 47.6,
+            longitude: -122.3,
+            altitude: 50.0,
+        };
+        let ned = Vector3::new(120.0, -80.0, -30.0);
+        let point = GeoPoint::from_ned(&origin, &ned);
+        assert_eq!(point.altitude, 80.0);
+        let actual = point.to_ned(&origin);
+        assert!((actual - ned).norm() < 0.01, "{actual:?}");
+    }
+}
@@ -0,0 +1,10 @@
+// provide attitude from the IMU
+pub mod attitude;
+// provide navigation (attitude, velocity, position) from IMU, GPS and baro
+pub mod ekf;
+// provide conversion of GPS positions into local coordinates
+pub mod geodetic;
+// provide online estimation of linear model parameters
+pub mod rls;
+// provide battery state (charge, remaining flight time) from voltage and current
+pub mod battery;
@@ -0,0 +1,113 @@
+//! Recursive least squares
+//!
+//! Estimates the parameters θ of a linear model `y = φᵀθ` as measurements
+//! arrive, where the forgetting factor (λ < 1.0) discounts older measurements
+//! so that the estimate tracks parameters that change over time - see
+//! [Recursive least squares filter](https://en.wikipedia.org/wiki/Recursive_least_squares_filter).
+
+pub struct Rls<const P: usize> {
+    /// estimated parameters
+    theta: [f32; P],
+    covariance: [[f32; P]; P],
+    /// weight of the prior measurements (0.0 - 1.0)
+    forgetting: f32,
+    /// limit of the covariance (diagonal) - avoids windup without excitation
+    max_variance: f32,
+}
+
+impl<const P: usize> Rls<P> {
+    /// initial parameters with their uncertainty (1 sigma per unit of
+    ///     measurement noise)
+    pub fn new(theta: [f32; P], sigma: f32, forgetting: f32) -> Self {
+        let mut covariance = [[0.0; P]; P];
+        for (i, row) in covariance.iter_mut().enumerate() {
+            row[i] = sigma * sigma;
+        }
+        Rls {
+            theta,
+            covariance,
+            forgetting,
+            max_variance: sigma * sigma,
+        }
+    }
+
+    pub fn parameters(&self) -> [f32; P] {
+        self.theta
+    }
+
+    /// uncertainty (1 sigma) of the parameter per unit of measurement noise
+    pub fn uncertainty(&self, i: usize) -> f32 {
+        libm::sqrtf(self.covariance[i][i].max(0.0))
+    }
+
+    pub fn predict(&self, phi: &[f32; P]) -> f32 {
+        phi.iter().zip(&self.theta).map(|(p, t)| p * t).sum()
+    }
+
+    /// account for the measurement `y` of the regressors `phi`
+    ///     returns the error of the prior prediction
+    pub fn update(&mut self, phi: &[f32; P], y: f32) -> f32 {
+        let error = y - self.predict(phi);
+
+        // gain = Pφ / (λ + φᵀPφ)
+        let mut p_phi = [0.0; P];
+        for (v, row) in p_phi.iter_mut().zip(&self.covariance) {
+            *v = row.iter().zip(phi).map(|(p, f)| p * f).sum();
+        }
+        let denominator = self.forgetting + phi.iter().zip(&p_phi).map(|(f, v)| f * v).sum::<f32>();
+        if denominator <= f32::EPSILON {
+            return error;
+        }
+        let gain = p_phi.map(|v| v / denominator);
+
+        for (t, k) in self.theta.iter_mut().zip(&gain) {
+            *t += k * error;
+        }
+        // P = (P - K φᵀP) / λ (P symmetric, so φᵀP = (Pφ)ᵀ)
+        //     kept symmetric against the accumulation of rounding errors
+        for i in 0..P {
+            for j in i..P {
+                let p = (self.covariance[i][j] + self.covariance[j][i]) * 0.5;
+                let update = (gain[i] * p_phi[j] + gain[j] * p_phi[i]) * 0.5;
+                self.covariance[i][j] = (p - update) / self.forgetting;
+                self.covariance[j][i] = self.covariance[i][j];
+            }
+        }
+        // bound the uncertainty of unexcited parameters
+        for i in 0..P {
+            let variance = self.covariance[i][i];
+            if variance > self.max_variance {
+                let scale = libm::sqrtf(self.max_variance / variance);
+                for j in 0..P {
+                    self.covariance[i][j] *= scale;
+                    self.covariance[j][i] *= scale;
+                }
+            }
+        }
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_linear_model() {
+        let truth = [2.0, -0.5, 0.25];
+        let mut rls = Rls::new([0.0; 3], 10.0, 0.999);
+        let mut seed: u32 = 1;
+        for _ in 0..2000 {
+            let phi = [0.0; 3].map(|_: f32| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 - 0.5
+            });
+            let y = phi.iter().zip(&truth).map(|(p, t)| p * t).sum();
+            rls.update(&phi, y);
+        }
+        for (estimate, truth) in rls.parameters().iter().zip(&truth) {
+            assert!((estimate - truth).abs() < 1e-3, "{estimate} != {truth}");
+        }
+        assert!(rls.uncertainty(0) < 0.2);
+    }
+}
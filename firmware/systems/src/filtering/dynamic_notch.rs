@@ -0,0 +1,367 @@
+//! Dynamic notch (tracking of the noise peaks)
+//!
+//! The noise of the motors (and of the frame resonances they excite) shifts
+//! with the throttle. The spectrum of each axis is analyzed (FFT of the latest
+//! samples) for the strongest peaks above the noise floor, and notches track
+//! their frequencies.
+//!     * analysis - the samples are decimated (averaged) to the analysis rate,
+//!       windowed (Hann) and transformed each half of the buffer (overlap)
+//!     * peaks - local maxima within the range, stronger than the threshold
+//!       times the noise floor (median of the range), interpolated between
+//!       bins (parabolic)
+//!     * tracking - each peak (strongest first) is assigned the nearest notch,
+//!       whose center is smoothed toward the peak
+
+use core::f32::consts::PI;
+use rusty_robot_common::Vector3;
+
+use super::iir::Biquad;
+
+/// samples of the analysis (power of two)
+pub const FFT_SIZE: usize = 64;
+
+/// capacity of the notches (per axis)
+pub const MAX_PEAKS: usize = 3;
+
+/// bins of the spectrum (DC to nyquist)
+const BINS: usize = FFT_SIZE / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DynamicNotchConfig {
+    /// notches per axis (up to MAX_PEAKS)
+    pub count: usize,
+    /// range of the tracked peaks (Hz)
+    pub min_hz: f32,
+    pub max_hz: f32,
+    /// quality (center / bandwidth) of the notches
+    pub q: f32,
+    /// strength of a peak relative to the noise floor
+    pub threshold: f32,
+    /// rate of the analyzed samples (Hz)
+    pub analysis_rate: f32,
+    /// time constant of the tracked centers (seconds)
+    pub smoothing: f32,
+}
+impl Default for DynamicNotchConfig {
+    fn default() -> Self {
+        DynamicNotchConfig {
+            count: 2,
+            min_hz: 100.0,
+            max_hz: 450.0,
+            q: 3.0,
+            threshold: 4.0,
+            analysis_rate: 1000.0,
+            smoothing: 0.05,
+        }
+    }
+}
+
+pub struct DynamicNotch {
+    pub config: DynamicNotchConfig,
+    sample_rate: f32,
+    /// samples averaged per analyzed sample
+    decimation: usize,
+    /// sum of the samples being decimated
+    accumulator: [f32; 3],
+    accumulated: usize,
+    /// latest analyzed samples (ring, per axis)
+    buffer: [[f32; FFT_SIZE]; 3],
+    /// next position of the ring
+    index: usize,
+    /// analyzed samples since the previous analysis
+    pending: usize,
+    /// ring has been filled
+    filled: bool,
+    window: [f32; FFT_SIZE],
+    /// cos and sin of the roots of unity
+    twiddles: [(f32, f32); BINS],
+    /// tracked frequencies (Hz, None until a peak is found)
+    centers: [[Option<f32>; MAX_PEAKS]; 3],
+    notches: [[Biquad; MAX_PEAKS]; 3],
+}
+
+impl DynamicNotch {
+    pub fn new(config: DynamicNotchConfig, sample_rate: f32) -> Self {
+        let decimation = libm::roundf(sample_rate / config.analysis_rate).max(1.0) as usize;
+        DynamicNotch {
+            config: DynamicNotchConfig {
+                count: config.count.min(MAX_PEAKS),
+                ..config
+            },
+            sample_rate,
+            decimation,
+            accumulator: [0.0; 3],
+            accumulated: 0,
+            buffer: [[0.0; FFT_SIZE]; 3],
+            index: 0,
+            pending: 0,
+            filled: false,
+            window: core::array::from_fn(|i| {
+                0.5 - 0.5 * libm::cosf(2.0 * PI * i as f32 / FFT_SIZE as f32)
+            }),
+            twiddles: core::array::from_fn(|k| {
+                let angle = -2.0 * PI * k as f32 / FFT_SIZE as f32;
+                (libm::cosf(angle), libm::sinf(angle))
+            }),
+            centers: [[None; MAX_PEAKS]; 3],
+            notches: [[Biquad::passthrough(); MAX_PEAKS]; 3],
+        }
+    }
+
+    /// tracked frequencies of the axis (x, y, z)
+    pub fn centers(&self, axis: usize) -> &[Option<f32>] {
+        &self.centers[axis][..self.config.count]
+    }
+
+    pub fn apply(&mut self, input: &Vector3) -> Vector3 {
+        let mut values = [input.x, input.y, input.z];
+        self.decimate(&values);
+        for ((value, notches), centers) in values
+            .iter_mut()
+            .zip(self.notches.iter_mut())
+            .zip(&self.centers)
+        {
+            for (notch, _) in notches.iter_mut().zip(centers).filter(|(_, c)| c.is_some()) {
+                *value = notch.apply(*value);
+            }
+        }
+        Vector3::new(values[0], values[1], values[2])
+    }
+
+    /// rate of the analyzed samples (Hz)
+    fn analysis_rate(&self) -> f32 {
+        self.sample_rate / self.decimation as f32
+    }
+
+    fn decimate(&mut self, values: &[f32; 3]) {
+        for (sum, value) in self.accumulator.iter_mut().zip(values) {
+            *sum += value;
+        }
+        self.accumulated += 1;
+        if self.accumulated < self.decimation {
+            return;
+        }
+        for (buffer, sum) in self.buffer.iter_mut().zip(self.accumulator.iter_mut()) {
+            buffer[self.index] = *sum / self.decimation as f32;
+            *sum = 0.0;
+        }
+        self.accumulated = 0;
+        self.index = (self.index + 1) % FFT_SIZE;
+        self.filled |= self.index == 0;
+        self.pending += 1;
+        if self.filled && self.pending >= FFT_SIZE / 2 {
+            self.pending = 0;
+            for axis in 0..3 {
+                self.analyze(axis);
+            }
+        }
+    }
+
+    /// track the peaks of the spectrum of the axis
+    fn analyze(&mut self, axis: usize) {
+        let magnitudes = self.spectrum(axis);
+        let resolution = self.analysis_rate() / FFT_SIZE as f32;
+        // within the range (excluding DC and nyquist)
+        let first = (libm::ceilf(self.config.min_hz / resolution) as usize).max(1);
+        let last = (libm::floorf(self.config.max_hz / resolution) as usize).min(BINS - 1);
+        if last <= first + 1 {
+            return;
+        }
+
+        let mut sorted = [0.0f32; BINS];
+        let range = &mut sorted[..=last - first];
+        range.copy_from_slice(&magnitudes[first..=last]);
+        range.sort_unstable_by(f32::total_cmp);
+        let floor = range[range.len() / 2];
+
+        // strongest peaks (descending)
+        let mut peaks = [(0.0f32, 0.0f32); MAX_PEAKS];
+        let mut found = 0;
+        for k in first..=last {
+            let (previous, magnitude, next) = (magnitudes[k - 1], magnitudes[k], magnitudes[k + 1]);
+            if magnitude <= previous || magnitude < next {
+                continue;
+            }
+            if magnitude <= self.config.threshold * floor || magnitude <= f32::EPSILON {
+                continue;
+            }
+            // parabolic interpolation between the bins
+            let curvature = previous - 2.0 * magnitude + next;
+            let offset = if curvature < 0.0 {
+                0.5 * (previous - next) / curvature
+            } else {
+                0.0
+            };
+            let frequency = (k as f32 + offset) * resolution;
+            let peaks = &mut peaks[..self.config.count];
+            if let Some(position) = peaks.iter().position(|p| magnitude > p.0) {
+                peaks.copy_within(position..peaks.len() - 1, position + 1);
+                peaks[position] = (magnitude, frequency);
+                found = (found + 1).min(peaks.len());
+            }
+        }
+
+        let hop = (FFT_SIZE / 2) as f32 / self.analysis_rate();
+        let k = hop / (self.config.smoothing + hop);
+        let mut assigned = [false; MAX_PEAKS];
+        for (_, frequency) in peaks.iter().take(found) {
+            // the nearest tracked notch, else an idle notch
+            let slots = assigned.iter().zip(&self.centers[axis]).enumerate();
+            let Some((slot, _)) = slots
+                .take(self.config.count)
+                .filter(|(_, (assigned, _))| !**assigned)
+                .map(|(slot, (_, center))| {
+                    (slot, center.map_or(f32::MAX, |c| (c - frequency).abs()))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+            else {
+                break;
+            };
+            assigned[slot] = true;
+            let center = self.centers[axis][slot].get_or_insert(*frequency);
+            *center += (frequency - *center) * k;
+            let center = center.clamp(self.config.min_hz, 0.45 * self.sample_rate);
+            self.notches[axis][slot].set_notch(center, self.config.q, self.sample_rate);
+        }
+    }
+
+    /// magnitudes of the windowed samples of the axis (oldest first)
+    fn spectrum(&self, axis: usize) -> [f32; BINS + 1] {
+        let mut re: [f32; FFT_SIZE] = core::array::from_fn(|i| {
+            self.buffer[axis][(self.index + i) % FFT_SIZE] * self.window[i]
+        });
+        let mut im = [0.0f32; FFT_SIZE];
+        self.fft(&mut re, &mut im);
+        core::array::from_fn(|k| libm::sqrtf(re[k] * re[k] + im[k] * im[k]))
+    }
+
+    /// radix-2 decimation in time (in place)
+    fn fft(&self, re: &mut [f32; FFT_SIZE], im: &mut [f32; FFT_SIZE]) {
+        let bits = FFT_SIZE.trailing_zeros();
+        for i in 0..FFT_SIZE {
+            let j = i.reverse_bits() >> (usize::BITS - bits);
+            if i < j {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+        let mut size = 2;
+        while size <= FFT_SIZE {
+            let stride = FFT_SIZE / size;
+            for start in (0..FFT_SIZE).step_by(size) {
+                for k in 0..size / 2 {
+                    let (cos, sin) = self.twiddles[k * stride];
+                    let (even, odd) = (start + k, start + k + size / 2);
+                    let t_re = re[odd] * cos - im[odd] * sin;
+                    let t_im = re[odd] * sin + im[odd] * cos;
+                    re[odd] = re[even] - t_re;
+                    im[odd] = im[even] - t_im;
+                    re[even] += t_re;
+                    im[even] += t_im;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtering::iir::gain;
+
+    const SAMPLE_RATE: f32 = 4000.0;
+
+    /// maneuvering (low frequency) with the noise of the motors
+    fn gyro(i: usize, noise: &[(f32, f32)]) -> Vector3 {
+        let t = i as f32 / SAMPLE_RATE;
+        let noise: f32 = noise
+            .iter()
+            .map(|(frequency, amplitude)| amplitude * libm::sinf(2.0 * PI * frequency * t))
+            .sum();
+        Vector3::new(
+            20.0 * libm::sinf(2.0 * PI * 3.0 * t) + noise,
+            noise * 0.5,
+            -noise,
+        )
+    }
+
+    fn run(notch: &mut DynamicNotch, seconds: f32, noise: &[(f32, f32)]) {
+        for i in 0..(seconds * SAMPLE_RATE) as usize {
+            notch.apply(&gyro(i, noise));
+        }
+    }
+
+    #[test]
+    fn transforms() {
+        let notch = DynamicNotch::new(DynamicNotchConfig::default(), 1000.0);
+        let mut re: [f32; FFT_SIZE] =
+            core::array::from_fn(|i| libm::cosf(2.0 * PI * 5.0 * i as f32 / FFT_SIZE as f32));
+        let mut im = [0.0; FFT_SIZE];
+        notch.fft(&mut re, &mut im);
+        for (k, (re, im)) in re.iter().zip(&im).enumerate() {
+            let expected = if k == 5 || k == FFT_SIZE - 5 {
+                FFT_SIZE as f32 / 2.0
+            } else {
+                0.0
+            };
+            assert!((re - expected).abs() < 1e-3 && im.abs() < 1e-3, "bin {k}");
+        }
+    }
+
+    #[test]
+    fn tracks_noise_peaks() {
+        let mut notch = DynamicNotch::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+        assert_eq!(notch.centers(0), [None, None]);
+        run(&mut notch, 0.5, &[(180.0, 5.0), (330.0, 3.0)]);
+        for axis in 0..3 {
+            let mut centers = [
+                notch.centers(axis)[0].unwrap(),
+                notch.centers(axis)[1].unwrap(),
+            ];
+            centers.sort_unstable_by(f32::total_cmp);
+            assert!((centers[0] - 180.0).abs() < 5.0, "{centers:?}");
+            assert!((centers[1] - 330.0).abs() < 5.0, "{centers:?}");
+        }
+
+        // peaks follow the throttle
+        run(&mut notch, 0.5, &[(220.0, 5.0), (370.0, 3.0)]);
+        let mut centers = [notch.centers(1)[0].unwrap(), notch.centers(1)[1].unwrap()];
+        centers.sort_unstable_by(f32::total_cmp);
+        assert!((centers[0] - 220.0).abs() < 5.0, "{centers:?}");
+        assert!((centers[1] - 370.0).abs() < 5.0, "{centers:?}");
+    }
+
+    #[test]
+    fn rejects_tracked_noise() {
+        let mut notch = DynamicNotch::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+        run(&mut notch, 0.5, &[(250.0, 5.0)]);
+        // frozen (the response of the x axis to a sine, without tracking it)
+        notch.config.smoothing = f32::MAX;
+        let mut response = |frequency| {
+            gain(
+                |x| notch.apply(&Vector3::new(x, 0.0, 0.0)).x,
+                frequency,
+                SAMPLE_RATE,
+            )
+        };
+        assert!(response(250.0) < 0.05);
+        assert!(response(3.0) > 0.99);
+        assert!(response(40.0) > 0.95);
+    }
+
+    #[test]
+    fn ignores_noise_floor() {
+        let mut notch = DynamicNotch::new(DynamicNotchConfig::default(), SAMPLE_RATE);
+        // broadband noise (xorshift), without peaks
+        let mut state = 0x2545_f491u32;
+        for _ in 0..SAMPLE_RATE as usize {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let x = state as f32 / u32::MAX as f32 - 0.5;
+            notch.apply(&Vector3::new(x, x, x));
+        }
+        assert_eq!(notch.centers(0), [None, None]);
+    }
+}
@@ -0,0 +1,287 @@
+//! Gyro filter chain
+//!
+//! The raw gyro samples carry the vibration of the motors and frame, which
+//! the rate control (in particular its derivative) would otherwise amplify
+//! into the motors. Each (optional) stage, in order:
+//!     * RPM notches - the harmonics of the measured motor speeds (bypassed
+//!       until the speeds are received)
+//!     * static notch - a fixed resonance (i.e. of the frame)
+//!     * dynamic notch - the remaining peaks of the spectrum
+//!     * low-pass - broadband noise (PT1 or biquad), in up to two stages
+//!
+//! Stages whose frequencies aren't below the nyquist frequency of the sample
+//! rate are bypassed.
+
+use log::*;
+use rusty_robot_common::Vector3;
+
+use super::dynamic_notch::{DynamicNotch, DynamicNotchConfig};
+use super::iir::{BUTTERWORTH_Q, Biquad, Pt1};
+use super::rpm_notch::{RpmNotch, RpmNotchConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lowpass {
+    /// first order
+    Pt1 { cutoff: f32 },
+    /// second order (Butterworth)
+    Biquad { cutoff: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NotchConfig {
+    /// center frequency (Hz)
+    pub center: f32,
+    /// quality (center / bandwidth)
+    pub q: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GyroFilterConfig {
+    /// notches of the motor speeds (when the speeds are received)
+    pub rpm_notch: Option<RpmNotchConfig>,
+    pub notch: Option<NotchConfig>,
+    pub dynamic_notch: Option<DynamicNotchConfig>,
+    pub lowpass: Option<Lowpass>,
+    pub lowpass2: Option<Lowpass>,
+}
+impl Default for GyroFilterConfig {
+    fn default() -> Self {
+        GyroFilterConfig {
+            rpm_notch: Some(RpmNotchConfig::default()),
+            notch: None,
+            dynamic_notch: Some(DynamicNotchConfig::default()),
+            lowpass: Some(Lowpass::Pt1 { cutoff: 250.0 }),
+            lowpass2: None,
+        }
+    }
+}
+
+/// per axis low-pass
+enum LowpassStage {
+    Pt1([Pt1; 3]),
+    Biquad([Biquad; 3]),
+}
+impl LowpassStage {
+    fn new(lowpass: Lowpass, sample_rate: f32) -> Option<Self> {
+        let cutoff = match lowpass {
+            Lowpass::Pt1 { cutoff } | Lowpass::Biquad { cutoff } => cutoff,
+        };
+        if cutoff >= 0.5 * sample_rate {
+            warn!("{lowpass:?} bypassed [beyond nyquist]");
+            return None;
+        }
+        Some(match lowpass {
+            Lowpass::Pt1 { cutoff } => LowpassStage::Pt1([Pt1::new(cutoff, sample_rate); 3]),
+            Lowpass::Biquad { cutoff } => {
+                LowpassStage::Biquad([Biquad::lowpass(cutoff, BUTTERWORTH_Q, sample_rate); 3])
+            }
+        })
+    }
+
+    fn apply(&mut self, values: &mut [f32; 3]) {
+        match self {
+            LowpassStage::Pt1(filters) => {
+                for (value, filter) in values.iter_mut().zip(filters.iter_mut()) {
+                    *value = filter.apply(*value);
+                }
+            }
+            LowpassStage::Biquad(filters) => {
+                for (value, filter) in values.iter_mut().zip(filters.iter_mut()) {
+                    *value = filter.apply(*value);
+                }
+            }
+        }
+    }
+}
+
+/// chain of filters (per the configuration) of N motors
+pub struct GyroFilter<const N: usize> {
+    rpm_notch: Option<RpmNotch<N>>,
+    notch: Option<[Biquad; 3]>,
+    dynamic_notch: Option<DynamicNotch>,
+    lowpass: Option<LowpassStage>,
+    lowpass2: Option<LowpassStage>,
+}
+
+impl<const N: usize> GyroFilter<N> {
+    /// filters of the samples at the sample rate (Hz)
+    pub fn new(config: GyroFilterConfig, sample_rate: f32) -> Self {
+        let nyquist = 0.5 * sample_rate;
+        GyroFilter {
+            rpm_notch: config
+                .rpm_notch
+                .map(|config| RpmNotch::new(config, sample_rate)),
+            notch: config.notch.and_then(|notch| {
+                if notch.center >= nyquist {
+                    warn!("{notch:?} bypassed [beyond nyquist]");
+                    return None;
+                }
+                Some([Biquad::notch(notch.center, notch.q, sample_rate); 3])
+            }),
+            dynamic_notch: config.dynamic_notch.and_then(|config| {
+                if config.min_hz >= nyquist {
+                    warn!("dynamic notch bypassed [beyond nyquist]");
+                    return None;
+                }
+                Some(DynamicNotch::new(config, sample_rate))
+            }),
+            lowpass: config
+                .lowpass
+                .and_then(|lowpass| LowpassStage::new(lowpass, sample_rate)),
+            lowpass2: config
+                .lowpass2
+                .and_then(|lowpass| LowpassStage::new(lowpass, sample_rate)),
+        }
+    }
+
+    /// retune the RPM notches per the motor speeds (RPM)
+    pub fn rpm(&mut self, rpm: &[f32; N]) {
+        if let Some(rpm_notch) = self.rpm_notch.as_mut() {
+            rpm_notch.update(rpm);
+        }
+    }
+
+    /// the RPM notches (i.e. for their centers)
+    pub fn rpm_notch(&self) -> Option<&RpmNotch<N>> {
+        self.rpm_notch.as_ref()
+    }
+
+    /// the dynamic notch (i.e. for the tracked frequencies)
+    pub fn dynamic_notch(&self) -> Option<&DynamicNotch> {
+        self.dynamic_notch.as_ref()
+    }
+
+    /// filtered sample (deg/s)
+    pub fn apply(&mut self, gyro: &Vector3) -> Vector3 {
+        let mut gyro = *gyro;
+        if let Some(rpm_notch) = self.rpm_notch.as_mut() {
+            gyro = rpm_notch.apply(&gyro);
+        }
+        let mut values = [gyro.x, gyro.y, gyro.z];
+        if let Some(notch) = self.notch.as_mut() {
+            for (value, filter) in values.iter_mut().zip(notch.iter_mut()) {
+                *value = filter.apply(*value);
+            }
+        }
+        if let Some(dynamic_notch) = self.dynamic_notch.as_mut() {
+            let filtered = dynamic_notch.apply(&Vector3::new(values[0], values[1], values[2]));
+            values = [filtered.x, filtered.y, filtered.z];
+        }
+        for stage in [self.lowpass.as_mut(), self.lowpass2.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            stage.apply(&mut values);
+        }
+        Vector3::new(values[0], values[1], values[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtering::iir::gain;
+    use core::f32::consts::PI;
+
+    const SAMPLE_RATE: f32 = 4000.0;
+
+    fn unfiltered() -> GyroFilterConfig {
+        GyroFilterConfig {
+            rpm_notch: None,
+            notch: None,
+            dynamic_notch: None,
+            lowpass: None,
+            lowpass2: None,
+        }
+    }
+
+    fn response(filter: &mut GyroFilter<4>, frequency: f32) -> f32 {
+        gain(
+            |x| filter.apply(&Vector3::new(0.0, 0.0, x)).z,
+            frequency,
+            SAMPLE_RATE,
+        )
+    }
+
+    #[test]
+    fn chains_stages() {
+        let mut filter = GyroFilter::<4>::new(unfiltered(), SAMPLE_RATE);
+        let gyro = Vector3::new(1.0, -2.0, 3.0);
+        assert_eq!(filter.apply(&gyro), gyro);
+
+        let config = GyroFilterConfig {
+            notch: Some(NotchConfig {
+                center: 150.0,
+                q: 4.0,
+            }),
+            lowpass: Some(Lowpass::Pt1 { cutoff: 300.0 }),
+            lowpass2: Some(Lowpass::Biquad { cutoff: 500.0 }),
+            ..unfiltered()
+        };
+        let mut filter = GyroFilter::<4>::new(config, SAMPLE_RATE);
+        assert!(response(&mut filter, 5.0) > 0.99);
+        assert!(response(&mut filter, 150.0) < 0.01);
+        // both low-pass stages (-60dB/decade)
+        let high = response(&mut filter, 1500.0);
+        assert!(high < 0.05, "{high}");
+        // the RPM notches once the speeds are received
+        let config = GyroFilterConfig {
+            rpm_notch: Some(RpmNotchConfig::default()),
+            ..config
+        };
+        let mut filter = GyroFilter::<4>::new(config, SAMPLE_RATE);
+        let passed = response(&mut filter, 220.0);
+        filter.rpm(&[13_200.0; 4]);
+        assert!(response(&mut filter, 220.0) < 0.01 * passed);
+    }
+
+    #[test]
+    fn bypasses_beyond_nyquist() {
+        let config = GyroFilterConfig {
+            notch: Some(NotchConfig {
+                center: 600.0,
+                q: 4.0,
+            }),
+            lowpass: Some(Lowpass::Biquad { cutoff: 800.0 }),
+            ..GyroFilterConfig::default()
+        };
+        // i.e. per a slow IMU
+        let mut filter = GyroFilter::<4>::new(config, 1000.0);
+        assert!(filter.notch.is_none() && filter.lowpass.is_none());
+        assert!(filter.dynamic_notch().is_some());
+        let gyro = Vector3::new(1.0, -2.0, 3.0);
+        assert_eq!(filter.apply(&gyro), gyro);
+    }
+
+    #[test]
+    fn attenuates_motor_noise() {
+        let mut filter = GyroFilter::<4>::new(GyroFilterConfig::default(), SAMPLE_RATE);
+        // vibration of the motors (with telemetry) and of the frame
+        let rpm = [12_000.0, 12_300.0, 12_600.0, 12_900.0];
+        filter.rpm(&rpm);
+        let noise = |t: f32| {
+            let motors: f32 = rpm
+                .iter()
+                .map(|rpm| 10.0 * libm::sinf(2.0 * PI * rpm / 60.0 * t))
+                .sum();
+            motors + 30.0 * libm::sinf(2.0 * PI * 310.0 * t)
+        };
+        let (mut residual, mut raw) = (0.0, 0.0);
+        for i in 0..2 * SAMPLE_RATE as usize {
+            let t = i as f32 / SAMPLE_RATE;
+            let filtered = filter.apply(&Vector3::new(noise(t), 0.0, 0.0));
+            // once the dynamic notch has converged
+            if t > 1.0 {
+                residual += filtered.x * filtered.x;
+                raw += noise(t) * noise(t);
+            }
+        }
+        let attenuation = libm::sqrtf(residual / raw);
+        assert!(attenuation < 0.05, "{attenuation}");
+        let centers = filter.dynamic_notch().unwrap().centers(0);
+        assert!(
+            centers.iter().flatten().any(|c| (c - 310.0).abs() < 5.0),
+            "{centers:?}"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! IIR filters
+//!     * [PT1] - first order low-pass (-20dB/decade)
+//!     * [Biquad] - second order section (transposed direct form II), as a
+//!       low-pass (-40dB/decade) or a notch (per the [RBJ cookbook])
+//!
+//! Frequencies are in Hz, at the sample rate of the filtered signal.
+//!
+//! [PT1]: https://en.wikipedia.org/wiki/Low-pass_filter#Discrete-time_realization
+//! [Biquad]: https://en.wikipedia.org/wiki/Digital_biquad_filter
+//! [RBJ cookbook]: https://webaudio.github.io/Audio-EQ-Cookbook/audio-eq-cookbook.html
+
+use core::f32::consts::PI;
+
+/// quality of the Butterworth (maximally flat) low-pass
+pub const BUTTERWORTH_Q: f32 = core::f32::consts::FRAC_1_SQRT_2;
+
+/// first order low-pass
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pt1 {
+    /// fraction of the error applied per sample
+    k: f32,
+    state: f32,
+}
+
+impl Pt1 {
+    pub fn new(cutoff: f32, sample_rate: f32) -> Self {
+        let dt = 1.0 / sample_rate;
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        Pt1 {
+            k: dt / (rc + dt),
+            state: 0.0,
+        }
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        self.state += (input - self.state) * self.k;
+        self.state
+    }
+}
+
+/// second order section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Biquad {
+    pub fn lowpass(cutoff: f32, q: f32, sample_rate: f32) -> Self {
+        let mut biquad = Self::passthrough();
+        biquad.set_lowpass(cutoff, q, sample_rate);
+        biquad
+    }
+
+    /// rejection of the band about the center (bandwidth = center / q)
+    pub fn notch(center: f32, q: f32, sample_rate: f32) -> Self {
+        let mut biquad = Self::passthrough();
+        biquad.set_notch(center, q, sample_rate);
+        biquad
+    }
+
+    /// no filtering (unity gain)
+    pub fn passthrough() -> Self {
+        Biquad {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    /// change of the cutoff (retaining the state)
+    pub fn set_lowpass(&mut self, cutoff: f32, q: f32, sample_rate: f32) {
+        let omega = 2.0 * PI * cutoff / sample_rate;
+        let (cos, alpha) = (libm::cosf(omega), libm::sinf(omega) / (2.0 * q));
+        let b0 = (1.0 - cos) / 2.0;
+        self.set(b0, 1.0 - cos, b0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+    }
+
+    /// change of the center (retaining the state, i.e. tracking a peak)
+    pub fn set_notch(&mut self, center: f32, q: f32, sample_rate: f32) {
+        let cos = libm::cosf(2.0 * PI * center / sample_rate);
+        // bandwidth (-3dB) of the digital filter, rather than of the (warped)
+        //     analog prototype
+        let alpha = libm::tanf(PI * center / q / sample_rate);
+        self.set(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha);
+    }
+
+    pub fn apply(&mut self, input: f32) -> f32 {
+        let output = self.b0 * input + self.s1;
+        self.s1 = self.b1 * input - self.a1 * output + self.s2;
+        self.s2 = self.b2 * input - self.a2 * output;
+        output
+    }
+
+    /// coefficients normalized by a0
+    fn set(&mut self, b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) {
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+}
+
+/// gain of the filter at the frequency (per the steady response to a sine)
+#[cfg(test)]
+pub(crate) fn gain(mut filter: impl FnMut(f32) -> f32, frequency: f32, sample_rate: f32) -> f32 {
+    let samples = sample_rate as usize;
+    let sine = |i: usize| libm::sinf(2.0 * PI * frequency * i as f32 / sample_rate);
+    // settle, then the RMS of a second
+    for i in 0..samples {
+        filter(sine(i));
+    }
+    let power: f32 = (samples..2 * samples)
+        .map(|i| {
+            let output = filter(sine(i));
+            output * output
+        })
+        .sum();
+    libm::sqrtf(2.0 * power / samples as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 1000.0;
+
+    #[test]
+    fn pt1_response() {
+        let response = |frequency| {
+            let mut pt1 = Pt1::new(50.0, SAMPLE_RATE);
+            gain(|x| pt1.apply(x), frequency, SAMPLE_RATE)
+        };
+        assert!(response(2.0) > 0.99);
+        // -3dB at the cutoff (approximately, per the discretization)
+        let cutoff = response(50.0);
+        assert!((cutoff - BUTTERWORTH_Q).abs() < 0.06, "{cutoff}");
+        // -20dB/decade
+        let decade = response(400.0);
+        assert!(decade < 0.15, "{decade}");
+    }
+
+    #[test]
+    fn biquad_lowpass_response() {
+        let response = |frequency| {
+            let mut biquad = Biquad::lowpass(100.0, BUTTERWORTH_Q, SAMPLE_RATE);
+            gain(|x| biquad.apply(x), frequency, SAMPLE_RATE)
+        };
+        assert!((response(5.0) - 1.0).abs() < 0.01);
+        // maximally flat
+        assert!(response(50.0) > 0.95);
+        let cutoff = response(100.0);
+        assert!((cutoff - BUTTERWORTH_Q).abs() < 0.01, "{cutoff}");
+        // -40dB/decade (steeper as warped toward nyquist)
+        let octaves = response(300.0);
+        assert!(octaves < 1.0 / 9.0, "{octaves}");
+    }
+
+    #[test]
+    fn notch_response() {
+        let (center, q) = (200.0, 4.0);
+        let response = |frequency| {
+            let mut notch = Biquad::notch(center, q, SAMPLE_RATE);
+            gain(|x| notch.apply(x), frequency, SAMPLE_RATE)
+        };
+        assert!(response(center) < 0.01);
+        // -3dB at the edges of the band
+        let bandwidth = center / q;
+        for edge in [center - bandwidth / 2.0, center + bandwidth / 2.0] {
+            let gain = response(edge);
+            assert!((gain - BUTTERWORTH_Q).abs() < 0.05, "{gain} at {edge}Hz");
+        }
+        // passes beyond the band
+        assert!(response(center - 2.0 * bandwidth) > 0.95);
+        assert!(response(center + 2.0 * bandwidth) > 0.95);
+        assert!((response(5.0) - 1.0).abs() < 0.01);
+
+        // retuned (while filtering)
+        let mut notch = Biquad::notch(center, q, SAMPLE_RATE);
+        gain(|x| notch.apply(x), 300.0, SAMPLE_RATE);
+        notch.set_notch(300.0, q, SAMPLE_RATE);
+        assert!(gain(|x| notch.apply(x), 300.0, SAMPLE_RATE) < 0.01);
+    }
+}
@@ -0,0 +1,8 @@
+// provide low-pass and notch filters (PT1, biquad)
+pub mod iir;
+// provide notches tracking the peaks of the spectrum (FFT)
+pub mod dynamic_notch;
+// provide notches at the harmonics of the motor speeds
+pub mod rpm_notch;
+// provide the configurable chain of gyro filters
+pub mod gyro;
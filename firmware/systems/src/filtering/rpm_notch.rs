@@ -0,0 +1,152 @@
+//! RPM notches
+//!
+//! The vibration of each motor (imbalance of the rotor and propeller) is at
+//! the rotation frequency and its harmonics. Per the motor speeds (i.e. the
+//! bidirectional DShot telemetry), a notch is centered on each harmonic of each
+//! motor - narrow, as the frequencies are measured rather than estimated.
+//!
+//! Notches below the minimum frequency (i.e. idle) are bypassed, as are those
+//! beyond the nyquist frequency.
+
+use rusty_robot_common::Vector3;
+
+use super::iir::Biquad;
+
+/// capacity of the harmonics (per motor)
+pub const MAX_HARMONICS: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RpmNotchConfig {
+    /// harmonics per motor (up to MAX_HARMONICS)
+    pub harmonics: usize,
+    /// quality (center / bandwidth) of the notches
+    pub q: f32,
+    /// frequency of the lowest notch (Hz)
+    pub min_hz: f32,
+}
+impl Default for RpmNotchConfig {
+    fn default() -> Self {
+        RpmNotchConfig {
+            harmonics: 3,
+            q: 5.0,
+            min_hz: 80.0,
+        }
+    }
+}
+
+pub struct RpmNotch<const N: usize> {
+    pub config: RpmNotchConfig,
+    sample_rate: f32,
+    /// per motor, per harmonic, per axis
+    notches: [[[Biquad; 3]; MAX_HARMONICS]; N],
+    /// centers (Hz) of the notches, None while bypassed
+    centers: [[Option<f32>; MAX_HARMONICS]; N],
+}
+
+impl<const N: usize> RpmNotch<N> {
+    pub fn new(config: RpmNotchConfig, sample_rate: f32) -> Self {
+        RpmNotch {
+            config: RpmNotchConfig {
+                harmonics: config.harmonics.min(MAX_HARMONICS),
+                ..config
+            },
+            sample_rate,
+            notches: [[[Biquad::passthrough(); 3]; MAX_HARMONICS]; N],
+            centers: [[None; MAX_HARMONICS]; N],
+        }
+    }
+
+    /// centers (Hz) of the harmonics of the motor
+    pub fn centers(&self, motor: usize) -> &[Option<f32>] {
+        &self.centers[motor][..self.config.harmonics]
+    }
+
+    /// retune per the motor speeds (RPM)
+    pub fn update(&mut self, rpm: &[f32; N]) {
+        let (config, sample_rate) = (self.config, self.sample_rate);
+        for ((rpm, notches), centers) in rpm
+            .iter()
+            .zip(self.notches.iter_mut())
+            .zip(self.centers.iter_mut())
+        {
+            let fundamental = rpm / 60.0;
+            for (harmonic, (axes, center)) in notches.iter_mut().zip(centers.iter_mut()).enumerate()
+            {
+                let frequency = fundamental * (harmonic + 1) as f32;
+                let enabled = harmonic < config.harmonics
+                    && frequency >= config.min_hz
+                    && frequency < 0.45 * sample_rate;
+                *center = enabled.then_some(frequency);
+                if enabled {
+                    for notch in axes.iter_mut() {
+                        notch.set_notch(frequency, config.q, sample_rate);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn apply(&mut self, input: &Vector3) -> Vector3 {
+        let mut values = [input.x, input.y, input.z];
+        for (notches, centers) in self.notches.iter_mut().zip(&self.centers) {
+            for (axes, _) in notches.iter_mut().zip(centers).filter(|(_, c)| c.is_some()) {
+                for (value, notch) in values.iter_mut().zip(axes.iter_mut()) {
+                    *value = notch.apply(*value);
+                }
+            }
+        }
+        Vector3::new(values[0], values[1], values[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtering::iir::gain;
+
+    const SAMPLE_RATE: f32 = 2000.0;
+
+    fn response(notch: &mut RpmNotch<4>, frequency: f32) -> f32 {
+        gain(
+            |x| notch.apply(&Vector3::new(0.0, x, 0.0)).y,
+            frequency,
+            SAMPLE_RATE,
+        )
+    }
+
+    #[test]
+    fn notches_harmonics() {
+        let mut notch = RpmNotch::<4>::new(RpmNotchConfig::default(), SAMPLE_RATE);
+        // motors at 180, 190, 200 and 210 Hz
+        notch.update(&[10_800.0, 11_400.0, 12_000.0, 12_600.0]);
+        assert_eq!(notch.centers(2), [Some(200.0), Some(400.0), Some(600.0)]);
+        for frequency in [180.0, 190.0, 200.0, 210.0, 400.0, 600.0, 630.0] {
+            let gain = response(&mut notch, frequency);
+            assert!(gain < 0.01, "{gain} at {frequency}Hz");
+        }
+        // passes the maneuvering (and between the harmonics)
+        assert!(response(&mut notch, 5.0) > 0.99);
+        assert!(response(&mut notch, 40.0) > 0.95);
+        assert!(response(&mut notch, 300.0) > 0.5);
+    }
+
+    #[test]
+    fn bypasses_out_of_range() {
+        let config = RpmNotchConfig {
+            harmonics: 2,
+            ..Default::default()
+        };
+        let mut notch = RpmNotch::<4>::new(config, SAMPLE_RATE);
+        // idle (60 Hz, below the minimum) and beyond nyquist (second harmonic)
+        notch.update(&[3600.0, 3600.0, 30_000.0, 30_000.0]);
+        assert_eq!(notch.centers(0), [None, Some(120.0)]);
+        assert_eq!(notch.centers(2), [Some(500.0), None]);
+        assert!(response(&mut notch, 60.0) > 0.9);
+        assert!(response(&mut notch, 120.0) < 0.01);
+
+        // stopped
+        notch.update(&[0.0; 4]);
+        assert_eq!(notch.centers(0), [None, None]);
+        assert!((response(&mut notch, 120.0) - 1.0).abs() < 0.01);
+    }
+}
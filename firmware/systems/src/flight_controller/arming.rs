@@ -0,0 +1,274 @@
+//! Arming (pre-flight health gating)
+//!
+//! The motors are only enabled (Launch) once armed by an explicit command,
+//! which is refused unless the vehicle is ready for flight:
+//!     * IMU - sampling and calibrated (at rest, the corrected gyro reads no
+//!       rotation)
+//!     * level - tilt within the limit
+//!     * GPS - a fix (autonomous modes)
+//!     * battery - cell voltage above the minimum (when monitored)
+//!     * link - RC/mesh messages received
+//!     * throttle - low (when under RC)
+//!
+//! Each failed check is reported (logs/telemetry), rather than only the first.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArmingConfig {
+    /// limit of the tilt (degrees)
+    pub max_tilt: f32,
+    /// limit of the corrected rotation at rest (deg/s)
+    pub max_rate: f32,
+    /// minimum voltage (per cell)
+    pub min_cell_voltage: f32,
+    /// limit of the low throttle (0.0 - 1.0)
+    pub max_throttle: f32,
+    /// an RC/mesh link is required
+    pub require_link: bool,
+}
+impl Default for ArmingConfig {
+    fn default() -> Self {
+        ArmingConfig {
+            max_tilt: 15.0,
+            max_rate: 3.0,
+            min_cell_voltage: 3.7,
+            max_throttle: 0.05,
+            require_link: true,
+        }
+    }
+}
+
+/// health of the vehicle evaluated by the pre-arm checks
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Readiness {
+    /// IMU samples are received
+    pub imu_healthy: bool,
+    /// gyro bias is calibrated (per the correction layer)
+    pub calibrated: bool,
+    /// corrected rotation (deg/s)
+    pub rate: f32,
+    /// tilt from level (degrees)
+    pub tilt: f32,
+    pub gps_fix: bool,
+    /// voltage per cell (None without a battery monitor)
+    pub cell_voltage: Option<f32>,
+    /// RC/mesh link is established
+    pub link: bool,
+    /// RC throttle (None without RC)
+    pub throttle: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Refusal {
+    ImuUnhealthy,
+    NotCalibrated,
+    NotLevel,
+    NoGpsFix,
+    LowBattery,
+    NoLink,
+    ThrottleHigh,
+}
+impl Refusal {
+    const ALL: [Refusal; 7] = [
+        Refusal::ImuUnhealthy,
+        Refusal::NotCalibrated,
+        Refusal::NotLevel,
+        Refusal::NoGpsFix,
+        Refusal::LowBattery,
+        Refusal::NoLink,
+        Refusal::ThrottleHigh,
+    ];
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Refusal::ImuUnhealthy => "imu unhealthy",
+            Refusal::NotCalibrated => "imu not calibrated",
+            Refusal::NotLevel => "not level",
+            Refusal::NoGpsFix => "no gps fix",
+            Refusal::LowBattery => "low battery",
+            Refusal::NoLink => "no link",
+            Refusal::ThrottleHigh => "throttle not low",
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+/// set of failed pre-arm checks (i.e. for telemetry)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Refusals(u8);
+impl Refusals {
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, refusal: Refusal) -> bool {
+        self.0 & refusal.bit() != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Refusal> + '_ {
+        Refusal::ALL.into_iter().filter(|r| self.contains(*r))
+    }
+
+    /// compact encoding (bit per refusal)
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    fn insert(&mut self, refusal: Refusal) {
+        self.0 |= refusal.bit();
+    }
+}
+
+pub struct Arming {
+    pub config: ArmingConfig,
+    armed: bool,
+    /// failed checks of the latest arm command
+    refusals: Refusals,
+}
+
+impl Arming {
+    pub fn new(config: ArmingConfig) -> Self {
+        Arming {
+            config,
+            armed: false,
+            refusals: Refusals::default(),
+        }
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.armed
+    }
+
+    /// failed checks of the latest arm command
+    pub fn refusals(&self) -> Refusals {
+        self.refusals
+    }
+
+    /// the failed checks of the readiness (GPS required for autonomous modes)
+    pub fn check(&self, readiness: &Readiness, autonomous: bool) -> Refusals {
+        let config = &self.config;
+        let mut refusals = Refusals::default();
+        if !readiness.imu_healthy {
+            refusals.insert(Refusal::ImuUnhealthy);
+        } else {
+            if !readiness.calibrated || readiness.rate > config.max_rate {
+                refusals.insert(Refusal::NotCalibrated);
+            }
+            if readiness.tilt > config.max_tilt {
+                refusals.insert(Refusal::NotLevel);
+            }
+        }
+        if autonomous && !readiness.gps_fix {
+            refusals.insert(Refusal::NoGpsFix);
+        }
+        if readiness
+            .cell_voltage
+            .is_some_and(|voltage| voltage < config.min_cell_voltage)
+        {
+            refusals.insert(Refusal::LowBattery);
+        }
+        if config.require_link && !readiness.link {
+            refusals.insert(Refusal::NoLink);
+        }
+        if readiness
+            .throttle
+            .is_some_and(|throttle| throttle > config.max_throttle)
+        {
+            refusals.insert(Refusal::ThrottleHigh);
+        }
+        refusals
+    }
+
+    /// arm, unless refused by the pre-arm checks
+    pub fn arm(&mut self, readiness: &Readiness, autonomous: bool) -> Result<(), Refusals> {
+        self.refusals = self.check(readiness, autonomous);
+        if !self.refusals.is_empty() {
+            return Err(self.refusals);
+        }
+        self.armed = true;
+        Ok(())
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = false;
+    }
+
+    /// armed per the flight resumed across a reset (the checks were passed
+    ///     upon arming, prior to the reset)
+    pub fn resume(&mut self) {
+        self.armed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ready() -> Readiness {
+        Readiness {
+            imu_healthy: true,
+            calibrated: true,
+            rate: 0.5,
+            tilt: 2.0,
+            gps_fix: true,
+            cell_voltage: Some(4.1),
+            link: true,
+            throttle: Some(0.0),
+        }
+    }
+
+    #[test]
+    fn arms_when_ready() {
+        let mut arming = Arming::new(ArmingConfig::default());
+        assert!(!arming.is_armed());
+        assert_eq!(arming.arm(&ready(), true), Ok(()));
+        assert!(arming.is_armed());
+        arming.disarm();
+        assert!(!arming.is_armed());
+
+        // without a battery monitor or RC
+        let readiness = Readiness {
+            cell_voltage: None,
+            throttle: None,
+            ..ready()
+        };
+        assert_eq!(arming.arm(&readiness, true), Ok(()));
+    }
+
+    #[test]
+    fn reports_each_refusal() {
+        let mut arming = Arming::new(ArmingConfig::default());
+        let readiness = Readiness {
+            tilt: 30.0,
+            gps_fix: false,
+            throttle: Some(0.4),
+            ..ready()
+        };
+        // GPS only required for autonomous modes
+        let refusals = arming.arm(&readiness, false).unwrap_err();
+        assert_eq!(
+            refusals.bits(),
+            Refusal::NotLevel.bit() | Refusal::ThrottleHigh.bit()
+        );
+        assert_eq!(refusals.iter().next(), Some(Refusal::NotLevel));
+        let refusals = arming.arm(&readiness, true).unwrap_err();
+        assert!(refusals.contains(Refusal::NoGpsFix));
+        assert_eq!(arming.refusals(), refusals);
+        assert!(!arming.is_armed());
+
+        let readiness = Readiness {
+            imu_healthy: false,
+            cell_voltage: Some(3.5),
+            link: false,
+            ..ready()
+        };
+        let refusals = arming.arm(&readiness, true).unwrap_err();
+        assert_eq!(
+            refusals.bits(),
+            Refusal::ImuUnhealthy.bit() | Refusal::LowBattery.bit() | Refusal::NoLink.bit()
+        );
+        assert_eq!(refusals.iter().count(), 3);
+    }
+}
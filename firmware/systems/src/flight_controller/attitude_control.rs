@@ -0,0 +1,200 @@
+//! Attitude control
+//!
+//! Converts an attitude setpoint into body rate setpoints (P controller of the
+//! quaternion error). Tilt (roll/pitch) corrections are prioritized over yaw,
+//! as tilt determines the direction of thrust - per
+//! [Nonlinear Quadrocopter Attitude Control](https://www.research-collection.ethz.ch/handle/20.500.11850/154099)
+//! as used by PX4.
+
+use rusty_robot_common::{Quaternion, Vector3};
+
+/// down (body z) of the frame
+const DOWN: Vector3 = Vector3::new(0.0, 0.0, 1.0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct AttitudeSetpoint {
+    /// body (FRD) to world (NED)
+    pub attitude: Quaternion,
+    /// feedforward of the yaw rate (deg/s, positive clockwise from above)
+    pub yaw_rate: f32,
+    /// normalized collective thrust (0.0 - 1.0)
+    pub thrust: f32,
+}
+impl AttitudeSetpoint {
+    /// level at the heading (radians)
+    pub fn level(yaw: f32, thrust: f32) -> Self {
+        AttitudeSetpoint {
+            attitude: Vector3::new(0.0, 0.0, yaw).euler_to_quaternion(),
+            yaw_rate: 0.0,
+            thrust,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttitudeGains {
+    /// rate (1/s) per error (radians)
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    /// fraction (0.0 - 1.0) of the yaw error corrected with the tilt
+    pub yaw_weight: f32,
+    /// limits of the rate setpoints (deg/s)
+    pub max_rate: Vector3,
+}
+impl Default for AttitudeGains {
+    fn default() -> Self {
+        AttitudeGains {
+            roll: 6.5,
+            pitch: 6.5,
+            yaw: 2.8,
+            yaw_weight: 0.4,
+            max_rate: Vector3::new(220.0, 220.0, 200.0),
+        }
+    }
+}
+
+pub struct AttitudeController {
+    pub gains: AttitudeGains,
+}
+
+/// shortest rotation of `from` onto `to` (unit vectors)
+pub(crate) fn rotation_between(from: &Vector3, to: &Vector3) -> Quaternion {
+    let axis = from.cross(to);
+    Quaternion {
+        w: 1.0 + from.dot(to),
+        x: axis.x,
+        y: axis.y,
+        z: axis.z,
+    }
+    .normalized()
+}
+
+/// equivalent quaternion with a positive scalar (the shorter rotation)
+fn canonical(q: Quaternion) -> Quaternion {
+    if q.w < 0.0 { q * -1.0 } else { q }
+}
+
+impl AttitudeController {
+    pub fn new(gains: AttitudeGains) -> Self {
+        AttitudeController { gains }
+    }
+
+    /// body rate setpoints (deg/s) toward the setpoint
+    pub fn update(&self, attitude: &Quaternion, setpoint: &AttitudeSetpoint) -> Vector3 {
+        let gains = &self.gains;
+        let q = attitude.normalized();
+        let qd = setpoint.attitude.normalized();
+
+        // reduced setpoint - corrects tilt only
+        let thrust_axis = q.rotate_vector(DOWN, false);
+        let thrust_axis_d = qd.rotate_vector(DOWN, false);
+        let tilt = rotation_between(&thrust_axis, &thrust_axis_d);
+        let qd_reduced = if tilt.x.abs() > 1.0 - 1e-5 || tilt.y.abs() > 1.0 - 1e-5 {
+            // upside down - the tilt correction is ambiguous, so use the full setpoint
+            qd
+        } else {
+            tilt * q
+        };
+
+        // mix in the yaw (about the thrust axis) per the yaw weight
+        let yaw = canonical(qd_reduced.conjugate() * qd);
+        let w = libm::acosf(yaw.w.clamp(-1.0, 1.0)) * gains.yaw_weight;
+        let z = libm::asinf(yaw.z.clamp(-1.0, 1.0)) * gains.yaw_weight;
+        let qd = qd_reduced
+            * Quaternion {
+                w: libm::cosf(w),
+                x: 0.0,
+                y: 0.0,
+                z: libm::sinf(z),
+            };
+
+        // error as a rotation vector (body frame)
+        let error = canonical(q.conjugate() * qd);
+        let mut rate = Vector3::new(
+            2.0 * error.x * gains.roll,
+            2.0 * error.y * gains.pitch,
+            2.0 * error.z * gains.yaw,
+        ) * (180.0 / core::f32::consts::PI);
+
+        // yaw rate feedforward (about world down) in the body frame
+        rate += q.rotate_vector(DOWN, true) * setpoint.yaw_rate;
+
+        Vector3::new(
+            rate.x.clamp(-gains.max_rate.x, gains.max_rate.x),
+            rate.y.clamp(-gains.max_rate.y, gains.max_rate.y),
+            rate.z.clamp(-gains.max_rate.z, gains.max_rate.z),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setpoint(roll: f32, pitch: f32, yaw: f32) -> AttitudeSetpoint {
+        AttitudeSetpoint {
+            attitude: Vector3::new(roll, pitch, yaw).euler_to_quaternion(),
+            yaw_rate: 0.0,
+            thrust: 0.5,
+        }
+    }
+
+    #[test]
+    fn rates_toward_setpoint() {
+        let controller = AttitudeController::new(AttitudeGains::default());
+        let rate = controller.update(&Quaternion::IDENTITY, &setpoint(0.0, 0.0, 0.0));
+        assert!(rate.norm() < 1e-3);
+
+        let rate = controller.update(&Quaternion::IDENTITY, &setpoint(0.1, -0.05, 0.0));
+        assert!(
+            (rate.x - (0.1 * 6.5_f32).to_degrees()).abs() < 0.5,
+            "{rate:?}"
+        );
+        assert!(
+            (rate.y - (-0.05 * 6.5_f32).to_degrees()).abs() < 0.5,
+            "{rate:?}"
+        );
+    }
+
+    #[test]
+    fn prioritizes_tilt_over_yaw() {
+        let controller = AttitudeController::new(AttitudeGains {
+            max_rate: Vector3::new(1000.0, 1000.0, 1000.0),
+            ..Default::default()
+        });
+        let rate = controller.update(&Quaternion::IDENTITY, &setpoint(0.2, 0.0, 1.0));
+        // tilt fully corrected (about the roll axis of the setpoint), yaw per weight
+        let tilt = libm::sqrtf(rate.x * rate.x + rate.y * rate.y);
+        assert!((tilt - (0.2 * 6.5_f32).to_degrees()).abs() < 1.0, "{rate:?}");
+        let yaw = (0.4 * 1.0 * 2.8_f32).to_degrees();
+        assert!((rate.z - yaw).abs() < 3.0, "{rate:?}");
+    }
+
+    #[test]
+    fn limits_rates() {
+        let controller = AttitudeController::new(AttitudeGains {
+            yaw_weight: 1.0,
+            ..Default::default()
+        });
+        let rate = controller.update(&Quaternion::IDENTITY, &setpoint(1.2, 0.0, 0.0));
+        assert_eq!(rate.x, 220.0);
+        let rate = controller.update(&Quaternion::IDENTITY, &setpoint(0.0, 0.0, -3.0));
+        assert_eq!(rate.z, -200.0);
+    }
+
+    #[test]
+    fn converges_with_ideal_rates() {
+        let controller = AttitudeController::new(AttitudeGains::default());
+        let target = setpoint(-0.3, 0.4, 2.5);
+        let mut q = Vector3::new(0.2, 0.0, -1.0).euler_to_quaternion();
+        let dt = 0.001;
+        // yaw converges slower (weighted)
+        for _ in 0..6000 {
+            let rate = controller.update(&q, &target) * (core::f32::consts::PI / 180.0);
+            q = (q * Quaternion::from_rotation_vector(&(rate * dt))).normalized();
+        }
+        let error = q.angle_to(&target.attitude).to_degrees();
+        assert!(error < 0.5, "error {error} deg");
+    }
+}
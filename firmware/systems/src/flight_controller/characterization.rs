@@ -0,0 +1,421 @@
+//! Actuation self-characterization
+//!
+//! Continuously characterizes the impact of the actuation (motors/propellers)
+//! on the vehicle, so that the system can determine when it can no longer
+//! meet its intents (i.e. a damaged propeller or a payload beyond its
+//! capability).
+//!
+//! Per motor, recursive least squares estimates
+//!     * thrust - specific force (m/s²) per normalized command
+//!     * torque - angular acceleration (rad/s²) per normalized command
+//!     * time constant - of the first order response of the motor speed (per
+//!       the RPM telemetry when available)
+//!
+//! The measurements (angular acceleration, specific force) are the response
+//! of the modeled (lagged) motor commands. Both are low-pass filtered alike,
+//! so that the filter doesn't bias the estimates.
+
+use rusty_robot_common::Vector3;
+
+use super::mixer::Geometry;
+use crate::estimation::attitude::GRAVITY;
+use crate::estimation::rls::Rls;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorModel {
+    /// specific force (m/s²) per normalized command
+    pub thrust: f32,
+    /// angular acceleration (rad/s², body FRD) per normalized command
+    pub torque: Vector3,
+    /// time constant of the motor response (seconds)
+    pub time_constant: f32,
+}
+
+/// the vehicle can no longer meet its intents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deficiency {
+    /// thrust relative to the weight is below the minimum
+    Thrust(f32),
+    /// thrust of the motor relative to the mean is below the minimum
+    Motor(usize),
+    /// demands have exceeded the actuation (for the saturation time)
+    Saturated,
+}
+impl Deficiency {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Deficiency::Thrust(_) => "insufficient thrust",
+            Deficiency::Motor(_) => "motor degraded",
+            Deficiency::Saturated => "actuation saturated",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CharacterizationConfig {
+    /// weight of the prior measurements (per update)
+    pub forgetting: f32,
+    /// cutoff frequency of the measurement low-pass
+    pub cutoff_hz: f32,
+    /// initial estimate of the motor time constants (seconds)
+    pub time_constant: f32,
+    /// initial estimate of the angular acceleration per mixer factor (rad/s²)
+    pub authority: f32,
+    /// RPM of a normalized motor speed
+    pub max_rpm: f32,
+    /// updates prior to assessing the estimates
+    pub min_samples: u32,
+    /// minimum thrust relative to the weight to maneuver
+    pub min_thrust_to_weight: f32,
+    /// minimum thrust of a motor relative to the mean
+    pub min_motor_thrust: f32,
+    /// duration of saturation (seconds) considered unable to meet the intent
+    pub max_saturation_time: f32,
+}
+impl Default for CharacterizationConfig {
+    fn default() -> Self {
+        CharacterizationConfig {
+            forgetting: 0.9995,
+            cutoff_hz: 15.0,
+            time_constant: 0.03,
+            authority: 100.0,
+            max_rpm: 30_000.0,
+            min_samples: 2000,
+            min_thrust_to_weight: 1.3,
+            min_motor_thrust: 0.5,
+            max_saturation_time: 1.5,
+        }
+    }
+}
+
+pub struct Characterization<const N: usize> {
+    pub config: CharacterizationConfig,
+    thrust: Rls<N>,
+    roll: Rls<N>,
+    pitch: Rls<N>,
+    yaw: Rls<N>,
+    /// per motor, rpm = a * previous rpm + b * command (normalized)
+    lag: [Rls<2>; N],
+    time_constants: [f32; N],
+    /// modeled (lagged) response to the commands
+    response: [f32; N],
+    // low-passed
+    filtered_response: [f32; N],
+    filtered_rate: Option<Vector3>,
+    filtered_force: f32,
+    previous_rpm: Option<[f32; N]>,
+    saturation_time: f32,
+    samples: u32,
+}
+
+/// PT1 low-pass coefficient
+fn lowpass(cutoff_hz: f32, dt: f32) -> f32 {
+    let rc = 1.0 / (2.0 * core::f32::consts::PI * cutoff_hz);
+    dt / (rc + dt)
+}
+
+impl<const N: usize> Characterization<N> {
+    /// initial estimates per the geometry and the hover thrust (normalized)
+    pub fn new(config: CharacterizationConfig, geometry: &Geometry<N>, hover_thrust: f32) -> Self {
+        let motors = &geometry.motors;
+        let total: f32 = motors.iter().map(|m| m.thrust).sum();
+        let thrust = motors.map(|m| m.thrust * GRAVITY / (hover_thrust * total));
+        let forgetting = config.forgetting;
+        Characterization {
+            config,
+            thrust: Rls::new(thrust, 10.0, forgetting),
+            roll: Rls::new(motors.map(|m| m.roll * config.authority), 100.0, forgetting),
+            pitch: Rls::new(
+                motors.map(|m| m.pitch * config.authority),
+                100.0,
+                forgetting,
+            ),
+            yaw: Rls::new(motors.map(|m| m.yaw * config.authority), 100.0, forgetting),
+            lag: core::array::from_fn(|_| Rls::new([0.9, 0.1], 1.0, forgetting)),
+            time_constants: [config.time_constant; N],
+            response: [0.0; N],
+            filtered_response: [0.0; N],
+            filtered_rate: None,
+            filtered_force: 0.0,
+            previous_rpm: None,
+            saturation_time: 0.0,
+            samples: 0,
+        }
+    }
+
+    /// account for an IMU sample `dt` seconds after the previous (while airborne)
+    ///     `outputs` - motor commands applied since the previous sample
+    ///     `gyro` - deg/s, `accel` - m/s² (body FRD)
+    ///     `saturated` - the mixer could not provide the demands
+    pub fn update(
+        &mut self,
+        outputs: &[f32; N],
+        gyro: Vector3,
+        accel: Vector3,
+        dt: f32,
+        saturated: bool,
+    ) {
+        if dt <= 0.0 {
+            return;
+        }
+        self.saturation_time = if saturated {
+            self.saturation_time + dt
+        } else {
+            0.0
+        };
+
+        let c = lowpass(self.config.cutoff_hz, dt);
+        for (((response, filtered), output), tau) in self
+            .response
+            .iter_mut()
+            .zip(&mut self.filtered_response)
+            .zip(outputs)
+            .zip(&self.time_constants)
+        {
+            *response += (output - *response) * dt / (tau + dt);
+            *filtered += (*response - *filtered) * c;
+        }
+        let rate = gyro * (core::f32::consts::PI / 180.0);
+        let Some(previous) = self.filtered_rate else {
+            // first sample
+            self.filtered_rate = Some(rate);
+            self.filtered_force = -accel.z;
+            return;
+        };
+        let filtered_rate = previous + (rate - previous) * c;
+        let angular_accel = (filtered_rate - previous) * (1.0 / dt);
+        self.filtered_rate = Some(filtered_rate);
+        // specific force of the thrust (up the body)
+        self.filtered_force += (-accel.z - self.filtered_force) * c;
+
+        let phi = &self.filtered_response;
+        self.thrust.update(phi, self.filtered_force);
+        self.roll.update(phi, angular_accel.x);
+        self.pitch.update(phi, angular_accel.y);
+        self.yaw.update(phi, angular_accel.z);
+        self.samples = self.samples.saturating_add(1);
+    }
+
+    /// account for the motor speeds `dt` seconds after the previous
+    ///     `outputs` - motor commands applied since the previous measurement
+    pub fn update_rpm(&mut self, rpm: &[f32; N], outputs: &[f32; N], dt: f32) {
+        let speed = rpm.map(|r| r / self.config.max_rpm);
+        if let Some(previous) = self.previous_rpm
+            && dt > 0.0
+        {
+            for i in 0..N {
+                self.lag[i].update(&[previous[i], outputs[i]], speed[i]);
+                // a = exp(-dt / tau)
+                let a = self.lag[i].parameters()[0];
+                if a > 0.0 && a < 1.0 {
+                    self.time_constants[i] = -dt / libm::logf(a);
+                }
+            }
+        }
+        self.previous_rpm = Some(speed);
+    }
+
+    pub fn motors(&self) -> [MotorModel; N] {
+        let thrust = self.thrust.parameters();
+        let roll = self.roll.parameters();
+        let pitch = self.pitch.parameters();
+        let yaw = self.yaw.parameters();
+        core::array::from_fn(|i| MotorModel {
+            thrust: thrust[i],
+            torque: Vector3::new(roll[i], pitch[i], yaw[i]),
+            time_constant: self.time_constants[i],
+        })
+    }
+
+    /// maximum thrust relative to the weight
+    pub fn thrust_to_weight(&self) -> f32 {
+        self.thrust.parameters().iter().sum::<f32>() / GRAVITY
+    }
+
+    /// collective command (normalized) to hover
+    pub fn hover_throttle(&self) -> f32 {
+        1.0 / self.thrust_to_weight().max(f32::EPSILON)
+    }
+
+    /// whether the vehicle is able to meet its intents
+    pub fn assess(&self) -> Result<(), Deficiency> {
+        if self.samples < self.config.min_samples {
+            return Ok(());
+        }
+        let ratio = self.thrust_to_weight();
+        if ratio < self.config.min_thrust_to_weight {
+            return Err(Deficiency::Thrust(ratio));
+        }
+        let thrust = self.thrust.parameters();
+        let mean = thrust.iter().sum::<f32>() / N as f32;
+        if let Some((weakest, _)) = thrust
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| **t < self.config.min_motor_thrust * mean)
+            .min_by(|a, b| a.1.total_cmp(b.1))
+        {
+            return Err(Deficiency::Motor(weakest));
+        }
+        if self.saturation_time > self.config.max_saturation_time {
+            return Err(Deficiency::Saturated);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    /// deterministic uniform noise [-0.5, 0.5]
+    struct Noise(u32);
+    impl Noise {
+        fn next(&mut self) -> f32 {
+            self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (self.0 >> 8) as f32 / (1 << 24) as f32 - 0.5
+        }
+    }
+
+    /// quad with lagged motors of the thrusts (m/s² per command)
+    struct Vehicle {
+        geometry: Geometry<4>,
+        thrust: [f32; 4],
+        time_constant: f32,
+        response: [f32; 4],
+        rate: Vector3,
+    }
+    impl Vehicle {
+        fn new(thrust: [f32; 4], time_constant: f32) -> Self {
+            Vehicle {
+                geometry: Geometry::quad_x(),
+                thrust,
+                time_constant,
+                response: [0.5; 4],
+                rate: Vector3::ZERO,
+            }
+        }
+        fn torque(&self, i: usize) -> Vector3 {
+            let m = &self.geometry.motors[i];
+            // torque proportional to the thrust of the motor
+            Vector3::new(m.roll, m.pitch, m.yaw) * (self.thrust[i] * 20.0)
+        }
+        /// (gyro deg/s, accel m/s²) after the commands
+        fn step(&mut self, outputs: &[f32; 4]) -> (Vector3, Vector3) {
+            let mut angular_accel = Vector3::ZERO;
+            let mut force = 0.0;
+            for (i, output) in outputs.iter().enumerate() {
+                self.response[i] += (output - self.response[i]) * DT / (self.time_constant + DT);
+                angular_accel += self.torque(i) * self.response[i];
+                force += self.thrust[i] * self.response[i];
+            }
+            self.rate += angular_accel * DT;
+            (
+                self.rate * (180.0 / core::f32::consts::PI),
+                Vector3::new(0.0, 0.0, -force),
+            )
+        }
+    }
+
+    /// fly with doublets about the hover (i.e. maneuvers of the rate control)
+    fn fly(vehicle: &mut Vehicle, characterization: &mut Characterization<4>, steps: usize) {
+        let mut noise = Noise(7);
+        let mut doublet = [0.0; 4];
+        for step in 0..steps {
+            if step % 40 == 0 {
+                doublet = doublet.map(|_| 0.3 * noise.next());
+            } else if step % 40 == 20 {
+                doublet = doublet.map(|d| -d);
+            }
+            let outputs = doublet.map(|d| 0.5 + d);
+            let (gyro, accel) = vehicle.step(&outputs);
+            characterization.update(&outputs, gyro, accel, DT, false);
+        }
+    }
+
+    #[test]
+    fn identifies_motors() {
+        let truth = [9.0, 10.0, 11.0, 9.5];
+        let mut vehicle = Vehicle::new(truth, 0.03);
+        let geometry = Geometry::quad_x();
+        let mut characterization =
+            Characterization::new(CharacterizationConfig::default(), &geometry, 0.5);
+        fly(&mut vehicle, &mut characterization, 20_000);
+
+        let motors = characterization.motors();
+        for i in 0..4 {
+            let thrust = motors[i].thrust;
+            assert!((thrust - truth[i]).abs() < 0.2, "motor {i} thrust {thrust}");
+            let error = (motors[i].torque - vehicle.torque(i)).norm();
+            assert!(
+                error < 0.05 * vehicle.torque(i).norm(),
+                "motor {i} {motors:?}"
+            );
+        }
+        let ratio: f32 = truth.iter().sum::<f32>() / GRAVITY;
+        assert!((characterization.thrust_to_weight() - ratio).abs() < 0.05);
+        assert!((characterization.hover_throttle() - 1.0 / ratio).abs() < 0.01);
+        assert_eq!(characterization.assess(), Ok(()));
+    }
+
+    #[test]
+    fn reports_deficiencies() {
+        let geometry = Geometry::quad_x();
+        let config = CharacterizationConfig::default();
+
+        // damaged propeller
+        let mut vehicle = Vehicle::new([10.0, 10.0, 3.0, 10.0], 0.03);
+        let mut characterization = Characterization::new(config, &geometry, 0.5);
+        fly(&mut vehicle, &mut characterization, 20_000);
+        assert_eq!(characterization.assess(), Err(Deficiency::Motor(2)));
+
+        // payload beyond capability
+        let mut vehicle = Vehicle::new([3.0; 4], 0.03);
+        let mut characterization = Characterization::new(config, &geometry, 0.5);
+        fly(&mut vehicle, &mut characterization, 20_000);
+        assert!(matches!(
+            characterization.assess(),
+            Err(Deficiency::Thrust(ratio)) if ratio < 1.3
+        ));
+
+        // persistent saturation
+        let mut vehicle = Vehicle::new([10.0; 4], 0.03);
+        let mut characterization = Characterization::new(config, &geometry, 0.5);
+        fly(&mut vehicle, &mut characterization, 5_000);
+        for _ in 0..2_000 {
+            let (gyro, accel) = vehicle.step(&[1.0; 4]);
+            characterization.update(&[1.0; 4], gyro, accel, DT, true);
+        }
+        assert_eq!(characterization.assess(), Err(Deficiency::Saturated));
+    }
+
+    #[test]
+    fn identifies_time_constants() {
+        let geometry = Geometry::quad_x();
+        let mut characterization =
+            Characterization::new(CharacterizationConfig::default(), &geometry, 0.5);
+        let truth = [0.02, 0.04, 0.05, 0.08];
+        let mut rpm = [0.0; 4];
+        let mut noise = Noise(3);
+        let mut outputs = [0.5; 4];
+        for step in 0..20_000 {
+            if step % 50 == 0 {
+                outputs = outputs.map(|_| 0.5 + 0.4 * noise.next());
+            }
+            for i in 0..4 {
+                let steady = 25_000.0 * outputs[i];
+                rpm[i] += (steady - rpm[i]) * (1.0 - libm::expf(-DT / truth[i]));
+            }
+            characterization.update_rpm(&rpm, &outputs, DT);
+        }
+        let motors = characterization.motors();
+        for i in 0..4 {
+            let tau = motors[i].time_constant;
+            assert!(
+                (tau - truth[i]).abs() < 0.05 * truth[i],
+                "motor {i} tau {tau}"
+            );
+        }
+    }
+}
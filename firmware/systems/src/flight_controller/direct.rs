@@ -0,0 +1,291 @@
+//! Direct mode (headless manual trajectory)
+//!
+//! Per the user story, the user specifies the trajectory (3D direction and
+//! rate) and the flight controller manages the maneuvers along it. The
+//! trajectory is in the world frame (NED), so it is independent of the heading
+//! of the vehicle (headless) - i.e. stick forward is always north.
+//!     * RC - sticks (shaped to [-1.0, 1.0]) scale the maximum rates, where the
+//!       centered throttle holds the altitude
+//!     * mesh - the trajectory vector
+//!
+//! The commanded velocity is approached within the acceleration limit, so
+//! that abrupt commands (i.e. a released stick) result in smooth maneuvers.
+
+use rusty_robot_common::Vector3;
+use rusty_robot_drivers::rc_traits::RcCommand;
+
+use super::position_control::Target;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectConfig {
+    /// horizontal speed at full deflection (m/s)
+    pub max_speed: f32,
+    /// vertical rates at full deflection (m/s)
+    pub max_climb_rate: f32,
+    pub max_descent_rate: f32,
+    /// throttle about center that holds the altitude (fraction)
+    pub throttle_deadband: f32,
+    /// heading rate at full deflection (deg/s)
+    pub max_yaw_rate: f32,
+    /// limit of the change of velocity (m/s²)
+    pub max_accel: f32,
+    /// duration without commands after which the vehicle stops (seconds)
+    pub timeout: f32,
+}
+impl Default for DirectConfig {
+    fn default() -> Self {
+        DirectConfig {
+            max_speed: 5.0,
+            max_climb_rate: 2.0,
+            max_descent_rate: 1.0,
+            throttle_deadband: 0.1,
+            max_yaw_rate: 90.0,
+            max_accel: 3.0,
+            timeout: 0.5,
+        }
+    }
+}
+
+pub struct Direct {
+    pub config: DirectConfig,
+    /// commanded velocity (NED, m/s)
+    commanded: Vector3,
+    /// commanded heading rate (rad/s)
+    yaw_rate: f32,
+    /// time of the latest command (seconds since boot)
+    commanded_at: f32,
+    /// acceleration limited velocity (NED, m/s)
+    velocity: Vector3,
+}
+
+impl Direct {
+    pub fn new(config: DirectConfig) -> Self {
+        Direct {
+            config,
+            commanded: Vector3::ZERO,
+            yaw_rate: 0.0,
+            commanded_at: 0.0,
+            velocity: Vector3::ZERO,
+        }
+    }
+
+    /// engage from the current velocity (NED, m/s)
+    pub fn reset(&mut self, velocity: &Vector3) {
+        self.velocity = *velocity;
+        self.commanded = *velocity;
+        self.yaw_rate = 0.0;
+    }
+
+    /// command the velocity per the sticks at the time (seconds since boot)
+    pub fn sticks(&mut self, command: &RcCommand, time: f32) {
+        let config = &self.config;
+        // stick forward (nose down) is north, stick right is east
+        let north = -command.pitch.clamp(-1.0, 1.0);
+        let east = command.roll.clamp(-1.0, 1.0);
+        let deflection = libm::sqrtf(north * north + east * east).max(1.0);
+
+        // throttle above (below) the deadband climbs (descends), an unknown
+        //  throttle holds the altitude
+        let offset = command
+            .throttle
+            .map_or(0.0, |throttle| throttle.clamp(0.0, 1.0) - 0.5);
+        let span = 0.5 - config.throttle_deadband;
+        let vertical = if offset.abs() <= config.throttle_deadband || span <= 0.0 {
+            0.0
+        } else {
+            (offset - offset.signum() * config.throttle_deadband) / span
+        };
+        let down = if vertical > 0.0 {
+            -vertical * config.max_climb_rate
+        } else {
+            -vertical * config.max_descent_rate
+        };
+
+        self.commanded = Vector3::new(
+            north / deflection * config.max_speed,
+            east / deflection * config.max_speed,
+            down,
+        );
+        self.yaw_rate = command.yaw.clamp(-1.0, 1.0) * config.max_yaw_rate.to_radians();
+        self.commanded_at = time;
+    }
+
+    /// command the trajectory (direction NED, rate m/s) at the time (seconds
+    ///     since boot)
+    pub fn trajectory(&mut self, direction: &Vector3, rate: f32, time: f32) {
+        let config = &self.config;
+        let velocity = direction.normalized().unwrap_or(Vector3::ZERO) * rate.abs();
+        // within the limits of the sticks
+        let horizontal = libm::sqrtf(velocity.x * velocity.x + velocity.y * velocity.y);
+        let scale = if horizontal > config.max_speed {
+            config.max_speed / horizontal
+        } else {
+            1.0
+        };
+        self.commanded = Vector3::new(
+            velocity.x * scale,
+            velocity.y * scale,
+            velocity
+                .z
+                .clamp(-config.max_climb_rate, config.max_descent_rate),
+        );
+        self.yaw_rate = 0.0;
+        self.commanded_at = time;
+    }
+
+    /// commanded heading rate (rad/s, clockwise from above)
+    pub fn yaw_rate(&self) -> f32 {
+        self.yaw_rate
+    }
+
+    /// trajectory toward the commanded velocity `dt` seconds after the
+    ///     previous, at the time (seconds since boot)
+    pub fn update(&mut self, time: f32, dt: f32) -> Target {
+        if time - self.commanded_at > self.config.timeout {
+            // commands stopped
+            self.commanded = Vector3::ZERO;
+            self.yaw_rate = 0.0;
+        }
+        let change = self.commanded - self.velocity;
+        let limit = self.config.max_accel * dt;
+        let norm = change.norm();
+        self.velocity += if norm > limit {
+            change * (limit / norm)
+        } else {
+            change
+        };
+        let rate = self.velocity.norm();
+        Target::Trajectory {
+            direction: if rate > 0.0 {
+                self.velocity * (1.0 / rate)
+            } else {
+                Vector3::ZERO
+            },
+            rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    fn velocity(target: &Target) -> Vector3 {
+        match target {
+            Target::Trajectory { direction, rate } => *direction * *rate,
+            _ => panic!("{target:?}"),
+        }
+    }
+
+    #[test]
+    fn maps_sticks_to_world_frame() {
+        let config = DirectConfig::default();
+        let mut direct = Direct::new(config);
+        let centered = RcCommand {
+            throttle: Some(0.5),
+            ..Default::default()
+        };
+        direct.sticks(&centered, 0.0);
+        assert_eq!(direct.commanded, Vector3::ZERO);
+
+        // forward is north (regardless of the heading)
+        direct.sticks(
+            &RcCommand {
+                pitch: -1.0,
+                ..centered
+            },
+            0.0,
+        );
+        assert_eq!(direct.commanded, Vector3::new(config.max_speed, 0.0, 0.0));
+
+        // diagonal limited to the maximum speed
+        direct.sticks(
+            &RcCommand {
+                pitch: -1.0,
+                roll: 1.0,
+                ..centered
+            },
+            0.0,
+        );
+        let horizontal = Vector3::new(direct.commanded.x, direct.commanded.y, 0.0);
+        assert!((horizontal.norm() - config.max_speed).abs() < 1e-3);
+        assert!(direct.commanded.y > 0.0);
+
+        // throttle climbs and descends beyond the deadband
+        direct.sticks(
+            &RcCommand {
+                throttle: Some(0.55),
+                ..centered
+            },
+            0.0,
+        );
+        assert_eq!(direct.commanded.z, 0.0);
+        direct.sticks(
+            &RcCommand {
+                throttle: Some(1.0),
+                ..centered
+            },
+            0.0,
+        );
+        assert_eq!(direct.commanded.z, -config.max_climb_rate);
+        direct.sticks(
+            &RcCommand {
+                throttle: Some(0.0),
+                yaw: 0.5,
+                ..centered
+            },
+            0.0,
+        );
+        assert_eq!(direct.commanded.z, config.max_descent_rate);
+        assert!((direct.yaw_rate() - 45f32.to_radians()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn limits_acceleration() {
+        let config = DirectConfig::default();
+        let mut direct = Direct::new(config);
+        direct.reset(&Vector3::ZERO);
+        direct.trajectory(&Vector3::new(0.0, -1.0, 0.0), 4.0, 0.0);
+        let mut time = 0.0;
+        let mut previous = Vector3::ZERO;
+        for _ in 0..100 {
+            time += DT;
+            direct.trajectory(&Vector3::new(0.0, -1.0, 0.0), 4.0, time);
+            let velocity = velocity(&direct.update(time, DT));
+            let accel = (velocity - previous).norm() / DT;
+            assert!(accel <= config.max_accel + 1e-3, "accel {accel}");
+            previous = velocity;
+        }
+        // 3 m/s² for a second
+        assert!((previous - Vector3::new(0.0, -3.0, 0.0)).norm() < 1e-3);
+        for _ in 0..100 {
+            time += DT;
+            direct.trajectory(&Vector3::new(0.0, -1.0, 0.0), 4.0, time);
+            previous = velocity(&direct.update(time, DT));
+        }
+        assert!((previous - Vector3::new(0.0, -4.0, 0.0)).norm() < 1e-3);
+    }
+
+    #[test]
+    fn stops_without_commands() {
+        let config = DirectConfig::default();
+        let mut direct = Direct::new(config);
+        direct.reset(&Vector3::new(2.0, 0.0, 0.0));
+        direct.trajectory(&Vector3::new(1.0, 0.0, 0.0), 2.0, 0.0);
+        assert_eq!(
+            velocity(&direct.update(0.1, DT)),
+            Vector3::new(2.0, 0.0, 0.0)
+        );
+
+        // decelerates (smoothly) once the commands stop
+        let mut time = 0.1;
+        let mut target = direct.update(time, DT);
+        for _ in 0..200 {
+            time += DT;
+            target = direct.update(time, DT);
+        }
+        assert!(velocity(&target).norm() < 1e-3);
+    }
+}
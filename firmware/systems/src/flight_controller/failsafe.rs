@@ -0,0 +1,329 @@
+//! Soft-descent failsafe
+//!
+//! When unable to continue toward the intent, the flight controller manages a
+//! soft descent (Land). The failsafe monitors the conditions of the flight
+//!     * IMU data - without it the vehicle can't be stabilized
+//!     * RC/mesh link - once a link has been established
+//!     * battery - sustained low cell voltage
+//!     * navigation - divergence of the estimator
+//!     * actuation - per the [self-characterization](super::characterization)
+//!
+//! The descent is controlled per the remaining sensors
+//!     * Guided - descent rate controlled per the navigation estimate
+//!     * Level - attitude held level with thrust below the hover thrust
+//!     * OpenLoop - no IMU, thrust below the hover thrust without stabilization
+//!
+//! Without navigation, touchdown is detected by the impact followed by rest
+//! (per the IMU), otherwise by the expiry of the descent duration.
+
+use rusty_robot_common::Vector3;
+
+use crate::estimation::attitude::GRAVITY;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    ImuLost,
+    NavigationDiverged,
+    ActuationDeficient,
+    LowBattery,
+    LinkLost,
+}
+impl Failure {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Failure::ImuLost => "imu lost",
+            Failure::NavigationDiverged => "navigation diverged",
+            Failure::ActuationDeficient => "actuation deficient",
+            Failure::LowBattery => "low battery",
+            Failure::LinkLost => "link lost",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Descent {
+    Guided,
+    Level,
+    OpenLoop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FailsafeConfig {
+    /// duration without IMU data (seconds)
+    pub imu_timeout: f32,
+    /// duration without RC/mesh messages (seconds)
+    pub link_timeout: f32,
+    /// cell voltage below which the battery is low (V)
+    pub min_cell_voltage: f32,
+    /// duration of the low voltage (seconds) - ignores sag during maneuvers
+    pub low_battery_time: f32,
+    /// thrust of a descent without navigation (fraction of the hover thrust)
+    pub blind_thrust: f32,
+    /// duration after which a descent without navigation has landed (seconds)
+    pub blind_timeout: f32,
+    /// acceleration beyond gravity detected as an impact (m/s²)
+    pub impact_accel: f32,
+    /// duration at rest after the impact (seconds)
+    pub rest_time: f32,
+}
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        FailsafeConfig {
+            imu_timeout: 0.05,
+            link_timeout: 1.5,
+            min_cell_voltage: 3.4,
+            low_battery_time: 2.0,
+            blind_thrust: 0.85,
+            blind_timeout: 60.0,
+            impact_accel: 6.0,
+            rest_time: 1.0,
+        }
+    }
+}
+
+pub struct Failsafe {
+    pub config: FailsafeConfig,
+    /// time of the latest IMU sample
+    imu: Option<f32>,
+    /// time of the latest link message
+    link: Option<f32>,
+    /// start of the low battery voltage
+    low_battery_since: Option<f32>,
+    diverged: bool,
+    deficient: bool,
+}
+
+impl Failsafe {
+    pub fn new(config: FailsafeConfig) -> Self {
+        Failsafe {
+            config,
+            imu: None,
+            link: None,
+            low_battery_since: None,
+            diverged: false,
+            deficient: false,
+        }
+    }
+
+    pub fn imu_received(&mut self, time: f32) {
+        self.imu = Some(time);
+    }
+
+    /// RC/mesh message received
+    pub fn link_received(&mut self, time: f32) {
+        self.link = Some(time);
+    }
+
+    /// voltage of the battery (per cell)
+    pub fn battery(&mut self, time: f32, cell_voltage: f32) {
+        if cell_voltage >= self.config.min_cell_voltage {
+            self.low_battery_since = None;
+        } else if self.low_battery_since.is_none() {
+            self.low_battery_since = Some(time);
+        }
+    }
+
+    pub fn navigation(&mut self, diverged: bool) {
+        self.diverged = diverged;
+    }
+
+    /// the vehicle can no longer meet its intents
+    pub fn actuation(&mut self, deficient: bool) {
+        self.deficient = deficient;
+    }
+
+    /// the RC/mesh link is established (and not lost)
+    pub fn link(&self, time: f32) -> bool {
+        self.link
+            .is_some_and(|link| time - link <= self.config.link_timeout)
+    }
+
+    pub fn imu_lost(&self, time: f32) -> bool {
+        self.imu
+            .is_none_or(|imu| time - imu > self.config.imu_timeout)
+    }
+
+    /// the most severe of the current failures
+    pub fn check(&self, time: f32) -> Option<Failure> {
+        let config = &self.config;
+        if self.imu_lost(time) {
+            Some(Failure::ImuLost)
+        } else if self.diverged {
+            Some(Failure::NavigationDiverged)
+        } else if self.deficient {
+            Some(Failure::ActuationDeficient)
+        } else if self
+            .low_battery_since
+            .is_some_and(|since| time - since >= config.low_battery_time)
+        {
+            Some(Failure::LowBattery)
+        } else if self
+            .link
+            .is_some_and(|link| time - link > config.link_timeout)
+        {
+            Some(Failure::LinkLost)
+        } else {
+            None
+        }
+    }
+
+    /// control of the descent per the remaining sensors
+    pub fn descent(&self, time: f32) -> Descent {
+        if self.imu_lost(time) {
+            Descent::OpenLoop
+        } else if self.diverged {
+            Descent::Level
+        } else {
+            Descent::Guided
+        }
+    }
+}
+
+/// touchdown of a descent without navigation
+pub struct BlindTouchdown {
+    config: FailsafeConfig,
+    /// start of the descent
+    since: f32,
+    impact: bool,
+    /// start of the rest after the impact
+    rest_since: Option<f32>,
+}
+
+impl BlindTouchdown {
+    pub fn new(config: FailsafeConfig, time: f32) -> Self {
+        BlindTouchdown {
+            config,
+            since: time,
+            impact: false,
+            rest_since: None,
+        }
+    }
+
+    /// account for the IMU (accel m/s², gyro deg/s), true upon touchdown
+    pub fn update(&mut self, time: f32, accel: Option<Vector3>, gyro: Option<Vector3>) -> bool {
+        let config = &self.config;
+        if time - self.since >= config.blind_timeout {
+            return true;
+        }
+        let (Some(accel), Some(gyro)) = (accel, gyro) else {
+            return false;
+        };
+        let excess = (accel.norm() - GRAVITY).abs();
+        if excess > config.impact_accel {
+            self.impact = true;
+            self.rest_since = None;
+            return false;
+        }
+        let at_rest = excess < 1.0 && gyro.norm() < 10.0;
+        match (self.impact && at_rest, self.rest_since) {
+            (false, _) => self.rest_since = None,
+            (true, None) => self.rest_since = Some(time),
+            (true, Some(since)) => return time - since >= config.rest_time,
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.01;
+
+    fn monitored() -> Failsafe {
+        let mut failsafe = Failsafe::new(FailsafeConfig::default());
+        failsafe.imu_received(0.0);
+        failsafe
+    }
+
+    #[test]
+    fn detects_failures() {
+        let mut failsafe = monitored();
+        // no link has been established
+        assert_eq!(failsafe.check(0.01), None);
+        assert_eq!(failsafe.descent(0.01), Descent::Guided);
+
+        // imu
+        assert_eq!(failsafe.check(0.1), Some(Failure::ImuLost));
+        assert_eq!(failsafe.descent(0.1), Descent::OpenLoop);
+        failsafe.imu_received(0.1);
+
+        // navigation
+        failsafe.navigation(true);
+        assert_eq!(failsafe.check(0.1), Some(Failure::NavigationDiverged));
+        assert_eq!(failsafe.descent(0.1), Descent::Level);
+        failsafe.navigation(false);
+
+        // actuation
+        failsafe.actuation(true);
+        assert_eq!(failsafe.check(0.1), Some(Failure::ActuationDeficient));
+        assert_eq!(failsafe.descent(0.1), Descent::Guided);
+        failsafe.actuation(false);
+
+        // link
+        failsafe.link_received(0.1);
+        failsafe.imu_received(1.0);
+        assert_eq!(failsafe.check(1.0), None);
+        failsafe.imu_received(2.0);
+        assert_eq!(failsafe.check(2.0), Some(Failure::LinkLost));
+        assert_eq!(failsafe.descent(2.0), Descent::Guided);
+        failsafe.link_received(2.0);
+        assert_eq!(failsafe.check(2.0), None);
+    }
+
+    #[test]
+    fn ignores_battery_sag() {
+        let mut failsafe = monitored();
+        let mut time = 0.0;
+        // sag during a maneuver
+        for voltage in [3.7, 3.3, 3.3, 3.6] {
+            time += 0.5;
+            failsafe.imu_received(time);
+            failsafe.battery(time, voltage);
+            assert_eq!(failsafe.check(time), None);
+        }
+        // sustained
+        for _ in 0..6 {
+            time += 0.5;
+            failsafe.imu_received(time);
+            failsafe.battery(time, 3.3);
+        }
+        assert_eq!(failsafe.check(time), Some(Failure::LowBattery));
+    }
+
+    #[test]
+    fn detects_touchdown_by_impact() {
+        let config = FailsafeConfig::default();
+        let mut touchdown = BlindTouchdown::new(config, 0.0);
+        let level = Some(Vector3::new(0.0, 0.0, -GRAVITY));
+        let still = Some(Vector3::ZERO);
+        let mut time = 0.0;
+        // steady descent reads as at rest, but hasn't impacted
+        for _ in 0..500 {
+            time += DT;
+            assert!(!touchdown.update(time, level, still));
+        }
+        // impact, bounce then rest
+        time += DT;
+        assert!(!touchdown.update(time, Some(Vector3::new(0.0, 0.0, -30.0)), still));
+        for _ in 0..10 {
+            time += DT;
+            let bounce = Some(Vector3::new(3.0, 0.0, -5.0));
+            assert!(!touchdown.update(time, bounce, Some(Vector3::new(50.0, 0.0, 0.0))));
+        }
+        let mut landed = false;
+        for _ in 0..(config.rest_time / DT) as usize + 2 {
+            time += DT;
+            landed |= touchdown.update(time, level, still);
+        }
+        assert!(landed);
+    }
+
+    #[test]
+    fn touchdown_upon_timeout() {
+        let config = FailsafeConfig::default();
+        let mut touchdown = BlindTouchdown::new(config, 10.0);
+        assert!(!touchdown.update(10.0 + config.blind_timeout - 1.0, None, None));
+        assert!(touchdown.update(10.0 + config.blind_timeout, None, None));
+    }
+}
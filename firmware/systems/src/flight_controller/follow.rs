@@ -0,0 +1,309 @@
+//! Follow mode (drone bodyguard)
+//!
+//! Per the user story, the user provides their location (i.e. a bluetooth
+//! leash over the mesh) and the flight controller maintains the vehicle at a
+//! safe distance within the proximity of the user.
+//!     * prediction - the velocity of the user is estimated from the stream of
+//!       positions, predicting their position between updates
+//!     * standoff - the vehicle is kept at the standoff distance (horizontal)
+//!       and altitude above the user, facing the user
+//!     * orbit - optionally circling the user at the orbit rate
+//!
+//! The trajectory is the velocity of the user (feedforward) plus the
+//! correction toward the standoff position. Without updates for the timeout,
+//! the user is lost (i.e. the flight controller holds its position).
+
+use rusty_robot_common::Vector3;
+
+use super::position_control::Target;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// remain on the side of the user at the standoff distance
+    Trail,
+    /// circle the user (deg/s, positive clockwise from above)
+    Orbit { rate: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FollowConfig {
+    /// horizontal distance from the user (m)
+    pub standoff_distance: f32,
+    /// height above the user (m)
+    pub standoff_altitude: f32,
+    pub pattern: Pattern,
+    /// velocity (m/s) per error from the standoff position (m)
+    pub gain: f32,
+    /// limit of the speed (m/s)
+    pub max_speed: f32,
+    /// weight of each update within the estimated velocity (0.0 - 1.0)
+    pub smoothing: f32,
+    /// limit of the prediction (seconds)
+    pub max_prediction: f32,
+    /// duration without updates after which the user is lost (seconds)
+    pub timeout: f32,
+}
+impl Default for FollowConfig {
+    fn default() -> Self {
+        FollowConfig {
+            standoff_distance: 5.0,
+            standoff_altitude: 3.0,
+            pattern: Pattern::Trail,
+            gain: 0.8,
+            max_speed: 5.0,
+            smoothing: 0.3,
+            max_prediction: 1.0,
+            timeout: 3.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Following {
+    /// trajectory of the vehicle with its heading (radians) toward the user
+    Tracking { target: Target, heading: f32 },
+    /// without updates for the timeout
+    Lost,
+}
+
+pub struct Follow {
+    pub config: FollowConfig,
+    /// latest position of the user (NED, m) with the time of its receipt
+    user: Option<(Vector3, f32)>,
+    /// estimated velocity of the user (NED, m/s)
+    velocity: Vector3,
+    /// bearing of the vehicle from the user (radians)
+    bearing: Option<f32>,
+    /// time of the previous update (seconds since boot)
+    updated_at: Option<f32>,
+}
+
+impl Follow {
+    pub fn new(config: FollowConfig) -> Self {
+        Follow {
+            config,
+            user: None,
+            velocity: Vector3::ZERO,
+            bearing: None,
+            updated_at: None,
+        }
+    }
+
+    /// restart the pattern (i.e. upon engaging)
+    pub fn reset(&mut self) {
+        self.bearing = None;
+        self.updated_at = None;
+    }
+
+    /// position of the user (NED, m) received at the time (seconds since boot)
+    pub fn user_position(&mut self, position: &Vector3, time: f32) {
+        if let Some((previous, since)) = self.user {
+            let dt = time - since;
+            if dt > 0.0 {
+                let measured = (*position - previous) * (1.0 / dt);
+                self.velocity += (measured - self.velocity) * self.config.smoothing;
+            }
+        }
+        self.user = Some((*position, time));
+    }
+
+    /// estimated velocity of the user (NED, m/s)
+    pub fn user_velocity(&self) -> Vector3 {
+        self.velocity
+    }
+
+    /// predicted position of the user (NED, m) at the time (seconds since boot)
+    pub fn predicted(&self, time: f32) -> Option<Vector3> {
+        let (position, since) = self.user?;
+        let horizon = (time - since).clamp(0.0, self.config.max_prediction);
+        Some(position + self.velocity * horizon)
+    }
+
+    /// trajectory per the position of the vehicle (NED, m) at the time
+    ///     (seconds since boot)
+    pub fn update(&mut self, position: &Vector3, time: f32) -> Following {
+        let config = self.config;
+        let dt = self.updated_at.map_or(0.0, |previous| time - previous);
+        self.updated_at = Some(time);
+        let Some((_, since)) = self.user else {
+            return Following::Lost;
+        };
+        if time - since > config.timeout {
+            return Following::Lost;
+        }
+        let Some(user) = self.predicted(time) else {
+            return Following::Lost;
+        };
+
+        // bearing of the standoff position
+        let offset = *position - user;
+        let current = libm::atan2f(offset.y, offset.x);
+        let (bearing, tangential) = match config.pattern {
+            Pattern::Trail => (current, 0.0),
+            Pattern::Orbit { rate } => {
+                let rate = rate.to_radians();
+                let bearing = self.bearing.unwrap_or(current) + rate * dt;
+                (bearing, rate * config.standoff_distance)
+            }
+        };
+        // within (-PI, PI]
+        let bearing = libm::atan2f(libm::sinf(bearing), libm::cosf(bearing));
+        self.bearing = Some(bearing);
+        let (sin, cos) = (libm::sinf(bearing), libm::cosf(bearing));
+        let standoff = user
+            + Vector3::new(
+                cos * config.standoff_distance,
+                sin * config.standoff_distance,
+                -config.standoff_altitude,
+            );
+
+        let mut velocity = self.velocity
+            + Vector3::new(-sin * tangential, cos * tangential, 0.0)
+            + (standoff - *position) * config.gain;
+        let speed = velocity.norm();
+        if speed > config.max_speed {
+            velocity = velocity * (config.max_speed / speed);
+        }
+        let rate = velocity.norm();
+        Following::Tracking {
+            target: Target::Trajectory {
+                direction: if rate > 0.0 {
+                    velocity * (1.0 / rate)
+                } else {
+                    Vector3::ZERO
+                },
+                rate,
+            },
+            // facing the user
+            heading: libm::atan2f(-offset.y, -offset.x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.05;
+
+    /// vehicle attaining the trajectories, user updates at 5Hz
+    fn fly(
+        follow: &mut Follow,
+        vehicle: &mut Vector3,
+        user: &mut Vector3,
+        user_velocity: Vector3,
+        time: &mut f32,
+        steps: usize,
+    ) -> Following {
+        let mut following = Following::Lost;
+        for step in 0..steps {
+            *time += DT;
+            *user += user_velocity * DT;
+            if step % 4 == 0 {
+                follow.user_position(user, *time);
+            }
+            following = follow.update(vehicle, *time);
+            if let Following::Tracking {
+                target: Target::Trajectory { direction, rate },
+                ..
+            } = following
+            {
+                *vehicle += direction * rate * DT;
+            }
+        }
+        following
+    }
+
+    fn horizontal(v: Vector3) -> f32 {
+        libm::sqrtf(v.x * v.x + v.y * v.y)
+    }
+
+    #[test]
+    fn follows_walking_user() {
+        let config = FollowConfig::default();
+        let mut follow = Follow::new(config);
+        let mut vehicle = Vector3::new(-10.0, 5.0, -2.0);
+        let mut user = Vector3::ZERO;
+        let walking = Vector3::new(1.5, 0.5, 0.0);
+        let mut time = 0.0;
+        let following = fly(
+            &mut follow,
+            &mut vehicle,
+            &mut user,
+            walking,
+            &mut time,
+            600,
+        );
+
+        assert!((follow.user_velocity() - walking).norm() < 0.05);
+        let offset = vehicle - user;
+        let distance = horizontal(offset);
+        assert!(
+            (distance - config.standoff_distance).abs() < 0.5,
+            "distance {distance}"
+        );
+        assert!((-offset.z - config.standoff_altitude).abs() < 0.2);
+        let Following::Tracking { heading, .. } = following else {
+            panic!("lost");
+        };
+        let toward = libm::atan2f(-offset.y, -offset.x);
+        assert!((heading - toward).abs() < 0.05, "heading {heading}");
+    }
+
+    #[test]
+    fn orbits_user() {
+        let config = FollowConfig {
+            pattern: Pattern::Orbit { rate: 20.0 },
+            ..Default::default()
+        };
+        let mut follow = Follow::new(config);
+        let mut vehicle = Vector3::new(5.0, 0.0, -3.0);
+        let mut user = Vector3::ZERO;
+        let mut time = 0.0;
+        let mut bearings = 0.0;
+        let mut previous = 0.0;
+        for _ in 0..100 {
+            fly(
+                &mut follow,
+                &mut vehicle,
+                &mut user,
+                Vector3::ZERO,
+                &mut time,
+                1,
+            );
+            let bearing = libm::atan2f(vehicle.y, vehicle.x);
+            let mut change = bearing - previous;
+            if change < -core::f32::consts::PI {
+                change += 2.0 * core::f32::consts::PI;
+            }
+            bearings += change;
+            previous = bearing;
+            assert!((horizontal(vehicle) - config.standoff_distance).abs() < 0.5);
+        }
+        // circled clockwise at (about) the rate for 5 seconds
+        let degrees = bearings.to_degrees();
+        assert!((degrees - 100.0).abs() < 10.0, "orbited {degrees}");
+    }
+
+    #[test]
+    fn loses_user_without_updates() {
+        let config = FollowConfig::default();
+        let mut follow = Follow::new(config);
+        assert_eq!(follow.update(&Vector3::ZERO, 0.0), Following::Lost);
+
+        follow.user_position(&Vector3::ZERO, 0.0);
+        follow.user_position(&Vector3::new(1.0, 0.0, 0.0), 1.0);
+        assert!(matches!(
+            follow.update(&Vector3::new(-5.0, 0.0, -3.0), 1.0),
+            Following::Tracking { .. }
+        ));
+        // prediction is limited
+        let predicted = follow.predicted(3.0).unwrap();
+        let limit = 1.0 + follow.user_velocity().x * config.max_prediction;
+        assert!((predicted.x - limit).abs() < 1e-5);
+        assert_eq!(
+            follow.update(&Vector3::ZERO, 1.0 + config.timeout + 0.1),
+            Following::Lost
+        );
+    }
+}
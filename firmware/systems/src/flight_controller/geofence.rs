@@ -0,0 +1,451 @@
+//! Geofence and altitude limits
+//!
+//! Bounds where the vehicle may go
+//!     * zones - polygons or cylinders (unbounded vertically), either
+//!       inclusion (the vehicle must remain within one of them) or exclusion
+//!       (the vehicle must remain outside all of them)
+//!     * altitude - minimum/maximum height above home
+//!
+//! Zones are geographic, navigated relative to the origin of the navigation
+//! estimate. The check includes the position predicted by the velocity (the
+//! lookahead), so the breach action (hold, return, land) is taken before the
+//! limit is crossed.
+
+use rusty_robot_common::Vector3;
+
+use crate::estimation::geodetic::GeoPoint;
+
+/// capacity of the geofence
+pub const MAX_ZONES: usize = 8;
+/// capacity of a polygon
+pub const MAX_VERTICES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneKind {
+    Inclusion,
+    Exclusion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zone {
+    pub kind: ZoneKind,
+    /// vertices of the polygon, or the center of the cylinder
+    vertices: [GeoPoint; MAX_VERTICES],
+    count: usize,
+    /// radius of the cylinder (m)
+    radius: Option<f32>,
+}
+impl Zone {
+    /// polygon of the vertices (in order, either direction)
+    pub fn polygon(kind: ZoneKind, vertices: &[GeoPoint]) -> Result<Self, &'static str> {
+        if vertices.len() < 3 {
+            return Err("polygon requires 3 vertices");
+        }
+        if vertices.len() > MAX_VERTICES {
+            return Err("too many vertices");
+        }
+        let mut zone = Zone {
+            kind,
+            vertices: [GeoPoint::default(); MAX_VERTICES],
+            count: vertices.len(),
+            radius: None,
+        };
+        zone.vertices[..vertices.len()].copy_from_slice(vertices);
+        Ok(zone)
+    }
+
+    /// radius (m) about the center
+    pub fn cylinder(kind: ZoneKind, center: GeoPoint, radius: f32) -> Self {
+        let mut vertices = [GeoPoint::default(); MAX_VERTICES];
+        vertices[0] = center;
+        Zone {
+            kind,
+            vertices,
+            count: 1,
+            radius: Some(radius),
+        }
+    }
+
+    /// vertices of the polygon, or the center of the cylinder
+    pub fn vertices(&self) -> &[GeoPoint] {
+        &self.vertices[..self.count]
+    }
+
+    /// radius of the cylinder (m), None for a polygon
+    pub fn radius(&self) -> Option<f32> {
+        self.radius
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreachAction {
+    /// hold the position
+    Hold,
+    /// return to home
+    Return,
+    Land,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeofenceConfig {
+    /// minimum height above home (m)
+    pub min_altitude: Option<f32>,
+    /// maximum height above home (m)
+    pub max_altitude: Option<f32>,
+    pub action: BreachAction,
+    /// prediction of the position per the velocity (seconds)
+    pub lookahead: f32,
+}
+impl Default for GeofenceConfig {
+    fn default() -> Self {
+        GeofenceConfig {
+            min_altitude: None,
+            max_altitude: Some(120.0),
+            action: BreachAction::Hold,
+            lookahead: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Breach {
+    AboveMaximum,
+    BelowMinimum,
+    /// outside of the inclusion zones
+    Outside,
+    /// within the exclusion zone
+    Excluded(usize),
+}
+impl Breach {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Breach::AboveMaximum => "above maximum altitude",
+            Breach::BelowMinimum => "below minimum altitude",
+            Breach::Outside => "outside geofence",
+            Breach::Excluded(_) => "within exclusion zone",
+        }
+    }
+}
+
+/// zone navigated relative to the origin (NED, m)
+#[derive(Debug, Clone, Copy)]
+enum Area {
+    Polygon {
+        vertices: [(f32, f32); MAX_VERTICES],
+        count: usize,
+    },
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+}
+impl Area {
+    fn new(zone: &Zone, origin: &GeoPoint) -> Self {
+        let ned = |point: &GeoPoint| {
+            let v = point.to_ned(origin);
+            (v.x, v.y)
+        };
+        match zone.radius {
+            Some(radius) => Area::Circle {
+                center: ned(&zone.vertices[0]),
+                radius,
+            },
+            None => {
+                let mut vertices = [(0.0, 0.0); MAX_VERTICES];
+                for (v, vertex) in vertices.iter_mut().zip(zone.vertices()) {
+                    *v = ned(vertex);
+                }
+                Area::Polygon {
+                    vertices,
+                    count: zone.count,
+                }
+            }
+        }
+    }
+
+    /// horizontal position (north, east) within the area
+    fn contains(&self, (x, y): (f32, f32)) -> bool {
+        match self {
+            Area::Polygon { vertices, count } => {
+                // ray casting (crossings of the edges east of the point)
+                let vertices = &vertices[..*count];
+                let mut inside = false;
+                let mut j = count - 1;
+                for (i, &(xi, yi)) in vertices.iter().enumerate() {
+                    let (xj, yj) = vertices[j];
+                    if (xi > x) != (xj > x) && y < (yj - yi) * (x - xi) / (xj - xi) + yi {
+                        inside = !inside;
+                    }
+                    j = i;
+                }
+                inside
+            }
+            Area::Circle { center, radius } => {
+                let (dx, dy) = (x - center.0, y - center.1);
+                dx * dx + dy * dy <= radius * radius
+            }
+        }
+    }
+}
+
+pub struct Geofence {
+    pub config: GeofenceConfig,
+    zones: [Option<Zone>; MAX_ZONES],
+    /// zones navigated relative to the origin
+    areas: [Option<Area>; MAX_ZONES],
+    origin: Option<GeoPoint>,
+}
+
+impl Geofence {
+    pub fn new(config: GeofenceConfig) -> Self {
+        Geofence {
+            config,
+            zones: [None; MAX_ZONES],
+            areas: [None; MAX_ZONES],
+            origin: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.zones = [None; MAX_ZONES];
+        self.areas = [None; MAX_ZONES];
+    }
+
+    pub fn add(&mut self, zone: Zone) -> Result<(), &'static str> {
+        let index = self
+            .zones
+            .iter()
+            .position(Option::is_none)
+            .ok_or("geofence full")?;
+        self.zones[index] = Some(zone);
+        self.areas[index] = self.origin.map(|origin| Area::new(&zone, &origin));
+        Ok(())
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = &Zone> {
+        self.zones.iter().flatten()
+    }
+
+    /// navigate the zones relative to the origin
+    fn navigate(&mut self, origin: &GeoPoint) {
+        if self.origin == Some(*origin) {
+            return;
+        }
+        for (area, zone) in self.areas.iter_mut().zip(&self.zones) {
+            *area = zone.map(|zone| Area::new(&zone, origin));
+        }
+        self.origin = Some(*origin);
+    }
+
+    /// breach of the position (NED, m relative to the origin) or of the
+    ///     position predicted by the velocity (NED, m/s), where home is the
+    ///     position (NED) of the altitude reference
+    pub fn check(
+        &mut self,
+        origin: &GeoPoint,
+        home: &Vector3,
+        position: &Vector3,
+        velocity: &Vector3,
+    ) -> Option<Breach> {
+        self.navigate(origin);
+        let predicted = *position + *velocity * self.config.lookahead;
+        self.breach(home, position)
+            .or_else(|| self.breach(home, &predicted))
+    }
+
+    fn breach(&self, home: &Vector3, position: &Vector3) -> Option<Breach> {
+        let config = &self.config;
+        let height = home.z - position.z;
+        if config.max_altitude.is_some_and(|max| height > max) {
+            return Some(Breach::AboveMaximum);
+        }
+        if config.min_altitude.is_some_and(|min| height < min) {
+            return Some(Breach::BelowMinimum);
+        }
+
+        let point = (position.x, position.y);
+        let mut inclusions = false;
+        let mut included = false;
+        for (index, (zone, area)) in self.zones.iter().zip(&self.areas).enumerate() {
+            let (Some(zone), Some(area)) = (zone, area) else {
+                continue;
+            };
+            match zone.kind {
+                ZoneKind::Exclusion if area.contains(point) => {
+                    return Some(Breach::Excluded(index));
+                }
+                ZoneKind::Exclusion => {}
+                ZoneKind::Inclusion => {
+                    inclusions = true;
+                    included |= area.contains(point);
+                }
+            }
+        }
+        if inclusions && !included {
+            return Some(Breach::Outside);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: GeoPoint = GeoPoint {
+        latitude: 47.6,
+        longitude: -122.3,
+        altitude: 100.0,
+    };
+    const HOME: Vector3 = Vector3::ZERO;
+    const STILL: Vector3 = Vector3::ZERO;
+
+    fn point(north: f32, east: f32) -> GeoPoint {
+        GeoPoint::from_ned(&ORIGIN, &Vector3::new(north, east, 0.0))
+    }
+
+    /// position at the height (m) above home
+    fn at(north: f32, east: f32, height: f32) -> Vector3 {
+        Vector3::new(north, east, -height)
+    }
+
+    fn fence(config: GeofenceConfig, zones: &[Zone]) -> Geofence {
+        let mut fence = Geofence::new(config);
+        for zone in zones {
+            fence.add(*zone).unwrap();
+        }
+        fence
+    }
+
+    #[test]
+    fn limits_altitude() {
+        let mut fence = fence(
+            GeofenceConfig {
+                min_altitude: Some(2.0),
+                max_altitude: Some(50.0),
+                ..Default::default()
+            },
+            &[],
+        );
+        let mut check = |position: Vector3, velocity: Vector3| {
+            fence.check(&ORIGIN, &HOME, &position, &velocity)
+        };
+        assert_eq!(check(at(0.0, 0.0, 10.0), STILL), None);
+        assert_eq!(check(at(0.0, 0.0, 51.0), STILL), Some(Breach::AboveMaximum));
+        assert_eq!(check(at(0.0, 0.0, 1.0), STILL), Some(Breach::BelowMinimum));
+        // climbing toward the maximum (within the lookahead)
+        assert_eq!(
+            check(at(0.0, 0.0, 48.0), Vector3::new(0.0, 0.0, -3.0)),
+            Some(Breach::AboveMaximum)
+        );
+        // relative to home
+        let home = Vector3::new(0.0, 0.0, -20.0);
+        assert_eq!(
+            fence.check(&ORIGIN, &home, &at(0.0, 0.0, 60.0), &STILL),
+            None
+        );
+    }
+
+    #[test]
+    fn remains_within_polygon() {
+        // concave (L shaped) inclusion
+        let vertices = [
+            point(0.0, 0.0),
+            point(100.0, 0.0),
+            point(100.0, 50.0),
+            point(50.0, 50.0),
+            point(50.0, 100.0),
+            point(0.0, 100.0),
+        ];
+        let zone = Zone::polygon(ZoneKind::Inclusion, &vertices).unwrap();
+        let mut fence = fence(GeofenceConfig::default(), &[zone]);
+        let mut check =
+            |north: f32, east: f32| fence.check(&ORIGIN, &HOME, &at(north, east, 10.0), &STILL);
+        assert_eq!(check(25.0, 25.0), None);
+        assert_eq!(check(90.0, 25.0), None);
+        assert_eq!(check(25.0, 90.0), None);
+        // within the notch of the L
+        assert_eq!(check(75.0, 75.0), Some(Breach::Outside));
+        assert_eq!(check(-1.0, 25.0), Some(Breach::Outside));
+        assert_eq!(check(25.0, 101.0), Some(Breach::Outside));
+    }
+
+    #[test]
+    fn avoids_exclusion_cylinder() {
+        let inclusion = Zone::cylinder(ZoneKind::Inclusion, point(0.0, 0.0), 200.0);
+        let exclusion = Zone::cylinder(ZoneKind::Exclusion, point(50.0, 0.0), 10.0);
+        let mut fence = fence(GeofenceConfig::default(), &[inclusion, exclusion]);
+        let mut check = |position: Vector3, velocity: Vector3| {
+            fence.check(&ORIGIN, &HOME, &position, &velocity)
+        };
+        assert_eq!(check(at(30.0, 0.0, 10.0), STILL), None);
+        assert_eq!(check(at(45.0, 5.0, 10.0), STILL), Some(Breach::Excluded(1)));
+        // approaching at 5m/s
+        assert_eq!(
+            check(at(36.0, 0.0, 10.0), Vector3::new(5.0, 0.0, 0.0)),
+            Some(Breach::Excluded(1))
+        );
+        // departing
+        assert_eq!(
+            check(at(36.0, 0.0, 10.0), Vector3::new(-5.0, 0.0, 0.0)),
+            None
+        );
+        assert_eq!(check(at(0.0, 201.0, 10.0), STILL), Some(Breach::Outside));
+    }
+
+    #[test]
+    fn includes_union_of_zones() {
+        let west = Zone::cylinder(ZoneKind::Inclusion, point(0.0, -30.0), 20.0);
+        let east = Zone::cylinder(ZoneKind::Inclusion, point(0.0, 30.0), 20.0);
+        let mut fence = fence(GeofenceConfig::default(), &[west, east]);
+        let mut check = |east: f32| fence.check(&ORIGIN, &HOME, &at(0.0, east, 10.0), &STILL);
+        assert_eq!(check(-30.0), None);
+        assert_eq!(check(30.0), None);
+        assert_eq!(check(0.0), Some(Breach::Outside));
+    }
+
+    #[test]
+    fn navigates_relative_to_origin() {
+        let zone = Zone::cylinder(ZoneKind::Exclusion, point(100.0, 0.0), 10.0);
+        let mut fence = fence(GeofenceConfig::default(), &[zone]);
+        assert_eq!(
+            fence.check(&ORIGIN, &HOME, &at(100.0, 0.0, 10.0), &STILL),
+            Some(Breach::Excluded(0))
+        );
+        // origin 100m north - the zone is at the origin
+        let origin = point(100.0, 0.0);
+        assert_eq!(
+            fence.check(&origin, &HOME, &at(0.0, 0.0, 10.0), &STILL),
+            Some(Breach::Excluded(0))
+        );
+        assert_eq!(
+            fence.check(&origin, &HOME, &at(100.0, 0.0, 10.0), &STILL),
+            None
+        );
+    }
+
+    #[test]
+    fn validates_zones() {
+        assert_eq!(
+            Zone::polygon(ZoneKind::Inclusion, &[point(0.0, 0.0), point(1.0, 0.0)]),
+            Err("polygon requires 3 vertices")
+        );
+        assert_eq!(
+            Zone::polygon(ZoneKind::Inclusion, &[point(0.0, 0.0); MAX_VERTICES + 1]),
+            Err("too many vertices")
+        );
+        let mut fence = Geofence::new(GeofenceConfig::default());
+        for _ in 0..MAX_ZONES {
+            fence
+                .add(Zone::cylinder(ZoneKind::Exclusion, point(0.0, 0.0), 1.0))
+                .unwrap();
+        }
+        assert_eq!(
+            fence.add(Zone::cylinder(ZoneKind::Exclusion, point(0.0, 0.0), 1.0)),
+            Err("geofence full")
+        );
+        assert_eq!(fence.zones().count(), MAX_ZONES);
+        fence.clear();
+        assert_eq!(fence.zones().count(), 0);
+    }
+}
@@ -0,0 +1,111 @@
+//! Hover thrust estimation
+//!
+//! The collective thrust that balances gravity varies with the vehicle (mass,
+//! propellers, battery voltage), so it is estimated in flight by a single
+//! state Kalman filter of the vertical acceleration resulting from the thrust
+//!     acceleration (down) = g * (1 - thrust / hover thrust)
+//! per [PX4 hover thrust estimator](https://docs.px4.io/main/en/config_mc/pid_tuning_guide_multicopter.html#thrust-curve).
+
+use crate::estimation::attitude::GRAVITY;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoverThrustConfig {
+    /// initial estimate (normalized thrust)
+    pub initial: f32,
+    /// uncertainty of the initial estimate (1 sigma)
+    pub initial_noise: f32,
+    /// drift of the hover thrust (1/s/√Hz) - i.e. as the battery discharges
+    pub process_noise: f32,
+    /// vertical acceleration noise (m/s²)
+    pub accel_noise: f32,
+    /// limits of the estimate
+    pub min: f32,
+    pub max: f32,
+}
+impl Default for HoverThrustConfig {
+    fn default() -> Self {
+        HoverThrustConfig {
+            initial: 0.5,
+            initial_noise: 0.1,
+            process_noise: 0.01,
+            accel_noise: 2.0,
+            min: 0.1,
+            max: 0.9,
+        }
+    }
+}
+
+pub struct HoverThrustEstimator {
+    pub config: HoverThrustConfig,
+    hover_thrust: f32,
+    variance: f32,
+}
+
+impl HoverThrustEstimator {
+    pub fn new(config: HoverThrustConfig) -> Self {
+        HoverThrustEstimator {
+            config,
+            hover_thrust: config.initial,
+            variance: config.initial_noise * config.initial_noise,
+        }
+    }
+
+    pub fn hover_thrust(&self) -> f32 {
+        self.hover_thrust
+    }
+
+    /// resume from a prior estimate (i.e. retained across a reset)
+    pub fn restore(&mut self, hover_thrust: f32) {
+        let config = &self.config;
+        self.hover_thrust = hover_thrust.clamp(config.min, config.max);
+        self.variance = config.initial_noise * config.initial_noise;
+    }
+
+    /// uncertainty of the estimate (1 sigma)
+    pub fn uncertainty(&self) -> f32 {
+        libm::sqrtf(self.variance)
+    }
+
+    /// update per the vertical component of the applied thrust and the
+    ///     resulting vertical acceleration (m/s², NED) - only while airborne
+    pub fn update(&mut self, thrust: f32, accel_down: f32, dt: f32) {
+        let config = &self.config;
+        self.variance += config.process_noise * config.process_noise * dt;
+        if thrust <= 0.0 {
+            return;
+        }
+
+        let h = self.hover_thrust;
+        let innovation = accel_down - GRAVITY * (1.0 - thrust / h);
+        // ∂accel/∂h
+        let jacobian = GRAVITY * thrust / (h * h);
+        let innovation_variance =
+            jacobian * jacobian * self.variance + config.accel_noise * config.accel_noise;
+        let gain = self.variance * jacobian / innovation_variance;
+
+        self.hover_thrust = (h + gain * innovation).clamp(config.min, config.max);
+        self.variance *= 1.0 - gain * jacobian;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converges_to_hover_thrust() {
+        let truth = 0.35;
+        let mut estimator = HoverThrustEstimator::new(HoverThrustConfig::default());
+        let dt = 0.001;
+        for i in 0..10_000 {
+            // thrust varying about the hover (i.e. position control)
+            let thrust = truth + 0.05 * libm::sinf(i as f32 * 0.01);
+            let noise = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let accel = GRAVITY * (1.0 - thrust / truth) + noise;
+            estimator.update(thrust, accel, dt);
+        }
+        let error = (estimator.hover_thrust() - truth).abs();
+        assert!(error < 0.01, "error {error}");
+        assert!(estimator.uncertainty() < 0.01);
+    }
+}
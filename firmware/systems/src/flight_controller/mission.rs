@@ -0,0 +1,570 @@
+//! Waypoint missions
+//!
+//! Per the user story, the user supplies waypoints (and optionally arrival
+//! deadlines) and the flight controller manages the maneuvers toward them.
+//! Each waypoint is approached (Vectoring) at its speed, attained within its
+//! acceptance radius, then held for its loiter time before the next.
+//!
+//! Deadlines (seconds since the start of the mission) determine the speed
+//! required to arrive in time - the remaining path through the waypoints less
+//! their loiter times (and the time lost decelerating upon each arrival). The
+//! approach is at the greater of the waypoint and the required speeds, where a
+//! required speed beyond the maximum speed (or an expired deadline) is
+//! reported as infeasible.
+
+use log::*;
+use rusty_robot_common::Vector3;
+
+use super::position_control::Target;
+use crate::estimation::geodetic::GeoPoint;
+
+/// capacity of a mission
+pub const MAX_WAYPOINTS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    pub position: GeoPoint,
+    /// distance considered attained (m)
+    pub acceptance_radius: f32,
+    /// duration to hold the waypoint once attained (seconds)
+    pub loiter_time: f32,
+    /// speed of the approach (m/s)
+    pub speed: f32,
+    /// arrival time (seconds since the start of the mission)
+    pub deadline: Option<f32>,
+}
+impl Waypoint {
+    /// waypoint without loiter or deadline
+    pub fn new(position: GeoPoint) -> Self {
+        Waypoint {
+            position,
+            acceptance_radius: 1.0,
+            loiter_time: 0.0,
+            speed: 3.0,
+            deadline: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MissionConfig {
+    /// limit of the approach speed (m/s) - i.e. per the position control
+    pub max_speed: f32,
+    /// change of the approach speed (m/s) that updates the target
+    pub speed_tolerance: f32,
+    /// time lost decelerating to attain a waypoint (seconds)
+    pub arrival_time: f32,
+    /// flight time kept to climb and land upon returning (seconds)
+    pub return_reserve: f32,
+}
+impl Default for MissionConfig {
+    fn default() -> Self {
+        MissionConfig {
+            max_speed: 5.0,
+            speed_tolerance: 0.25,
+            arrival_time: 1.0,
+            return_reserve: 60.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    /// not started (or stopped)
+    Idle,
+    /// vectoring toward the waypoint
+    Approaching(usize),
+    /// holding the waypoint until the time (seconds since boot)
+    Loitering { index: usize, until: f32 },
+    /// holding the final waypoint
+    Complete,
+}
+
+/// the deadline of the waypoint can't be met
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Infeasible {
+    pub index: usize,
+    /// speed required to meet the deadline (m/s, infinite once expired)
+    pub required_speed: f32,
+}
+
+pub struct Mission {
+    pub config: MissionConfig,
+    waypoints: [Waypoint; MAX_WAYPOINTS],
+    len: usize,
+    /// NED (m) of the waypoints relative to the navigation origin
+    positions: [Vector3; MAX_WAYPOINTS],
+    progress: Progress,
+    /// waypoint at which the mission (re)starts
+    next: usize,
+    /// start of the mission (seconds since boot), retained while stopped
+    ///     so that the deadlines remain relative to the first start
+    start_time: Option<f32>,
+    /// latest target of the mission
+    target: Option<Target>,
+    infeasible: Option<Infeasible>,
+}
+
+impl Mission {
+    pub fn new(config: MissionConfig) -> Self {
+        Mission {
+            config,
+            waypoints: [Waypoint::new(GeoPoint::default()); MAX_WAYPOINTS],
+            len: 0,
+            positions: [Vector3::ZERO; MAX_WAYPOINTS],
+            progress: Progress::Idle,
+            next: 0,
+            start_time: None,
+            target: None,
+            infeasible: None,
+        }
+    }
+
+    /// remove all waypoints
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.next = 0;
+        self.start_time = None;
+        self.progress = Progress::Idle;
+        self.target = None;
+        self.infeasible = None;
+    }
+
+    /// append the waypoint
+    pub fn push(&mut self, waypoint: Waypoint) -> Result<(), &'static str> {
+        if self.len >= MAX_WAYPOINTS {
+            return Err("mission full");
+        }
+        self.waypoints[self.len] = waypoint;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn waypoints(&self) -> &[Waypoint] {
+        &self.waypoints[..self.len]
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.progress,
+            Progress::Approaching(_) | Progress::Loitering { .. }
+        )
+    }
+
+    /// current waypoint (the waypoint at which the mission starts if idle)
+    pub fn index(&self) -> usize {
+        match self.progress {
+            Progress::Approaching(index) | Progress::Loitering { index, .. } => index,
+            Progress::Idle | Progress::Complete => self.next,
+        }
+    }
+
+    /// time (seconds) since the mission was first started
+    pub fn elapsed(&self, time: f32) -> Option<f32> {
+        self.start_time.map(|start_time| time - start_time)
+    }
+
+    /// start (or resume) at the waypoint, `elapsed` seconds after the mission
+    ///     was first started (i.e. per the state retained across a reset)
+    pub fn restore(&mut self, index: usize, elapsed: Option<f32>, time: f32) {
+        self.next = index;
+        self.start_time = elapsed.map(|elapsed| time - elapsed);
+    }
+
+    /// start the mission at the time (seconds since boot), where the
+    ///     waypoints are navigated relative to the origin
+    ///     a restart (after a stop) retains the time of the first start
+    pub fn start(&mut self, time: f32, origin: &GeoPoint) -> Result<(), &'static str> {
+        if self.len == 0 {
+            return Err("no waypoints");
+        }
+        if self.next >= self.len {
+            return Err("mission complete");
+        }
+        for (position, waypoint) in self.positions.iter_mut().zip(&self.waypoints[..self.len]) {
+            *position = waypoint.position.to_ned(origin);
+        }
+        info!("mission started at waypoint {}", self.next);
+        self.progress = Progress::Approaching(self.next);
+        self.start_time.get_or_insert(time);
+        self.target = None;
+        self.infeasible = None;
+        Ok(())
+    }
+
+    /// stop (i.e. overridden by a command), retaining the current waypoint
+    pub fn stop(&mut self) {
+        if self.is_running() {
+            self.next = self.index();
+            info!("mission stopped at waypoint {}", self.next);
+        }
+        self.progress = Progress::Idle;
+        self.target = None;
+    }
+
+    /// the latest deadline that can't be met
+    pub fn infeasible(&self) -> Option<Infeasible> {
+        self.infeasible
+    }
+
+    /// flight time (seconds) to return home (NED) from the position (NED)
+    pub fn return_time(&self, position: &Vector3, home: &Vector3) -> f32 {
+        let (dx, dy) = (home.x - position.x, home.y - position.y);
+        libm::sqrtf(dx * dx + dy * dy) / self.config.max_speed + self.config.return_reserve
+    }
+
+    /// the remaining flight time (seconds) only suffices to return home
+    pub fn must_return(&self, remaining: f32, position: &Vector3, home: &Vector3) -> bool {
+        remaining <= self.return_time(position, home)
+    }
+
+    /// speed (m/s) required to meet the next deadline - (waypoint, speed)
+    pub fn required_speed(&self, time: f32, position: &Vector3) -> Option<(usize, f32)> {
+        let (first, mut loiter) = match self.progress {
+            Progress::Approaching(index) => (index, 0.0),
+            Progress::Loitering { index, until } => (index + 1, (until - time).max(0.0)),
+            Progress::Idle | Progress::Complete => return None,
+        };
+        let elapsed = self.elapsed(time).unwrap_or(0.0);
+        let mut distance = 0.0;
+        let mut arrivals = 0.0;
+        let mut from = *position;
+        for index in first..self.len {
+            distance += (self.positions[index] - from).norm();
+            from = self.positions[index];
+            arrivals += self.config.arrival_time;
+            let waypoint = &self.waypoints[index];
+            if let Some(deadline) = waypoint.deadline {
+                let available = deadline - elapsed - loiter;
+                let speed = if available > arrivals {
+                    distance / (available - arrivals)
+                } else if available > 0.0 {
+                    // decelerating within the arrival
+                    distance / available
+                } else {
+                    f32::INFINITY
+                };
+                return Some((index, speed));
+            }
+            loiter += waypoint.loiter_time;
+        }
+        None
+    }
+
+    /// progress per the position (NED) at the time (seconds since boot)
+    ///     returns the target once changed
+    pub fn update(&mut self, time: f32, position: &Vector3) -> Option<Target> {
+        match self.progress {
+            Progress::Approaching(index) => {
+                let waypoint = &self.waypoints[index];
+                if (self.positions[index] - *position).norm() <= waypoint.acceptance_radius {
+                    info!("waypoint {index} attained");
+                    self.progress = Progress::Loitering {
+                        index,
+                        until: time + waypoint.loiter_time,
+                    };
+                }
+            }
+            Progress::Loitering { index, until } => {
+                if time >= until {
+                    if index + 1 < self.len {
+                        self.progress = Progress::Approaching(index + 1);
+                    } else {
+                        info!("mission complete");
+                        self.next = self.len;
+                        self.progress = Progress::Complete;
+                    }
+                }
+            }
+            Progress::Idle | Progress::Complete => return None,
+        }
+
+        let target = match self.progress {
+            Progress::Approaching(index) => {
+                let required = self.required_speed(time, position);
+                self.assess(required);
+                let speed = required
+                    .map_or(0.0, |(_, speed)| speed)
+                    .max(self.waypoints[index].speed)
+                    .min(self.config.max_speed);
+                Target::Waypoint {
+                    position: self.positions[index],
+                    speed,
+                }
+            }
+            Progress::Loitering { index, .. } => Target::Position(self.positions[index]),
+            Progress::Complete => Target::Position(self.positions[self.len - 1]),
+            Progress::Idle => return None,
+        };
+        let changed = match (self.target, target) {
+            (
+                Some(Target::Waypoint {
+                    position: previous,
+                    speed: previous_speed,
+                }),
+                Target::Waypoint { position, speed },
+            ) => {
+                previous != position || (speed - previous_speed).abs() > self.config.speed_tolerance
+            }
+            (previous, target) => previous != Some(target),
+        };
+        if !changed {
+            return None;
+        }
+        self.target = Some(target);
+        Some(target)
+    }
+
+    /// track the feasibility of the next deadline
+    fn assess(&mut self, required: Option<(usize, f32)>) {
+        let infeasible = required
+            .filter(|(_, speed)| *speed > self.config.max_speed)
+            .map(|(index, required_speed)| Infeasible {
+                index,
+                required_speed,
+            });
+        match (self.infeasible, infeasible) {
+            (None, Some(infeasible)) => warn!(
+                "deadline of waypoint {} infeasible [requires {} m/s]",
+                infeasible.index, infeasible.required_speed
+            ),
+            (Some(_), None) => info!("deadline feasible"),
+            _ => {}
+        }
+        self.infeasible = infeasible;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flight_controller::position_control::{PositionController, PositionGains};
+
+    const DT: f32 = 0.1;
+
+    const ORIGIN: GeoPoint = GeoPoint {
+        latitude: 47.6,
+        longitude: -122.3,
+        altitude: 100.0,
+    };
+
+    /// waypoint at the NED (m) relative to the origin
+    fn waypoint(north: f32, east: f32, down: f32) -> Waypoint {
+        Waypoint::new(GeoPoint::from_ned(
+            &ORIGIN,
+            &Vector3::new(north, east, down),
+        ))
+    }
+
+    /// vehicle attaining the velocity setpoints of the targets
+    struct Vehicle {
+        control: PositionController,
+        target: Option<Target>,
+        position: Vector3,
+        time: f32,
+    }
+    impl Vehicle {
+        fn new() -> Self {
+            Vehicle {
+                control: PositionController::new(PositionGains {
+                    max_speed: 8.0,
+                    ..Default::default()
+                }),
+                target: None,
+                position: Vector3::new(0.0, 0.0, -10.0),
+                time: 0.0,
+            }
+        }
+
+        fn step(&mut self, mission: &mut Mission) {
+            self.time += DT;
+            if let Some(target) = mission.update(self.time, &self.position) {
+                self.target = Some(target);
+            }
+            if let Some(target) = &self.target {
+                self.position += self.control.velocity_setpoint(target, &self.position) * DT;
+            }
+        }
+    }
+
+    #[test]
+    fn flies_waypoints() {
+        let mut mission = Mission::new(MissionConfig::default());
+        let loiter = Waypoint {
+            loiter_time: 5.0,
+            ..waypoint(20.0, 0.0, -10.0)
+        };
+        for w in [
+            loiter,
+            waypoint(20.0, 20.0, -15.0),
+            waypoint(0.0, 0.0, -10.0),
+        ] {
+            mission.push(w).unwrap();
+        }
+        let mut vehicle = Vehicle::new();
+        mission.start(vehicle.time, &ORIGIN).unwrap();
+        assert_eq!(mission.progress(), Progress::Approaching(0));
+
+        let mut loitered = 0.0;
+        let mut sequence = [0; 3];
+        while mission.progress() != Progress::Complete && vehicle.time < 120.0 {
+            vehicle.step(&mut mission);
+            match mission.progress() {
+                Progress::Loitering { index: 0, .. } => loitered += DT,
+                Progress::Approaching(index) => sequence[index] += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(mission.progress(), Progress::Complete);
+        assert!((loitered - 5.0).abs() < 2.0 * DT, "loitered {loitered}");
+        assert!(sequence.iter().all(|steps| *steps > 0));
+        assert!((vehicle.position - Vector3::new(0.0, 0.0, -10.0)).norm() <= 1.0);
+        assert_eq!(mission.index(), 3);
+        assert_eq!(
+            mission.start(vehicle.time, &ORIGIN),
+            Err("mission complete")
+        );
+    }
+
+    #[test]
+    fn speeds_up_to_meet_deadline() {
+        let mut mission = Mission::new(MissionConfig {
+            max_speed: 8.0,
+            ..Default::default()
+        });
+        let first = Waypoint {
+            loiter_time: 10.0,
+            ..waypoint(50.0, 0.0, -10.0)
+        };
+        let second = Waypoint {
+            deadline: Some(30.0),
+            ..waypoint(100.0, 0.0, -10.0)
+        };
+        mission.push(first).unwrap();
+        mission.push(second).unwrap();
+        let mut vehicle = Vehicle::new();
+        mission.start(vehicle.time, &ORIGIN).unwrap();
+
+        // 100m within 30 seconds less the 10 seconds of loiter (and arrivals)
+        let expected = 100.0 / (30.0 - 10.0 - 2.0 * mission.config.arrival_time);
+        let (index, speed) = mission
+            .required_speed(vehicle.time, &vehicle.position)
+            .unwrap();
+        assert_eq!(index, 1);
+        assert!((speed - expected).abs() < 1e-2, "speed {speed}");
+        vehicle.step(&mut mission);
+        let Some(Target::Waypoint { speed, .. }) = vehicle.target else {
+            panic!("{:?}", vehicle.target);
+        };
+        assert!((speed - expected).abs() < 0.1, "speed {speed}");
+
+        let mut arrival = None;
+        while vehicle.time < 60.0 {
+            vehicle.step(&mut mission);
+            if arrival.is_none()
+                && matches!(mission.progress(), Progress::Loitering { index: 1, .. })
+            {
+                arrival = Some(vehicle.time);
+            }
+        }
+        let arrival = arrival.unwrap();
+        assert!(arrival <= 30.0, "arrival {arrival}");
+        assert_eq!(mission.infeasible(), None);
+    }
+
+    #[test]
+    fn reports_infeasible_deadline() {
+        let mut mission = Mission::new(MissionConfig::default());
+        let far = Waypoint {
+            deadline: Some(10.0),
+            ..waypoint(100.0, 0.0, -10.0)
+        };
+        mission.push(far).unwrap();
+        let mut vehicle = Vehicle::new();
+        mission.start(vehicle.time, &ORIGIN).unwrap();
+        vehicle.step(&mut mission);
+        let infeasible = mission.infeasible().unwrap();
+        assert_eq!(infeasible.index, 0);
+        assert!(infeasible.required_speed > 9.0);
+        // approached at the maximum speed regardless
+        assert!(matches!(
+            vehicle.target,
+            Some(Target::Waypoint { speed, .. }) if speed == mission.config.max_speed
+        ));
+
+        // expired
+        while vehicle.time < 12.0 {
+            vehicle.step(&mut mission);
+        }
+        assert_eq!(
+            mission.infeasible().map(|i| i.required_speed),
+            Some(f32::INFINITY)
+        );
+    }
+
+    #[test]
+    fn returns_per_remaining_flight_time() {
+        let mission = Mission::new(MissionConfig::default());
+        let home = Vector3::new(0.0, 0.0, -2.0);
+        // 500m at 5 m/s plus the reserve
+        let position = Vector3::new(300.0, 400.0, -30.0);
+        assert_eq!(mission.return_time(&position, &home), 160.0);
+        assert!(!mission.must_return(300.0, &position, &home));
+        assert!(mission.must_return(150.0, &position, &home));
+        assert!(!mission.must_return(150.0, &home, &home));
+    }
+
+    #[test]
+    fn resumes_at_waypoint() {
+        let mut mission = Mission::new(MissionConfig::default());
+        assert_eq!(mission.start(0.0, &ORIGIN), Err("no waypoints"));
+        for i in 0..MAX_WAYPOINTS {
+            mission.push(waypoint(i as f32, 0.0, -10.0)).unwrap();
+        }
+        assert_eq!(mission.push(waypoint(0.0, 0.0, -10.0)), Err("mission full"));
+
+        // i.e. retained across a reset
+        mission.restore(5, None, 0.0);
+        mission.start(0.0, &ORIGIN).unwrap();
+        assert_eq!(mission.progress(), Progress::Approaching(5));
+        let target = mission.update(0.1, &Vector3::new(0.0, 0.0, -10.0));
+        assert!(matches!(
+            target,
+            Some(Target::Waypoint { position, .. }) if (position.x - 5.0).abs() < 1e-3
+        ));
+        // unchanged target isn't repeated
+        assert_eq!(mission.update(0.2, &Vector3::new(0.0, 0.0, -10.0)), None);
+
+        mission.stop();
+        assert_eq!(mission.progress(), Progress::Idle);
+        assert_eq!(mission.index(), 5);
+        assert_eq!(mission.update(0.3, &Vector3::ZERO), None);
+    }
+
+    #[test]
+    fn retains_start_time() {
+        let mut mission = Mission::new(MissionConfig::default());
+        mission.push(waypoint(50.0, 0.0, -10.0)).unwrap();
+        assert_eq!(mission.elapsed(1.0), None);
+        mission.start(1.0, &ORIGIN).unwrap();
+        // deadlines remain relative to the first start
+        mission.stop();
+        mission.start(11.0, &ORIGIN).unwrap();
+        assert_eq!(mission.elapsed(21.0), Some(20.0));
+
+        // i.e. retained across a reset (time restarts upon boot)
+        let elapsed = mission.elapsed(21.0);
+        let mut resumed = Mission::new(MissionConfig::default());
+        resumed.push(waypoint(50.0, 0.0, -10.0)).unwrap();
+        resumed.restore(mission.index(), elapsed, 0.0);
+        resumed.start(0.0, &ORIGIN).unwrap();
+        assert_eq!(resumed.elapsed(5.0), Some(25.0));
+
+        mission.clear();
+        assert_eq!(mission.elapsed(21.0), None);
+    }
+}
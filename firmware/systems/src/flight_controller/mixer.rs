@@ -0,0 +1,430 @@
+//! Motor mixer
+//!
+//! Maps roll/pitch/yaw/thrust demands onto motor outputs per the geometry of
+//! the vehicle.
+//!
+//! Body frame is FRD (x forward, y right, z down)
+//!     * roll - positive rolls right (right side down)
+//!     * pitch - positive pitches nose up
+//!     * yaw - positive yaws nose right (clockwise viewed from above)
+//!
+//! Motors can't provide every demand at once (outputs are limited to 0.0-1.0),
+//! so the mixer prioritizes (highest first)
+//!     1. roll/pitch - leaving `yaw_headroom` of the output range for yaw
+//!     2. yaw
+//!     3. thrust - unless airmode is disabled, in which case attitude authority
+//!        is reduced so that thrust is met
+
+use core::f32::consts::FRAC_1_SQRT_2;
+
+/// contribution of each demand to a motor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotorFactors {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub thrust: f32,
+}
+
+/// motor at (x, y) of the unit circle (body frame) with spin direction
+///     viewed from above, counter-clockwise spinning props yaw the vehicle clockwise
+const fn rotor(x: f32, y: f32, ccw: bool) -> MotorFactors {
+    MotorFactors {
+        roll: -y,
+        pitch: x,
+        yaw: if ccw { 1.0 } else { -1.0 },
+        thrust: 1.0,
+    }
+}
+
+const SIN_22_5: f32 = 0.382_683_43;
+const COS_22_5: f32 = 0.923_879_5;
+const SIN_30: f32 = 0.5;
+const COS_30: f32 = 0.866_025_4;
+
+/// mixing factors of the motors (in motor output order)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry<const N: usize> {
+    pub motors: [MotorFactors; N],
+}
+
+impl<const N: usize> Geometry<N> {
+    /// custom geometry
+    ///     factors are normalized so that a full demand spans the output range
+    pub fn new(motors: [MotorFactors; N]) -> Self {
+        let max = |f: fn(&MotorFactors) -> f32| motors.iter().map(f).fold(0.0_f32, f32::max);
+        let roll = max(|m| m.roll.abs());
+        let pitch = max(|m| m.pitch.abs());
+        let yaw = max(|m| m.yaw.abs());
+        let thrust = max(|m| m.thrust);
+        let scale = |v: f32, max: f32, to: f32| if max > 0.0 { v * to / max } else { 0.0 };
+        Geometry {
+            motors: motors.map(|m| MotorFactors {
+                roll: scale(m.roll, roll, 0.5),
+                pitch: scale(m.pitch, pitch, 0.5),
+                yaw: scale(m.yaw, yaw, 0.5),
+                thrust: scale(m.thrust, thrust, 1.0),
+            }),
+        }
+    }
+}
+
+impl Geometry<4> {
+    /// front-right (ccw), rear-left (ccw), front-left (cw), rear-right (cw)
+    pub fn quad_x() -> Self {
+        Self::new([
+            rotor(FRAC_1_SQRT_2, FRAC_1_SQRT_2, true),
+            rotor(-FRAC_1_SQRT_2, -FRAC_1_SQRT_2, true),
+            rotor(FRAC_1_SQRT_2, -FRAC_1_SQRT_2, false),
+            rotor(-FRAC_1_SQRT_2, FRAC_1_SQRT_2, false),
+        ])
+    }
+
+    /// right (ccw), left (ccw), front (cw), rear (cw)
+    pub fn quad_plus() -> Self {
+        Self::new([
+            rotor(0.0, 1.0, true),
+            rotor(0.0, -1.0, true),
+            rotor(1.0, 0.0, false),
+            rotor(-1.0, 0.0, false),
+        ])
+    }
+}
+
+impl Geometry<6> {
+    /// clockwise (viewed from above) from front-right (ccw), alternating spin
+    pub fn hex_x() -> Self {
+        Self::new([
+            rotor(COS_30, SIN_30, true),
+            rotor(0.0, 1.0, false),
+            rotor(-COS_30, SIN_30, true),
+            rotor(-COS_30, -SIN_30, false),
+            rotor(0.0, -1.0, true),
+            rotor(COS_30, -SIN_30, false),
+        ])
+    }
+
+    /// clockwise (viewed from above) from front (ccw), alternating spin
+    pub fn hex_plus() -> Self {
+        Self::new([
+            rotor(1.0, 0.0, true),
+            rotor(SIN_30, COS_30, false),
+            rotor(-SIN_30, COS_30, true),
+            rotor(-1.0, 0.0, false),
+            rotor(-SIN_30, -COS_30, true),
+            rotor(SIN_30, -COS_30, false),
+        ])
+    }
+}
+
+impl Geometry<8> {
+    /// clockwise (viewed from above) from front-right (ccw), alternating spin
+    pub fn octo_x() -> Self {
+        Self::new([
+            rotor(COS_22_5, SIN_22_5, true),
+            rotor(SIN_22_5, COS_22_5, false),
+            rotor(-SIN_22_5, COS_22_5, true),
+            rotor(-COS_22_5, SIN_22_5, false),
+            rotor(-COS_22_5, -SIN_22_5, true),
+            rotor(-SIN_22_5, -COS_22_5, false),
+            rotor(SIN_22_5, -COS_22_5, true),
+            rotor(COS_22_5, -SIN_22_5, false),
+        ])
+    }
+
+    /// coaxial quad X (X8) - top rotors per quad_x() followed by the bottom
+    /// rotors (of the same arms) spinning opposite
+    pub fn coaxial_x8() -> Self {
+        let top = Geometry::quad_x().motors;
+        let bottom = top.map(|m| MotorFactors { yaw: -m.yaw, ..m });
+        Self::new([
+            top[0], top[1], top[2], top[3], bottom[0], bottom[1], bottom[2], bottom[3],
+        ])
+    }
+}
+
+/// demanded torques (-1.0 - 1.0) and thrust (0.0 - 1.0)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Demands {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    pub thrust: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MixerConfig {
+    /// maintain attitude authority by adjusting thrust (i.e. at zero throttle)
+    pub airmode: bool,
+    /// fraction of the output range reserved for yaw (0.0 - 1.0)
+    pub yaw_headroom: f32,
+}
+impl Default for MixerConfig {
+    fn default() -> Self {
+        MixerConfig {
+            airmode: true,
+            yaw_headroom: 0.15,
+        }
+    }
+}
+
+/// motor outputs of the demands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mix<const N: usize> {
+    /// normalized (0.0 - 1.0) motor commands
+    pub outputs: [f32; N],
+    /// demands were reduced to fit the output range
+    pub saturated: bool,
+}
+
+pub struct Mixer<const N: usize> {
+    pub geometry: Geometry<N>,
+    pub config: MixerConfig,
+}
+
+/// (min, max) of the values
+fn bounds(values: &[f32]) -> (f32, f32) {
+    values
+        .iter()
+        .fold((f32::MAX, f32::MIN), |(lo, hi), v| (lo.min(*v), hi.max(*v)))
+}
+
+impl<const N: usize> Mixer<N> {
+    pub fn new(geometry: Geometry<N>, config: MixerConfig) -> Self {
+        Mixer { geometry, config }
+    }
+
+    pub fn mix(&self, demands: &Demands) -> Mix<N> {
+        let roll = demands.roll.clamp(-1.0, 1.0);
+        let pitch = demands.pitch.clamp(-1.0, 1.0);
+        let yaw = demands.yaw.clamp(-1.0, 1.0);
+        let thrust = demands.thrust.clamp(0.0, 1.0);
+        let motors = &self.geometry.motors;
+        let mut saturated = false;
+
+        // roll/pitch (limited to leave yaw its headroom)
+        let mut rp = motors.map(|m| roll * m.roll + pitch * m.pitch);
+        let yaw_only = motors.map(|m| yaw * m.yaw);
+        let (lo, hi) = bounds(&yaw_only);
+        let yaw_range = hi - lo;
+        let rp_limit = 1.0 - yaw_range.min(self.config.yaw_headroom.clamp(0.0, 1.0));
+        let (lo, hi) = bounds(&rp);
+        let rp_range = hi - lo;
+        if rp_range > rp_limit {
+            saturated = true;
+            let scale = rp_limit / rp_range;
+            rp.iter_mut().for_each(|v| *v *= scale);
+        }
+
+        // yaw (limited to the remaining range)
+        let mut attitude = [0.0; N];
+        for i in 0..N {
+            attitude[i] = rp[i] + yaw_only[i];
+        }
+        let (lo, hi) = bounds(&attitude);
+        if hi - lo > 1.0 {
+            saturated = true;
+            let yaw_scale = ((1.0 - rp_range.min(rp_limit)) / yaw_range).clamp(0.0, 1.0);
+            for i in 0..N {
+                attitude[i] = rp[i] + yaw_scale * yaw_only[i];
+            }
+        }
+
+        // thrust
+        let (lo, hi) = bounds(&attitude);
+        let mut outputs = [0.0; N];
+        if self.config.airmode {
+            // shift thrust to keep full attitude authority
+            let shifted = thrust.clamp(-lo, (1.0 - hi).max(-lo));
+            saturated |= shifted != thrust;
+            for i in 0..N {
+                outputs[i] = shifted * motors[i].thrust + attitude[i];
+            }
+        } else {
+            // reduce attitude authority to meet thrust
+            let mut scale: f32 = 1.0;
+            if thrust + lo < 0.0 {
+                scale = scale.min(thrust / -lo);
+            }
+            if thrust + hi > 1.0 {
+                scale = scale.min((1.0 - thrust) / hi);
+            }
+            saturated |= scale < 1.0;
+            for i in 0..N {
+                outputs[i] = thrust * motors[i].thrust + scale * attitude[i];
+            }
+        }
+
+        Mix {
+            outputs: outputs.map(|o| o.clamp(0.0, 1.0)),
+            saturated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_outputs<const N: usize>(actual: [f32; N], expected: [f32; N]) {
+        for i in 0..N {
+            assert!(
+                (actual[i] - expected[i]).abs() < EPSILON,
+                "motor {i}: {actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hovers() {
+        let mixer = Mixer::new(Geometry::quad_x(), MixerConfig::default());
+        let mix = mixer.mix(&Demands {
+            thrust: 0.5,
+            ..Default::default()
+        });
+        assert_outputs(mix.outputs, [0.5; 4]);
+        assert!(!mix.saturated);
+    }
+
+    #[test]
+    fn quad_x_directions() {
+        let mixer = Mixer::new(Geometry::quad_x(), MixerConfig::default());
+        let demand = |roll, pitch, yaw| {
+            mixer
+                .mix(&Demands {
+                    roll,
+                    pitch,
+                    yaw,
+                    thrust: 0.5,
+                })
+                .outputs
+        };
+        // roll right - left motors (rear-left, front-left) increase
+        assert_outputs(demand(0.2, 0.0, 0.0), [0.4, 0.6, 0.6, 0.4]);
+        // pitch up - front motors (front-right, front-left) increase
+        assert_outputs(demand(0.0, 0.2, 0.0), [0.6, 0.4, 0.6, 0.4]);
+        // yaw right - ccw motors (front-right, rear-left) increase
+        assert_outputs(demand(0.0, 0.0, 0.2), [0.6, 0.6, 0.4, 0.4]);
+    }
+
+    #[test]
+    fn reserves_yaw_headroom() {
+        let mixer = Mixer::new(
+            Geometry::quad_x(),
+            MixerConfig {
+                airmode: true,
+                yaw_headroom: 0.2,
+            },
+        );
+        let mix = mixer.mix(&Demands {
+            roll: 1.0,
+            yaw: 1.0,
+            thrust: 0.5,
+            ..Default::default()
+        });
+        assert!(mix.saturated);
+        // roll limited to 80% of the range, yaw provided the remaining 20%
+        let (lo, hi) = bounds(&mix.outputs);
+        assert!((hi - lo - 1.0).abs() < EPSILON, "range not used");
+        let yaw = (mix.outputs[0] + mix.outputs[1]) - (mix.outputs[2] + mix.outputs[3]);
+        assert!(
+            (yaw - 0.4).abs() < EPSILON,
+            "yaw headroom not reserved [{yaw}]"
+        );
+    }
+
+    #[test]
+    fn airmode_provides_authority_at_zero_thrust() {
+        let demands = Demands {
+            roll: 0.2,
+            ..Default::default()
+        };
+        let airmode = Mixer::new(Geometry::quad_x(), MixerConfig::default());
+        assert_outputs(airmode.mix(&demands).outputs, [0.0, 0.2, 0.2, 0.0]);
+
+        let no_airmode = Mixer::new(
+            Geometry::quad_x(),
+            MixerConfig {
+                airmode: false,
+                ..Default::default()
+            },
+        );
+        assert_outputs(no_airmode.mix(&demands).outputs, [0.0; 4]);
+    }
+
+    #[test]
+    fn desaturates_at_full_thrust() {
+        let mixer = Mixer::new(Geometry::hex_x(), MixerConfig::default());
+        let mix = mixer.mix(&Demands {
+            pitch: 0.4,
+            thrust: 1.0,
+            ..Default::default()
+        });
+        assert!(mix.saturated);
+        let (lo, hi) = bounds(&mix.outputs);
+        assert!((hi - 1.0).abs() < EPSILON);
+        // the full pitch demand is still met
+        assert!((hi - lo - 0.4).abs() < EPSILON);
+    }
+
+    #[test]
+    fn normalizes_custom_geometry() {
+        // tricopter-like (yaw by servo, so no yaw factors)
+        let geometry = Geometry::new([
+            MotorFactors {
+                roll: -2.0,
+                pitch: 1.0,
+                yaw: 0.0,
+                thrust: 2.0,
+            },
+            MotorFactors {
+                roll: 2.0,
+                pitch: 1.0,
+                yaw: 0.0,
+                thrust: 2.0,
+            },
+            MotorFactors {
+                roll: 0.0,
+                pitch: -2.0,
+                yaw: 0.0,
+                thrust: 2.0,
+            },
+        ]);
+        assert_eq!(geometry.motors[0].roll, -0.5);
+        assert_eq!(geometry.motors[1].pitch, 0.25);
+        assert_eq!(geometry.motors[2].pitch, -0.5);
+        assert_eq!(geometry.motors[2].yaw, 0.0);
+        assert_eq!(geometry.motors[2].thrust, 1.0);
+    }
+
+    #[test]
+    fn all_geometries_hover() {
+        let hover = Demands {
+            thrust: 0.3,
+            ..Default::default()
+        };
+        let config = MixerConfig::default();
+        assert_outputs(
+            Mixer::new(Geometry::quad_plus(), config)
+                .mix(&hover)
+                .outputs,
+            [0.3; 4],
+        );
+        assert_outputs(
+            Mixer::new(Geometry::hex_plus(), config).mix(&hover).outputs,
+            [0.3; 6],
+        );
+        assert_outputs(
+            Mixer::new(Geometry::octo_x(), config).mix(&hover).outputs,
+            [0.3; 8],
+        );
+        assert_outputs(
+            Mixer::new(Geometry::coaxial_x8(), config)
+                .mix(&hover)
+                .outputs,
+            [0.3; 8],
+        );
+    }
+}
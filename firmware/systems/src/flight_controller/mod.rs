@@ -1,3 +1,16 @@
-
-
-pub mod quadcopter;
\ No newline at end of file
+pub mod arming;
+pub mod attitude_control;
+pub mod characterization;
+pub mod direct;
+pub mod failsafe;
+pub mod follow;
+pub mod geofence;
+pub mod hover_thrust;
+pub mod mission;
+pub mod mixer;
+pub mod multicopter;
+pub mod pid;
+pub mod position_control;
+pub mod rate_control;
+pub mod recovery;
+pub mod state_machine;
@@ -0,0 +1,1283 @@
+use log::*;
+use rusty_robot_common::{Quaternion, Vector3};
+use rusty_robot_drivers::battery::BatteryData;
+use rusty_robot_drivers::imu_traits::ImuData;
+use rusty_robot_drivers::motor::{MotorTelemetry, Motors};
+use rusty_robot_drivers::rc_traits::{RcCommand, RcMode};
+
+use super::arming::{Arming, ArmingConfig, Readiness, Refusals};
+use super::attitude_control::{AttitudeController, AttitudeGains, AttitudeSetpoint};
+use super::characterization::{Characterization, CharacterizationConfig, Deficiency, MotorModel};
+use super::direct::{Direct, DirectConfig};
+use super::failsafe::{BlindTouchdown, Descent, Failsafe, FailsafeConfig, Failure};
+use super::follow::{Follow, FollowConfig, Following};
+use super::geofence::{Breach, BreachAction, Geofence, GeofenceConfig, Zone};
+use super::hover_thrust::{HoverThrustConfig, HoverThrustEstimator};
+use super::mission::{Mission, MissionConfig, Progress, Waypoint};
+use super::mixer::{Demands, Geometry, Mixer, MixerConfig};
+use super::position_control::{PositionController, PositionGains, PositionSetpoint, Target};
+use super::rate_control::{RateController, RateGains};
+use super::recovery::Snapshot;
+use super::state_machine::{Active, Event, Inputs, Return, State, StateConfig, StateMachine};
+use crate::calibration::imu::{GYRO_CALIBRATED, ImuCalibration};
+use crate::estimation::attitude::{AttitudeEstimator, Filter, GRAVITY};
+use crate::estimation::battery::{Battery, BatteryConfig};
+use crate::estimation::ekf::{Ekf, EkfConfig, Health, NavState};
+use crate::estimation::geodetic::GeoPoint;
+use crate::filtering::gyro::{GyroFilter, GyroFilterConfig};
+
+/// error rate of the ESC telemetry beyond which the motor speeds are ignored
+pub const MAX_TELEMETRY_ERROR_RATE: f32 = 0.2;
+
+pub struct Config<const N: usize> {
+    pub geometry: Geometry<N>,
+    /// corrections of the IMU samples (None when the robot provides corrected
+    ///     samples, i.e. per a `CorrectedImu`)
+    pub imu_calibration: Option<ImuCalibration>,
+    pub gyro_filter: GyroFilterConfig,
+    pub mixer: MixerConfig,
+    pub attitude: Filter,
+    pub navigation: EkfConfig,
+    pub attitude_gains: AttitudeGains,
+    pub rate_gains: RateGains,
+    pub position_gains: PositionGains,
+    pub hover_thrust: HoverThrustConfig,
+    pub states: StateConfig,
+    pub arming: ArmingConfig,
+    pub failsafe: FailsafeConfig,
+    pub battery: BatteryConfig,
+    pub characterization: CharacterizationConfig,
+    pub mission: MissionConfig,
+    pub direct: DirectConfig,
+    pub follow: FollowConfig,
+    pub geofence: GeofenceConfig,
+    /// rate at which step() is called
+    pub cycle_rate_hz: u32,
+    /// rate of the IMU samples (i.e. of the gyro filtering), where slower
+    ///     than the cycle rate
+    pub imu_rate_hz: u32,
+}
+impl<const N: usize> Config<N> {
+    /// default tuning for the geometry (an IMU sample per cycle)
+    pub fn new(geometry: Geometry<N>, cycle_rate_hz: u32) -> Self {
+        Config {
+            geometry,
+            // uncalibrated, so arming is refused until calibrated
+            imu_calibration: Some(ImuCalibration::default()),
+            gyro_filter: GyroFilterConfig::default(),
+            mixer: MixerConfig::default(),
+            attitude: Filter::default(),
+            navigation: EkfConfig::default(),
+            attitude_gains: AttitudeGains::default(),
+            rate_gains: RateGains::default(),
+            position_gains: PositionGains::default(),
+            hover_thrust: HoverThrustConfig::default(),
+            states: StateConfig::default(),
+            arming: ArmingConfig::default(),
+            failsafe: FailsafeConfig::default(),
+            battery: BatteryConfig::default(),
+            characterization: CharacterizationConfig::default(),
+            mission: MissionConfig::default(),
+            direct: DirectConfig::default(),
+            follow: FollowConfig::default(),
+            geofence: GeofenceConfig::default(),
+            cycle_rate_hz,
+            imu_rate_hz: cycle_rate_hz,
+        }
+    }
+}
+
+/// source of the vectoring (Active)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Guidance {
+    /// per the commands (events)
+    Commands,
+    Mission,
+    /// trajectory commanded directly (RC/mesh)
+    Direct,
+    /// bodyguard of the user
+    Follow,
+}
+
+pub struct FlightController<'a, Robot, const N: usize>
+where
+    // all flight controllers need an imu
+    Robot: rusty_robot_drivers::imu_traits::ImuReader,
+{
+    drone: &'a Robot,
+    imu_calibration: Option<ImuCalibration>,
+    gyro_filter: GyroFilter<N>,
+    mixer: Mixer<N>,
+    estimator: AttitudeEstimator,
+    ekf: Ekf,
+    attitude_control: AttitudeController,
+    rate_control: RateController,
+    position_control: PositionController,
+    hover_thrust: HoverThrustEstimator,
+    states: StateMachine,
+    arming: Arming,
+    failsafe: Failsafe,
+    battery: Battery,
+    /// time of the latest battery measurement (seconds)
+    battery_time: Option<f32>,
+    /// the remaining flight time only suffices to return
+    depleted: bool,
+    /// touchdown detection of a descent without navigation
+    blind_touchdown: Option<BlindTouchdown>,
+    characterization: Characterization<N>,
+    mission: Mission,
+    direct: Direct,
+    follow: Follow,
+    guidance: Guidance,
+    geofence: Geofence,
+    /// latest breach of the geofence
+    breach: Option<Breach>,
+    /// latest GPS fix
+    fix: Option<GeoPoint>,
+    /// position recorded upon arming
+    home: Option<GeoPoint>,
+    /// mode of the latest RC command
+    rc_mode: Option<RcMode>,
+    /// arming switch of the latest RC command (None until a command)
+    rc_arm: Option<bool>,
+    /// throttle of the latest RC command
+    throttle: Option<f32>,
+    /// latest IMU sample
+    imu: Option<ImuData>,
+    /// latest deficiency of the actuation
+    deficiency: Option<Deficiency>,
+    /// motor commands of the latest mix
+    outputs: [f32; N],
+    /// time of the latest motor speeds (seconds)
+    rpm_time: f32,
+    /// heading maintained by the position control (radians)
+    heading: f32,
+    /// the mixer could not provide the latest demands
+    saturated: bool,
+    /// collective thrust of the latest demands
+    mixer_thrust: f32,
+    /// duration of a step (seconds)
+    cycle_period: f32,
+    /// time since boot (seconds)
+    time: f32,
+    /// time since the latest IMU sample (seconds)
+    since_imu: f32,
+    /// time since the latest magnetometer fusion (seconds)
+    since_heading: f32,
+}
+
+impl<Robot, const N: usize> FlightController<'static, Robot, N>
+where
+    Robot: rusty_robot_drivers::imu_traits::ImuReader
+        + rusty_robot_drivers::gps_traits::Gps
+        + Motors<N>,
+{
+    /// cold boot (motors disabled)
+    pub fn new(drone: &'static Robot, config: Config<N>) -> Self {
+        Self::boot(drone, config, None)
+    }
+
+    /// warm boot, resuming the flight per the state retained prior to a reset
+    pub fn resume(drone: &'static Robot, config: Config<N>, snapshot: &Snapshot) -> Self {
+        Self::boot(drone, config, Some(snapshot))
+    }
+
+    fn boot(drone: &'static Robot, config: Config<N>, snapshot: Option<&Snapshot>) -> Self {
+        <Robot as Motors<N>>::disarm(drone);
+        let mut fc = FlightController {
+            drone,
+            imu_calibration: config.imu_calibration,
+            // filtering each IMU sample
+            gyro_filter: GyroFilter::new(config.gyro_filter, config.imu_rate_hz as f32),
+            mixer: Mixer::new(config.geometry, config.mixer),
+            estimator: AttitudeEstimator::new(config.attitude),
+            ekf: Ekf::new(config.navigation),
+            attitude_control: AttitudeController::new(config.attitude_gains),
+            rate_control: RateController::new(config.rate_gains),
+            position_control: PositionController::new(config.position_gains),
+            hover_thrust: HoverThrustEstimator::new(config.hover_thrust),
+            states: StateMachine::new(config.states),
+            arming: Arming::new(config.arming),
+            failsafe: Failsafe::new(config.failsafe),
+            battery: Battery::new(config.battery),
+            battery_time: None,
+            depleted: false,
+            blind_touchdown: None,
+            characterization: Characterization::new(
+                config.characterization,
+                &config.geometry,
+                config.hover_thrust.initial,
+            ),
+            mission: Mission::new(config.mission),
+            direct: Direct::new(config.direct),
+            follow: Follow::new(config.follow),
+            guidance: Guidance::Commands,
+            geofence: Geofence::new(config.geofence),
+            breach: None,
+            fix: None,
+            home: None,
+            rc_mode: None,
+            rc_arm: None,
+            throttle: None,
+            imu: None,
+            deficiency: None,
+            outputs: [0.0; N],
+            rpm_time: 0.0,
+            heading: 0.0,
+            saturated: false,
+            mixer_thrust: 0.0,
+            cycle_period: 1.0 / (config.cycle_rate_hz as f32),
+            time: 0.0,
+            since_imu: 0.0,
+            since_heading: 0.0,
+        };
+        // HasMission?
+        let resume = match snapshot {
+            Some(snapshot) => {
+                let nav = &snapshot.navigation;
+                fc.ekf.restore(snapshot.origin, nav);
+                fc.estimator.restore(nav.attitude, nav.gyro_bias);
+                fc.hover_thrust.restore(snapshot.hover_thrust);
+                fc.mission.restore(
+                    snapshot.mission_index as usize,
+                    snapshot.mission_elapsed,
+                    fc.time,
+                );
+                snapshot.armed.then_some(snapshot.state)
+            }
+            None => None,
+        };
+        if let Some(snapshot) = snapshot {
+            fc.heading = snapshot.heading;
+            fc.home = snapshot.home;
+        }
+        if resume.is_some() {
+            // still flying, so the motors are enabled without recording the
+            //  home and heading anew
+            fc.arming.resume();
+            <Robot as Motors<N>>::arm(drone);
+        }
+        let _ = fc.command(Event::Boot { resume });
+        if let Some(snapshot) = snapshot {
+            fc.resume_guidance(snapshot.guidance);
+        }
+        fc
+    }
+
+    /// re-engage the guidance retained across a reset (once Active)
+    fn resume_guidance(&mut self, guidance: Guidance) {
+        let result = match guidance {
+            Guidance::Commands => Ok(()),
+            // the waypoints aren't retained, so the mission resumes once loaded
+            Guidance::Mission => self.engage(Guidance::Mission),
+            Guidance::Direct => self.engage_direct(),
+            Guidance::Follow => self.start_follow(),
+        };
+        if let Err(e) = result {
+            warn!("{guidance:?} guidance not resumed [{e}]");
+        }
+    }
+
+    /// critical state to retain across a reset (i.e. stored each cycle)
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            armed: <Robot as Motors<N>>::is_armed(self.drone),
+            state: self.states.state(),
+            guidance: self.guidance,
+            mission_index: self.mission.index() as u16,
+            mission_elapsed: self.mission.elapsed(self.time),
+            origin: self.ekf.origin(),
+            home: self.home,
+            navigation: self.ekf.state(),
+            hover_thrust: self.hover_thrust.hover_thrust(),
+            heading: self.heading,
+        }
+    }
+
+    /// apply the corrections (i.e. upon completing a calibration procedure)
+    pub fn set_imu_calibration(&mut self, calibration: ImuCalibration) {
+        self.imu_calibration = Some(calibration);
+    }
+
+    /// estimated attitude (body to world)
+    pub fn attitude(&self) -> Quaternion {
+        self.estimator.attitude()
+    }
+
+    /// estimated navigation state
+    pub fn navigation(&self) -> NavState {
+        self.ekf.state()
+    }
+
+    pub fn navigation_health(&self) -> Health {
+        self.ekf.health()
+    }
+
+    /// estimated collective thrust to hover (normalized)
+    pub fn hover_thrust(&self) -> f32 {
+        self.hover_thrust.hover_thrust()
+    }
+
+    /// estimated characteristics of the motors
+    pub fn actuation(&self) -> [MotorModel; N] {
+        self.characterization.motors()
+    }
+
+    /// whether the vehicle is able to meet its intents
+    pub fn assess_actuation(&self) -> Result<(), Deficiency> {
+        self.characterization.assess()
+    }
+
+    pub fn state(&self) -> State {
+        self.states.state()
+    }
+
+    /// source of the vectoring
+    pub fn guidance(&self) -> Guidance {
+        self.guidance
+    }
+
+    /// position recorded upon arming
+    pub fn home(&self) -> Option<GeoPoint> {
+        self.home
+    }
+
+    pub fn mission(&self) -> &Mission {
+        &self.mission
+    }
+
+    /// replace the waypoints of the mission (stopping the mission), where a
+    ///     mission resumed after a reset continues at the retained waypoint
+    pub fn load_mission(&mut self, waypoints: &[Waypoint]) -> Result<(), &'static str> {
+        let resumed =
+            self.guidance == Guidance::Mission && self.mission.progress() == Progress::Idle;
+        let (index, elapsed) = (self.mission.index(), self.mission.elapsed(self.time));
+        self.mission.clear();
+        for waypoint in waypoints {
+            self.mission.push(*waypoint)?;
+        }
+        if resumed {
+            self.mission.restore(index, elapsed, self.time);
+            self.start_mission()?;
+        }
+        Ok(())
+    }
+
+    /// replace the zones of the geofence
+    pub fn load_geofence(&mut self, zones: &[Zone]) -> Result<(), &'static str> {
+        self.geofence.clear();
+        for zone in zones {
+            self.geofence.add(*zone)?;
+        }
+        Ok(())
+    }
+
+    /// latest breach of the geofence
+    pub fn breach(&self) -> Option<Breach> {
+        self.breach
+    }
+
+    /// start (or resume) the mission (once Active)
+    pub fn start_mission(&mut self) -> Result<(), &'static str> {
+        let Some(origin) = self.ekf.origin() else {
+            return Err("navigation unavailable");
+        };
+        self.engage(Guidance::Mission)?;
+        if let Err(e) = self.mission.start(self.time, &origin) {
+            self.guidance = Guidance::Commands;
+            return Err(e);
+        }
+        self.guide();
+        Ok(())
+    }
+
+    /// command the flight (launch, hold, vectoring, land), overriding the
+    ///     guidance (mission, direct, follow)
+    pub fn handle(&mut self, event: Event) -> Result<State, &'static str> {
+        if let Event::Hold | Event::Vector(_) | Event::Return | Event::Land = event {
+            self.disengage();
+        }
+        self.command(event)
+    }
+
+    /// arm (motors enabled upon Launch), unless refused by the pre-arm checks
+    ///     (GPS required for autonomous modes)
+    pub fn arm(&mut self, autonomous: bool) -> Result<(), Refusals> {
+        if self.arming.is_armed() {
+            return Ok(());
+        }
+        let readiness = self.readiness();
+        let result = self.arming.arm(&readiness, autonomous);
+        match result {
+            Ok(()) => info!("armed"),
+            Err(refusals) => {
+                for refusal in refusals.iter() {
+                    warn!("arming refused [{}]", refusal.reason());
+                }
+            }
+        }
+        result
+    }
+
+    /// disarm (while not flying)
+    pub fn disarm(&mut self) -> Result<(), &'static str> {
+        if self.states.is_flying() {
+            return Err("flying");
+        }
+        if self.arming.is_armed() {
+            info!("disarmed");
+            self.arming.disarm();
+        }
+        Ok(())
+    }
+
+    pub fn is_armed(&self) -> bool {
+        self.arming.is_armed()
+    }
+
+    /// failed pre-arm checks of the latest arm command (i.e. for telemetry)
+    pub fn arming_refusals(&self) -> Refusals {
+        self.arming.refusals()
+    }
+
+    /// health of the vehicle per the pre-arm checks
+    pub fn readiness(&self) -> Readiness {
+        let rate = self
+            .imu
+            .and_then(|imu| imu.gyroscope)
+            .map(|gyro| (gyro - self.estimator.gyro_bias()).norm());
+        // angle between the body and world down axes
+        let down = self
+            .estimator
+            .attitude()
+            .rotate_vector(Vector3::new(0.0, 0.0, 1.0), false);
+        Readiness {
+            imu_healthy: !self.failsafe.imu_lost(self.time) && rate.is_some(),
+            calibrated: self
+                .imu
+                .and_then(|imu| imu.calibration_status)
+                .is_some_and(|status| status & GYRO_CALIBRATED != 0),
+            rate: rate.unwrap_or(0.0),
+            tilt: libm::acosf(down.z.clamp(-1.0, 1.0)).to_degrees(),
+            gps_fix: self.fix.is_some(),
+            cell_voltage: self.battery.cell_voltage(),
+            link: self.failsafe.link(self.time),
+            throttle: self.throttle,
+        }
+    }
+
+    /// pilot intent per the RC, acting upon changes of the arming switch and
+    ///     the mode
+    ///     the switch of the first command is its initial position (i.e.
+    ///     already high at power on), so it only arms once moved low to high
+    pub fn rc(&mut self, command: &RcCommand) {
+        // an unknown throttle (missing channel) refuses arming as if high
+        self.throttle = Some(command.throttle.unwrap_or(1.0));
+        let previous = self.rc_arm.replace(command.arm);
+        if previous.is_some_and(|previous| previous != command.arm) {
+            if command.arm {
+                let _ = self.arm(command.mode == RcMode::Autonomous);
+            } else if let Err(e) = self.disarm() {
+                warn!("disarm rejected [{e}]");
+            }
+        }
+        let changed = self.rc_mode != Some(command.mode);
+        self.rc_mode = Some(command.mode);
+        match command.mode {
+            // sticks are ignored until Active
+            RcMode::Direct => {
+                let _ = self.direct_sticks(command);
+            }
+            RcMode::Hold if changed => {
+                let _ = self.handle(Event::Hold);
+            }
+            RcMode::Autonomous if changed => {
+                if let Err(e) = self.start_mission() {
+                    warn!("mission not started [{e}]");
+                }
+            }
+            RcMode::Return if changed => {
+                let _ = self.handle(Event::Return);
+            }
+            _ => {}
+        }
+    }
+
+    /// headless trajectory per the sticks (once Active)
+    pub fn direct_sticks(&mut self, command: &RcCommand) -> Result<(), &'static str> {
+        self.engage_direct()?;
+        self.direct.sticks(command, self.time);
+        Ok(())
+    }
+
+    /// headless trajectory per the direction (NED) and rate (m/s), i.e. per a
+    ///     mesh command (once Active)
+    pub fn direct_trajectory(
+        &mut self,
+        direction: &Vector3,
+        rate: f32,
+    ) -> Result<(), &'static str> {
+        self.engage_direct()?;
+        self.direct.trajectory(direction, rate, self.time);
+        Ok(())
+    }
+
+    fn engage_direct(&mut self) -> Result<(), &'static str> {
+        if self.guidance != Guidance::Direct {
+            self.engage(Guidance::Direct)?;
+            self.direct.reset(&self.ekf.state().velocity);
+        }
+        Ok(())
+    }
+
+    /// position of the user received (i.e. per the mesh), followed once the
+    ///     follow mode is started
+    pub fn user_position(&mut self, user: &GeoPoint) -> Result<(), &'static str> {
+        let Some(origin) = self.ekf.origin() else {
+            return Err("navigation unavailable");
+        };
+        self.follow.user_position(&user.to_ned(&origin), self.time);
+        Ok(())
+    }
+
+    /// bodyguard the user (once Active)
+    pub fn start_follow(&mut self) -> Result<(), &'static str> {
+        self.engage(Guidance::Follow)?;
+        self.follow.reset();
+        Ok(())
+    }
+
+    /// the guidance overrides the others (once Active)
+    fn engage(&mut self, guidance: Guidance) -> Result<(), &'static str> {
+        if !matches!(self.states.state(), State::Active(_)) {
+            return Err("not active");
+        }
+        if self.guidance != guidance {
+            self.disengage();
+            info!("{guidance:?} guidance");
+            self.guidance = guidance;
+        }
+        Ok(())
+    }
+
+    fn disengage(&mut self) {
+        if self.guidance == Guidance::Mission {
+            self.mission.stop();
+        }
+        self.guidance = Guidance::Commands;
+    }
+
+    fn command(&mut self, event: Event) -> Result<State, &'static str> {
+        let inputs = self.inputs();
+        let result = self.states.handle(event, &inputs);
+        if let Err(e) = result {
+            warn!("{event:?} rejected [{e}]");
+        }
+        self.apply_state();
+        result
+    }
+
+    /// RC/mesh message received (the link is monitored once established)
+    pub fn link_received(&mut self) {
+        self.failsafe.link_received(self.time);
+    }
+
+    /// voltage and current of the battery (i.e. per the battery monitor)
+    ///     until provided, the battery isn't checked by the pre-arm checks,
+    ///     the failsafe nor the return upon depletion
+    pub fn battery(&mut self, data: &BatteryData) {
+        let dt = self.battery_time.map_or(0.0, |time| self.time - time);
+        self.battery_time = Some(self.time);
+        if let Err(e) = self.battery.update(data, dt) {
+            warn!("battery not monitored [{e}]");
+        }
+        // resting voltage (i.e. ignoring the sag of a punch out)
+        if let Some(cell_voltage) = self.battery.cell_voltage() {
+            self.failsafe.battery(self.time, cell_voltage);
+        }
+    }
+
+    /// estimated state of the battery
+    pub fn battery_state(&self) -> &Battery {
+        &self.battery
+    }
+
+    /// barometric altitude (m) received - until provided, the altitude is
+    ///     per the GPS
+    pub fn baro_received(&mut self, altitude: f32) {
+        if let Err(e) = self.ekf.fuse_baro(altitude) {
+            debug!("baro not fused [{e}]");
+        }
+    }
+
+    /// ESC telemetry (i.e. per an `RpmReader`) received - the speeds are
+    ///     ignored unless every motor is reliably measured
+    pub fn telemetry_received(&mut self, telemetry: &[MotorTelemetry; N]) {
+        if telemetry
+            .iter()
+            .any(|motor| motor.error_rate > MAX_TELEMETRY_ERROR_RATE)
+        {
+            debug!("motor speeds ignored [unreliable telemetry]");
+            return;
+        }
+        self.rpm_received(&telemetry.map(|motor| motor.rpm));
+    }
+
+    /// motor speeds (RPM) received
+    pub fn rpm_received(&mut self, rpm: &[f32; N]) {
+        self.gyro_filter.rpm(rpm);
+        self.characterization
+            .update_rpm(rpm, &self.outputs, self.time - self.rpm_time);
+        self.rpm_time = self.time;
+    }
+
+    pub fn step(&mut self) {
+        self.time += self.cycle_period;
+        self.since_imu += self.cycle_period;
+        let imu = <Robot as rusty_robot_drivers::imu_traits::ImuReader>::get_data(self.drone)
+            .ok()
+            .map(|data| match &self.imu_calibration {
+                Some(calibration) => calibration.correct(&data),
+                None => data,
+            });
+        if let Some(mut imu_data) = imu {
+            self.failsafe.imu_received(self.time);
+            imu_data.gyroscope = imu_data.gyroscope.map(|gyro| self.gyro_filter.apply(&gyro));
+            self.imu = Some(imu_data);
+            if let Err(e) = self.estimator.update(&mut imu_data, self.since_imu) {
+                warn!("attitude not estimated [{e}]");
+            }
+            if let Err(e) = self.ekf.predict(&imu_data, self.since_imu) {
+                warn!("navigation not estimated [{e}]");
+            }
+            // heading at the fusion rate (rather than the IMU rate)
+            self.since_heading += self.since_imu;
+            if let Some(mag) = imu_data.magnetometer
+                && self.since_heading * self.ekf.config.heading_rate_hz >= 1.0
+            {
+                self.since_heading = 0.0;
+                if let Err(e) = self.ekf.fuse_magnetometer(&mag) {
+                    debug!("magnetometer not fused [{e}]");
+                }
+            }
+            // control upon each IMU sample
+            self.control(&imu_data, self.since_imu);
+            self.since_imu = 0.0;
+        }
+
+        if let Ok(gps_data) = <Robot as rusty_robot_drivers::gps_traits::Gps>::get_data(self.drone)
+        {
+            if let Some(fix) = GeoPoint::from_nmea(&gps_data) {
+                self.fix = Some(fix);
+            }
+            if let Err(e) = self.ekf.fuse_gps(&gps_data) {
+                debug!("gps not fused [{e}]");
+            }
+        }
+
+        let inputs = self.inputs();
+        self.states.update(&inputs);
+        self.apply_state();
+        self.monitor(imu.as_ref());
+        self.fence();
+        self.power();
+        self.guide();
+    }
+
+    /// breach action upon leaving the geofence (while Active)
+    fn fence(&mut self) {
+        let (Some(origin), State::Active(_)) = (self.ekf.origin(), self.states.state()) else {
+            self.breach = None;
+            return;
+        };
+        let nav = self.ekf.state();
+        // the navigation origin until home is recorded
+        let home = self.home.map_or(Vector3::ZERO, |home| home.to_ned(&origin));
+        let breach = self
+            .geofence
+            .check(&origin, &home, &nav.position, &nav.velocity);
+        if let Some(breach) = breach
+            && self.breach.is_none()
+        {
+            warn!("{} [{breach:?}]", breach.reason());
+            self.disengage();
+            let _ = match self.geofence.config.action {
+                BreachAction::Hold => self.command(Event::Hold),
+                BreachAction::Return => self
+                    .command(Event::Return)
+                    .or_else(|_| self.command(Event::Hold)),
+                BreachAction::Land => self.command(Event::Failure(breach.reason())),
+            };
+        }
+        self.breach = breach;
+    }
+
+    /// return home once the remaining flight time only suffices to return
+    fn power(&mut self) {
+        let state = self.states.state();
+        let depleted = match (self.battery.remaining_time(), self.inputs().home, state) {
+            (Some(remaining), Some(home), State::Active(_)) => {
+                self.mission
+                    .must_return(remaining, &self.ekf.state().position, &home)
+            }
+            _ => false,
+        };
+        if depleted && !self.depleted && !matches!(state, State::Active(Active::Return(_))) {
+            warn!("battery depleted, returning");
+            self.disengage();
+            let _ = self.command(Event::Return);
+        }
+        self.depleted = depleted;
+    }
+
+    /// vectoring per the guidance
+    fn guide(&mut self) {
+        if !matches!(self.states.state(), State::Active(_)) {
+            // i.e. failsafe
+            self.disengage();
+            return;
+        }
+        let position = self.ekf.state().position;
+        match self.guidance {
+            Guidance::Commands => {}
+            Guidance::Mission => {
+                if let Some(target) = self.mission.update(self.time, &position) {
+                    let _ = self.command(Event::Vector(target));
+                }
+            }
+            Guidance::Direct => {
+                let target = self.direct.update(self.time, self.cycle_period);
+                let _ = self.command(Event::Vector(target));
+                let heading = self.heading + self.direct.yaw_rate() * self.cycle_period;
+                // within (-PI, PI]
+                self.heading = libm::atan2f(libm::sinf(heading), libm::cosf(heading));
+            }
+            Guidance::Follow => match self.follow.update(&position, self.time) {
+                Following::Tracking { target, heading } => {
+                    let _ = self.command(Event::Vector(target));
+                    self.heading = heading;
+                }
+                Following::Lost => {
+                    warn!("user lost");
+                    self.disengage();
+                    let _ = self.command(Event::Hold);
+                }
+            },
+        }
+    }
+
+    /// soft descent when unable to continue toward the intent
+    fn monitor(&mut self, imu_data: Option<&ImuData>) {
+        self.failsafe.navigation(self.ekf.health().diverged);
+        let deficiency = self.characterization.assess().err();
+        if let Some(deficiency) = deficiency
+            && self.deficiency.is_none()
+        {
+            warn!("{} {deficiency:?}", deficiency.reason());
+        }
+        self.deficiency = deficiency;
+        self.failsafe.actuation(deficiency.is_some());
+        match (self.failsafe.check(self.time), self.states.state()) {
+            // already returning
+            (Some(Failure::LinkLost), State::Active(Active::Return(_))) => {}
+            (Some(Failure::LinkLost), State::Launch { .. } | State::Active(_))
+                if self.home.is_some() =>
+            {
+                warn!("{}, returning", Failure::LinkLost.reason());
+                self.disengage();
+                if self.command(Event::Return).is_err() {
+                    let _ = self.command(Event::Failure(Failure::LinkLost.reason()));
+                }
+            }
+            (Some(failure), State::Launch { .. } | State::Active(_)) => {
+                let _ = self.command(Event::Failure(failure.reason()));
+            }
+            _ => {}
+        }
+
+        let descent = self.failsafe.descent(self.time);
+        if !matches!(self.states.state(), State::Land { .. }) || descent == Descent::Guided {
+            self.blind_touchdown = None;
+            return;
+        }
+        let touchdown = self
+            .blind_touchdown
+            .get_or_insert_with(|| BlindTouchdown::new(self.failsafe.config, self.time))
+            .update(
+                self.time,
+                imu_data.and_then(|imu| imu.accelerometer),
+                imu_data.and_then(|imu| imu.gyroscope),
+            );
+        if touchdown {
+            let _ = self.command(Event::Touchdown);
+        } else if descent == Descent::OpenLoop {
+            // without stabilization (no IMU to control upon)
+            let thrust = self.blind_thrust();
+            let mix = self.mixer.mix(&Demands {
+                thrust,
+                ..Default::default()
+            });
+            self.mixer_thrust = thrust;
+            self.outputs = mix.outputs;
+            <Robot as Motors<N>>::set_outputs(self.drone, mix.outputs);
+        }
+    }
+
+    /// thrust of a descent without navigation
+    fn blind_thrust(&self) -> f32 {
+        self.hover_thrust.hover_thrust() * self.failsafe.config.blind_thrust
+    }
+
+    /// guard inputs of the state machine
+    fn inputs(&self) -> Inputs {
+        let nav = self.ekf.state();
+        Inputs {
+            time: self.time,
+            armed: self.arming.is_armed(),
+            position: nav.position,
+            velocity: nav.velocity,
+            // including the innovation checks of each source
+            navigation_healthy: self.ekf.origin().is_some() && self.ekf.health().is_healthy(),
+            thrust: self.mixer_thrust,
+            hover_thrust: self.hover_thrust.hover_thrust(),
+            home: self
+                .home
+                .zip(self.ekf.origin())
+                .map(|(home, origin)| home.to_ned(&origin)),
+        }
+    }
+
+    /// motors and controllers per the (possibly changed) state
+    fn apply_state(&mut self) {
+        let armed = <Robot as Motors<N>>::is_armed(self.drone);
+        if self.states.is_flying() && !armed {
+            self.record_home();
+            self.heading = self.estimator.attitude().to_euler().z;
+            self.position_control.reset();
+            self.rate_control.reset();
+            <Robot as Motors<N>>::arm(self.drone);
+        } else if !self.states.is_flying() && armed {
+            // landed, re-arming is required
+            self.arming.disarm();
+            <Robot as Motors<N>>::disarm(self.drone);
+            self.mixer_thrust = 0.0;
+            self.outputs = [0.0; N];
+        }
+    }
+
+    /// home is the position upon arming (per the GPS fix, else the navigation)
+    fn record_home(&mut self) {
+        let navigation = self
+            .ekf
+            .origin()
+            .map(|origin| GeoPoint::from_ned(&origin, &self.ekf.state().position));
+        self.home = self.fix.or(navigation);
+        match self.home {
+            Some(home) => info!("home {home:?}"),
+            None => warn!("no home"),
+        }
+    }
+
+    /// position setpoint of the state (None while the motors are disabled)
+    fn position_setpoint(&self) -> Option<PositionSetpoint> {
+        let target = match self.states.state() {
+            State::HasMission | State::Safe => return None,
+            State::Launch { target, .. } => Target::Position(target),
+            State::Active(Active::Hold(position)) => Target::Position(position),
+            State::Active(Active::Vectoring(target)) => target,
+            State::Active(Active::Return(Return::Climb(position) | Return::Navigate(position))) => {
+                Target::Position(position)
+            }
+            State::Land { .. } => Target::Trajectory {
+                direction: Vector3::new(0.0, 0.0, 1.0),
+                rate: self.states.config.landing_rate,
+            },
+        };
+        Some(PositionSetpoint {
+            target,
+            yaw: self.heading,
+        })
+    }
+
+    /// cascade of position, attitude and rate control (while flying)
+    fn control(&mut self, imu_data: &ImuData, dt: f32) {
+        let Some(gyro) = imu_data.gyroscope else {
+            return;
+        };
+        if !self.states.is_flying() {
+            return;
+        }
+        let attitude = self.estimator.attitude();
+
+        // hover thrust per the vertical acceleration of the previous thrust
+        if let State::Active(_) = self.states.state()
+            && let Some(accel) = imu_data.accelerometer
+        {
+            let thrust_axis = attitude.rotate_vector(Vector3::new(0.0, 0.0, -1.0), false);
+            let accel_down = attitude.rotate_vector(accel, false).z + GRAVITY;
+            let thrust = self.mixer_thrust * -thrust_axis.z;
+            self.hover_thrust.update(thrust, accel_down, dt);
+        }
+        // actuation per the response to the previous outputs
+        if let State::Active(_) = self.states.state()
+            && let Some(accel) = imu_data.accelerometer
+        {
+            self.characterization
+                .update(&self.outputs, gyro, accel, dt, self.saturated);
+        }
+
+        let setpoint = match (self.states.state(), self.position_setpoint()) {
+            (State::Land { .. }, _) if self.failsafe.descent(self.time) != Descent::Guided => {
+                AttitudeSetpoint::level(self.heading, self.blind_thrust())
+            }
+            (_, Some(position_setpoint)) => self.position_control.update(
+                &position_setpoint,
+                &self.ekf.state(),
+                self.hover_thrust.hover_thrust(),
+                dt,
+            ),
+            (_, None) => return,
+        };
+        let rate_setpoint = self.attitude_control.update(&attitude, &setpoint);
+        let rate = gyro - self.estimator.gyro_bias();
+        let demands =
+            self.rate_control
+                .update(&rate_setpoint, &rate, setpoint.thrust, dt, self.saturated);
+        let mix = self.mixer.mix(&demands);
+        self.saturated = mix.saturated;
+        self.mixer_thrust = setpoint.thrust;
+        self.outputs = mix.outputs;
+        <Robot as Motors<N>>::set_outputs(self.drone, mix.outputs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtering::gyro::NotchConfig;
+    use crate::filtering::rpm_notch::RpmNotchConfig;
+    use crate::flight_controller::arming::Refusal;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use rusty_robot_drivers::nmea::Nmea;
+
+    const CYCLE_RATE_HZ: u32 = 1000;
+    const ORIGIN: GeoPoint = GeoPoint {
+        latitude: 47.6,
+        longitude: -122.3,
+        altitude: 100.0,
+    };
+
+    /// stationary (level) drone, without a GPS fix
+    struct TestDrone {
+        /// steps per IMU sample
+        imu_divider: u32,
+        /// frequency (Hz) of the roll rate vibration (none when 0)
+        vibration: f32,
+        steps: AtomicU32,
+        armed: AtomicBool,
+    }
+    impl TestDrone {
+        const fn new(imu_divider: u32) -> Self {
+            Self::vibrating(imu_divider, 0.0)
+        }
+
+        const fn vibrating(imu_divider: u32, vibration: f32) -> Self {
+            TestDrone {
+                imu_divider,
+                vibration,
+                steps: AtomicU32::new(0),
+                armed: AtomicBool::new(false),
+            }
+        }
+    }
+    impl rusty_robot_drivers::imu_traits::ImuReader for TestDrone {
+        fn get_data(&self) -> Result<ImuData, &str> {
+            let step = self.steps.fetch_add(1, Ordering::Relaxed);
+            if !step.is_multiple_of(self.imu_divider) {
+                return Err("no data");
+            }
+            let t = step as f32 / CYCLE_RATE_HZ as f32;
+            let roll = 10.0 * libm::sinf(2.0 * core::f32::consts::PI * self.vibration * t);
+            Ok(ImuData {
+                accelerometer: Some(Vector3::new(0.0, 0.0, -GRAVITY)),
+                gyroscope: Some(Vector3::new(roll, 0.0, 0.0)),
+                // heading north
+                magnetometer: Some(Vector3::new(20.0, 0.0, 45.0)),
+                ..Default::default()
+            })
+        }
+        fn stop(&self) -> Result<(), &str> {
+            Ok(())
+        }
+    }
+    impl rusty_robot_drivers::gps_traits::Gps for TestDrone {
+        fn get_data(&self) -> Result<Nmea, &str> {
+            Err("no fix")
+        }
+    }
+    impl Motors<4> for TestDrone {
+        fn set_outputs(&self, _outputs: [f32; 4]) {}
+        fn arm(&self) {
+            self.armed.store(true, Ordering::Relaxed);
+        }
+        fn disarm(&self) {
+            self.armed.store(false, Ordering::Relaxed);
+        }
+        fn is_armed(&self) -> bool {
+            self.armed.load(Ordering::Relaxed)
+        }
+    }
+
+    fn calibrated() -> ImuCalibration {
+        let mut calibration = ImuCalibration::default();
+        calibration.set_gyro(&Vector3::ZERO, None);
+        calibration
+    }
+
+    fn config() -> Config<4> {
+        Config {
+            imu_calibration: Some(calibrated()),
+            ..Config::new(Geometry::quad_x(), CYCLE_RATE_HZ)
+        }
+    }
+
+    /// holding 10m above the origin
+    fn snapshot(guidance: Guidance) -> Snapshot {
+        let position = Vector3::new(0.0, 0.0, -10.0);
+        Snapshot {
+            armed: true,
+            state: State::Active(Active::Hold(position)),
+            guidance,
+            mission_index: 1,
+            mission_elapsed: Some(30.0),
+            origin: Some(ORIGIN),
+            home: Some(ORIGIN),
+            navigation: NavState {
+                attitude: Quaternion::IDENTITY,
+                position,
+                ..Default::default()
+            },
+            hover_thrust: 0.5,
+            heading: 0.0,
+        }
+    }
+
+    #[test]
+    fn resumes_repeated_resets() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        let mut snapshot = snapshot(Guidance::Commands);
+        for _ in 0..2 {
+            let mut fc = FlightController::resume(&DRONE, config(), &snapshot);
+            assert!(fc.is_armed());
+            assert!(DRONE.is_armed());
+            assert!(matches!(fc.state(), State::Active(Active::Hold(_))));
+            // rather than the position upon resuming
+            assert_eq!(fc.home(), Some(ORIGIN));
+            for _ in 0..CYCLE_RATE_HZ / 10 {
+                fc.step();
+            }
+            snapshot = fc.snapshot();
+            assert!(snapshot.armed);
+            assert_eq!(snapshot.home, Some(ORIGIN));
+        }
+    }
+
+    #[test]
+    fn resumes_guidance() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        for guidance in [Guidance::Commands, Guidance::Direct, Guidance::Follow] {
+            let fc = FlightController::resume(&DRONE, config(), &snapshot(guidance));
+            assert!(matches!(fc.state(), State::Active(Active::Hold(_))));
+            assert_eq!(fc.guidance(), guidance);
+        }
+
+        // the mission resumes at the retained waypoint once loaded
+        let mut fc = FlightController::resume(&DRONE, config(), &snapshot(Guidance::Mission));
+        assert_eq!(fc.guidance(), Guidance::Mission);
+        assert!(!fc.mission().is_running());
+        let waypoints = [50.0, 100.0, 150.0].map(|north| {
+            Waypoint::new(GeoPoint::from_ned(
+                &ORIGIN,
+                &Vector3::new(north, 0.0, -10.0),
+            ))
+        });
+        fc.load_mission(&waypoints).unwrap();
+        assert_eq!(fc.mission().progress(), Progress::Approaching(1));
+        assert_eq!(fc.mission().elapsed(fc.time), Some(30.0));
+
+        // a cold boot has no guidance to resume
+        let fc = FlightController::new(&DRONE, config());
+        assert_eq!(fc.state(), State::Safe);
+        assert_eq!(fc.guidance(), Guidance::Commands);
+    }
+
+    #[test]
+    fn filters_at_imu_rate() {
+        // an IMU sample per 4 steps (250Hz), vibrating at 100Hz
+        static DRONE: TestDrone = TestDrone::vibrating(4, 100.0);
+        let config = Config {
+            imu_rate_hz: CYCLE_RATE_HZ / 4,
+            gyro_filter: GyroFilterConfig {
+                rpm_notch: None,
+                notch: Some(NotchConfig {
+                    center: 100.0,
+                    q: 2.0,
+                }),
+                dynamic_notch: None,
+                lowpass: None,
+                lowpass2: None,
+            },
+            ..config()
+        };
+        let mut fc = FlightController::new(&DRONE, config);
+        let mut peak: f32 = 0.0;
+        for step in 0..2 * CYCLE_RATE_HZ {
+            fc.step();
+            // once the notch has settled
+            if step > CYCLE_RATE_HZ
+                && let Some(gyro) = fc.imu.and_then(|imu| imu.gyroscope)
+            {
+                peak = peak.max(gyro.x.abs());
+            }
+        }
+        assert!(peak < 0.1, "{peak}");
+    }
+
+    #[test]
+    fn fuses_at_source_rates() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        let mut fc = FlightController::new(&DRONE, config());
+        for _ in 0..CYCLE_RATE_HZ {
+            fc.step();
+        }
+        // the magnetometer at the fusion rate, rather than per IMU sample
+        let heading = fc.navigation_health().heading;
+        let rate = fc.ekf.config.heading_rate_hz as u32;
+        assert!(heading.fused.abs_diff(rate) <= 1, "{heading:?}");
+
+        for _ in 0..5 {
+            fc.baro_received(120.0);
+        }
+        assert_eq!(fc.navigation_health().baro.fused, 5);
+    }
+
+    #[test]
+    fn receives_reliable_telemetry() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        let mut fc = FlightController::new(&DRONE, config());
+        let centers = |fc: &FlightController<TestDrone, 4>| {
+            let rpm_notch = fc.gyro_filter.rpm_notch().unwrap();
+            core::array::from_fn::<_, 4, _>(|motor| rpm_notch.centers(motor)[0])
+        };
+        // until the ESCs respond
+        let telemetry = [MotorTelemetry {
+            rpm: 12_000.0,
+            error_rate: 1.0,
+        }; 4];
+        fc.telemetry_received(&telemetry);
+        assert!(centers(&fc).iter().all(|center| center.is_none()));
+
+        let mut telemetry = telemetry.map(|motor| MotorTelemetry {
+            error_rate: 0.05,
+            ..motor
+        });
+        fc.telemetry_received(&telemetry);
+        assert!(centers(&fc).iter().all(|center| *center == Some(200.0)));
+        // a failing motor (retaining the previous speeds)
+        telemetry[2] = MotorTelemetry {
+            rpm: 0.0,
+            error_rate: 0.5,
+        };
+        fc.telemetry_received(&telemetry);
+        assert!(centers(&fc).iter().all(|center| *center == Some(200.0)));
+    }
+
+    #[test]
+    fn tracks_motor_speeds() {
+        // an IMU sample per step, vibrating at the motor speeds (12000RPM)
+        static DRONE: TestDrone = TestDrone::vibrating(1, 200.0);
+        let config = Config {
+            gyro_filter: GyroFilterConfig {
+                rpm_notch: Some(RpmNotchConfig::default()),
+                notch: None,
+                dynamic_notch: None,
+                lowpass: None,
+                lowpass2: None,
+            },
+            ..config()
+        };
+        let mut fc = FlightController::new(&DRONE, config);
+        let peak = |fc: &mut FlightController<'static, TestDrone, 4>, rpm| {
+            let telemetry = [MotorTelemetry {
+                rpm,
+                error_rate: 0.0,
+            }; 4];
+            let mut peak: f32 = 0.0;
+            for step in 0..CYCLE_RATE_HZ {
+                fc.telemetry_received(&telemetry);
+                fc.step();
+                // once the notches have settled
+                if step > CYCLE_RATE_HZ / 2
+                    && let Some(gyro) = fc.imu.and_then(|imu| imu.gyroscope)
+                {
+                    peak = peak.max(gyro.x.abs());
+                }
+            }
+            peak
+        };
+        // idle (notches bypassed), then at the speed of the vibration
+        assert!(peak(&mut fc, 1_500.0) > 9.0);
+        let tracked = peak(&mut fc, 12_000.0);
+        assert!(tracked < 0.1, "{tracked}");
+    }
+
+    #[test]
+    fn arms_upon_switch_transition() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        let mut fc = FlightController::new(&DRONE, config());
+        for _ in 0..CYCLE_RATE_HZ {
+            fc.step();
+        }
+        fc.link_received();
+        let rc = |arm| RcCommand {
+            arm,
+            throttle: Some(0.0),
+            ..Default::default()
+        };
+        // the switch was high upon connecting
+        fc.rc(&rc(true));
+        assert!(!fc.is_armed());
+        fc.rc(&rc(true));
+        assert!(!fc.is_armed());
+        fc.rc(&rc(false));
+        fc.rc(&rc(true));
+        assert!(fc.is_armed(), "{:?}", fc.arming_refusals());
+        fc.rc(&rc(false));
+        assert!(!fc.is_armed());
+
+        // an unknown throttle (missing channel)
+        fc.rc(&RcCommand {
+            arm: true,
+            ..Default::default()
+        });
+        assert!(!fc.is_armed());
+        assert!(fc.arming_refusals().contains(Refusal::ThrottleHigh));
+    }
+
+    #[test]
+    fn refuses_arming_until_calibrated() {
+        static DRONE: TestDrone = TestDrone::new(1);
+        let mut fc = FlightController::new(&DRONE, Config::new(Geometry::quad_x(), CYCLE_RATE_HZ));
+        for _ in 0..CYCLE_RATE_HZ {
+            fc.step();
+        }
+        fc.link_received();
+        let refusals = fc.arm(false).unwrap_err();
+        assert!(refusals.contains(Refusal::NotCalibrated), "{refusals:?}");
+
+        // i.e. upon the gyro calibration
+        fc.set_imu_calibration(calibrated());
+        fc.step();
+        assert_eq!(fc.arm(false), Ok(()));
+    }
+}
@@ -0,0 +1,159 @@
+//! PID controller
+//!
+//! ```text
+//! output = kp * error + ki * ∫error + kd * d(-measurement)/dt + ff * setpoint
+//! ```
+//!
+//! The derivative is of the measurement (rather than the error) so that steps
+//! of the setpoint don't kick the output, and is low-pass filtered as it
+//! amplifies sensor noise. The integral is limited and doesn't accumulate
+//! while the output is saturated (anti-windup).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    /// feedforward of the setpoint
+    pub ff: f32,
+    /// limit of the integral contribution (absolute)
+    pub i_limit: f32,
+    /// cutoff frequency of the derivative low-pass (0.0 disables filtering)
+    pub d_cutoff_hz: f32,
+}
+impl Default for PidGains {
+    fn default() -> Self {
+        PidGains {
+            kp: 1.0,
+            ki: 0.0,
+            kd: 0.0,
+            ff: 0.0,
+            i_limit: 0.0,
+            d_cutoff_hz: 0.0,
+        }
+    }
+}
+
+pub struct Pid {
+    pub gains: PidGains,
+    /// integral contribution
+    integral: f32,
+    previous_measurement: Option<f32>,
+    /// filtered derivative of the measurement
+    derivative: f32,
+}
+
+impl Pid {
+    pub fn new(gains: PidGains) -> Self {
+        Pid {
+            gains,
+            integral: 0.0,
+            previous_measurement: None,
+            derivative: 0.0,
+        }
+    }
+
+    /// clear the integral and derivative (i.e. when the controller is engaged)
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.previous_measurement = None;
+        self.derivative = 0.0;
+    }
+
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// output of the measurement `dt` seconds after the previous
+    ///     `saturated` - the previous output could not be (fully) applied
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: f32, saturated: bool) -> f32 {
+        let gains = &self.gains;
+        let error = setpoint - measurement;
+
+        if let Some(previous) = self.previous_measurement
+            && dt > 0.0
+        {
+            let derivative = -(measurement - previous) / dt;
+            if gains.d_cutoff_hz > 0.0 {
+                // PT1 low-pass
+                let rc = 1.0 / (2.0 * core::f32::consts::PI * gains.d_cutoff_hz);
+                self.derivative += (dt / (rc + dt)) * (derivative - self.derivative);
+            } else {
+                self.derivative = derivative;
+            }
+        }
+        self.previous_measurement = Some(measurement);
+
+        // integrate unless that would deepen the saturation
+        let unwinding = error * self.integral < 0.0;
+        if !saturated || unwinding {
+            self.integral =
+                (self.integral + gains.ki * error * dt).clamp(-gains.i_limit, gains.i_limit);
+        }
+
+        gains.kp * error + self.integral + gains.kd * self.derivative + gains.ff * setpoint
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proportional_and_feedforward() {
+        let mut pid = Pid::new(PidGains {
+            kp: 2.0,
+            ff: 0.5,
+            ..Default::default()
+        });
+        assert_eq!(pid.update(1.0, 0.25, 0.01, false), 2.0 * 0.75 + 0.5);
+    }
+
+    #[test]
+    fn integral_limited_and_held_while_saturated() {
+        let mut pid = Pid::new(PidGains {
+            kp: 0.0,
+            ki: 1.0,
+            i_limit: 0.3,
+            ..Default::default()
+        });
+        for _ in 0..10 {
+            pid.update(1.0, 0.0, 0.01, false);
+        }
+        assert!((pid.integral() - 0.1).abs() < 1e-6);
+        // saturated - held
+        pid.update(1.0, 0.0, 0.01, true);
+        assert!((pid.integral() - 0.1).abs() < 1e-6);
+        // saturated, but unwinding
+        pid.update(-1.0, 0.0, 0.01, true);
+        assert!((pid.integral() - 0.09).abs() < 1e-6);
+        // limited
+        for _ in 0..100 {
+            pid.update(1.0, 0.0, 0.01, false);
+        }
+        assert_eq!(pid.integral(), 0.3);
+    }
+
+    #[test]
+    fn derivative_of_measurement_filtered() {
+        let gains = PidGains {
+            kp: 0.0,
+            kd: 1.0,
+            ..Default::default()
+        };
+        let mut pid = Pid::new(gains);
+        // no derivative kick upon setpoint change
+        assert_eq!(pid.update(0.0, 0.0, 0.01, false), 0.0);
+        assert_eq!(pid.update(10.0, 0.0, 0.01, false), 0.0);
+        // derivative opposes the measurement change
+        assert_eq!(pid.update(10.0, 0.1, 0.01, false), -10.0);
+
+        let mut filtered = Pid::new(PidGains {
+            d_cutoff_hz: 10.0,
+            ..gains
+        });
+        filtered.update(0.0, 0.0, 0.01, false);
+        let output = filtered.update(0.0, 0.1, 0.01, false);
+        assert!(output > -10.0 && output < -1.0, "not filtered [{output}]");
+    }
+}
@@ -0,0 +1,348 @@
+//! Position and velocity control
+//!
+//! Outer loops of the cascade (Active state) - converts a target into the
+//! attitude and thrust setpoints of the attitude controller.
+//!     * Hold - P control of the position to a velocity setpoint
+//!     * Vectoring - the velocity setpoint of the trajectory (direction + rate),
+//!       or of the position limited to the speed of the waypoint
+//!
+//! PID control of the velocity yields an acceleration setpoint, which (less
+//! gravity) is the direction and magnitude of the thrust. Tilt is limited by
+//! sacrificing the horizontal acceleration (altitude is prioritized).
+
+use rusty_robot_common::{Quaternion, Vector3};
+
+use super::attitude_control::{AttitudeSetpoint, rotation_between};
+use super::pid::{Pid, PidGains};
+use crate::estimation::attitude::GRAVITY;
+use crate::estimation::ekf::NavState;
+
+/// down (z) of the frames
+const DOWN: Vector3 = Vector3::new(0.0, 0.0, 1.0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// hold the position (NED, meters)
+    Position(Vector3),
+    /// move in the direction (NED) at the rate (m/s)
+    Trajectory { direction: Vector3, rate: f32 },
+    /// approach the position (NED, meters) at up to the speed (m/s)
+    Waypoint { position: Vector3, speed: f32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionSetpoint {
+    pub target: Target,
+    /// heading (radians, clockwise from north)
+    pub yaw: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PositionGains {
+    /// velocity (m/s) per position error (m)
+    pub horizontal: f32,
+    pub vertical: f32,
+    /// acceleration (m/s²) per velocity error (m/s)
+    pub horizontal_velocity: PidGains,
+    pub vertical_velocity: PidGains,
+    /// limits of the velocity setpoints (m/s)
+    pub max_speed: f32,
+    pub max_climb_rate: f32,
+    pub max_descent_rate: f32,
+    /// limit of the tilt (degrees)
+    pub max_tilt: f32,
+    /// limits of the collective thrust (normalized)
+    pub min_thrust: f32,
+    pub max_thrust: f32,
+}
+impl Default for PositionGains {
+    fn default() -> Self {
+        PositionGains {
+            horizontal: 0.95,
+            vertical: 1.0,
+            horizontal_velocity: PidGains {
+                kp: 1.8,
+                ki: 0.4,
+                kd: 0.2,
+                ff: 0.0,
+                i_limit: 2.0,
+                d_cutoff_hz: 5.0,
+            },
+            vertical_velocity: PidGains {
+                kp: 4.0,
+                ki: 2.0,
+                kd: 0.0,
+                ff: 0.0,
+                i_limit: 3.0,
+                d_cutoff_hz: 0.0,
+            },
+            max_speed: 5.0,
+            max_climb_rate: 3.0,
+            max_descent_rate: 1.5,
+            max_tilt: 35.0,
+            min_thrust: 0.12,
+            max_thrust: 0.9,
+        }
+    }
+}
+
+pub struct PositionController {
+    pub gains: PositionGains,
+    north: Pid,
+    east: Pid,
+    down: Pid,
+    /// the latest acceleration setpoint could not be attained
+    saturated: bool,
+}
+
+impl PositionController {
+    pub fn new(gains: PositionGains) -> Self {
+        PositionController {
+            gains,
+            north: Pid::new(gains.horizontal_velocity),
+            east: Pid::new(gains.horizontal_velocity),
+            down: Pid::new(gains.vertical_velocity),
+            saturated: false,
+        }
+    }
+
+    /// clear the integrals (i.e. when the controller is engaged)
+    pub fn reset(&mut self) {
+        self.north.reset();
+        self.east.reset();
+        self.down.reset();
+        self.saturated = false;
+    }
+
+    /// velocity setpoint (NED, m/s) of the target - within the limits
+    pub fn velocity_setpoint(&self, target: &Target, position: &Vector3) -> Vector3 {
+        let gains = &self.gains;
+        let approach = |target: Vector3| {
+            let error = target - *position;
+            Vector3::new(
+                error.x * gains.horizontal,
+                error.y * gains.horizontal,
+                error.z * gains.vertical,
+            )
+        };
+        let velocity = match target {
+            Target::Position(target) => approach(*target),
+            Target::Waypoint { position, speed } => {
+                let velocity = approach(*position);
+                let norm = velocity.norm();
+                if norm > speed.abs() {
+                    velocity * (speed.abs() / norm)
+                } else {
+                    velocity
+                }
+            }
+            Target::Trajectory { direction, rate } => match direction.normalized() {
+                Some(direction) => direction * rate.abs(),
+                None => Vector3::ZERO,
+            },
+        };
+
+        // horizontal limited preserving the direction
+        let horizontal = libm::sqrtf(velocity.x * velocity.x + velocity.y * velocity.y);
+        let scale = if horizontal > gains.max_speed {
+            gains.max_speed / horizontal
+        } else {
+            1.0
+        };
+        Vector3::new(
+            velocity.x * scale,
+            velocity.y * scale,
+            velocity
+                .z
+                .clamp(-gains.max_climb_rate, gains.max_descent_rate),
+        )
+    }
+
+    /// attitude and thrust setpoint toward the target `dt` seconds after the
+    ///     previous, per the hover thrust (normalized)
+    pub fn update(
+        &mut self,
+        setpoint: &PositionSetpoint,
+        state: &NavState,
+        hover_thrust: f32,
+        dt: f32,
+    ) -> AttitudeSetpoint {
+        let velocity = self.velocity_setpoint(&setpoint.target, &state.position);
+        let accel = Vector3::new(
+            self.north
+                .update(velocity.x, state.velocity.x, dt, self.saturated),
+            self.east
+                .update(velocity.y, state.velocity.y, dt, self.saturated),
+            self.down
+                .update(velocity.z, state.velocity.z, dt, self.saturated),
+        );
+        let (attitude, thrust, saturated) =
+            self.thrust_setpoint(&accel, setpoint.yaw, hover_thrust);
+        self.saturated = saturated;
+        AttitudeSetpoint {
+            attitude,
+            yaw_rate: 0.0,
+            thrust,
+        }
+    }
+
+    /// attitude and thrust providing the acceleration (NED, m/s²) - and
+    ///     whether it was limited
+    fn thrust_setpoint(
+        &self,
+        accel: &Vector3,
+        yaw: f32,
+        hover_thrust: f32,
+    ) -> (Quaternion, f32, bool) {
+        let gains = &self.gains;
+        // specific force of the thrust (m/s²)
+        let max_force = GRAVITY * gains.max_thrust / hover_thrust;
+        let min_force = GRAVITY * gains.min_thrust / hover_thrust;
+
+        // vertical first
+        let up = (GRAVITY - accel.z).clamp(min_force, max_force);
+        let mut saturated = up != GRAVITY - accel.z;
+
+        // horizontal within the tilt and the remaining thrust
+        let tilt_limit = up * libm::tanf(gains.max_tilt.to_radians());
+        let thrust_limit = libm::sqrtf((max_force * max_force - up * up).max(0.0));
+        let limit = tilt_limit.min(thrust_limit);
+        let horizontal = libm::sqrtf(accel.x * accel.x + accel.y * accel.y);
+        let scale = if horizontal > limit {
+            saturated = true;
+            limit / horizontal
+        } else {
+            1.0
+        };
+        let force = Vector3::new(accel.x * scale, accel.y * scale, -up);
+
+        // body down opposes the thrust
+        let body_down = (force * -1.0).normalized().unwrap_or(DOWN);
+        let heading = Quaternion::from_axis_angle(&DOWN, yaw);
+        let attitude = rotation_between(&DOWN, &body_down) * heading;
+        let thrust = force.norm() * hover_thrust / GRAVITY;
+        (attitude, thrust, saturated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hover_state(position: Vector3) -> NavState {
+        NavState {
+            attitude: Quaternion::IDENTITY,
+            position,
+            ..Default::default()
+        }
+    }
+
+    fn tilt(q: &Quaternion) -> f32 {
+        libm::acosf(q.rotate_vector(DOWN, false).z.clamp(-1.0, 1.0)).to_degrees()
+    }
+
+    #[test]
+    fn hovers_at_target() {
+        let mut controller = PositionController::new(PositionGains::default());
+        let setpoint = PositionSetpoint {
+            target: Target::Position(Vector3::new(1.0, 2.0, -10.0)),
+            yaw: 1.0,
+        };
+        let output = controller.update(
+            &setpoint,
+            &hover_state(Vector3::new(1.0, 2.0, -10.0)),
+            0.4,
+            0.01,
+        );
+        assert!((output.thrust - 0.4).abs() < 1e-4, "{output:?}");
+        assert!(tilt(&output.attitude) < 0.01);
+        let yaw = output.attitude.to_euler().z;
+        assert!((yaw - 1.0).abs() < 1e-3, "yaw {yaw}");
+    }
+
+    #[test]
+    fn tilts_toward_target() {
+        let mut controller = PositionController::new(PositionGains::default());
+        let setpoint = PositionSetpoint {
+            target: Target::Position(Vector3::new(3.0, 0.0, -10.0)),
+            yaw: 0.0,
+        };
+        let output = controller.update(
+            &setpoint,
+            &hover_state(Vector3::new(0.0, 0.0, -10.0)),
+            0.5,
+            0.01,
+        );
+        // thrust (body up) toward north - pitched nose down
+        let thrust_axis = output.attitude.rotate_vector(DOWN, false) * -1.0;
+        assert!(
+            thrust_axis.x > 0.1 && thrust_axis.y.abs() < 1e-4,
+            "{thrust_axis:?}"
+        );
+        assert!(output.attitude.to_euler().y < 0.0);
+        // thrust increased to hold altitude while tilted
+        assert!(output.thrust > 0.5);
+    }
+
+    #[test]
+    fn limits_tilt_and_velocity() {
+        let gains = PositionGains::default();
+        let mut controller = PositionController::new(gains);
+        let far = Target::Position(Vector3::new(1000.0, 1000.0, -10.0));
+        let velocity = controller.velocity_setpoint(&far, &Vector3::new(0.0, 0.0, -10.0));
+        assert!((velocity.norm() - gains.max_speed).abs() < 1e-3);
+        let climb = Target::Trajectory {
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            rate: 10.0,
+        };
+        assert_eq!(
+            controller.velocity_setpoint(&climb, &Vector3::ZERO).z,
+            -gains.max_climb_rate
+        );
+        // waypoint approached at its speed, slowing upon arrival
+        let waypoint = Target::Waypoint {
+            position: Vector3::new(100.0, 0.0, -10.0),
+            speed: 2.0,
+        };
+        let velocity = controller.velocity_setpoint(&waypoint, &Vector3::new(0.0, 0.0, -10.0));
+        assert!((velocity - Vector3::new(2.0, 0.0, 0.0)).norm() < 1e-3);
+        let velocity = controller.velocity_setpoint(&waypoint, &Vector3::new(99.0, 0.0, -10.0));
+        assert!((velocity.x - gains.horizontal).abs() < 1e-3);
+
+        let setpoint = PositionSetpoint {
+            target: Target::Trajectory {
+                direction: Vector3::new(0.0, -1.0, 0.0),
+                rate: 5.0,
+            },
+            yaw: 0.0,
+        };
+        let output = controller.update(&setpoint, &hover_state(Vector3::ZERO), 0.5, 0.01);
+        let tilt = tilt(&output.attitude);
+        assert!((tilt - gains.max_tilt).abs() < 0.1, "tilt {tilt}");
+        assert!(output.thrust <= gains.max_thrust);
+    }
+
+    #[test]
+    fn holds_position_in_simulation() {
+        // point mass with ideal attitude control, hover thrust unknown to the controller
+        let mass_hover = 0.45;
+        let mut controller = PositionController::new(PositionGains::default());
+        let setpoint = PositionSetpoint {
+            target: Target::Position(Vector3::new(5.0, -3.0, -10.0)),
+            yaw: 0.5,
+        };
+        let mut state = hover_state(Vector3::new(0.0, 0.0, -8.0));
+        let dt = 0.01;
+        for _ in 0..3000 {
+            let output = controller.update(&setpoint, &state, 0.5, dt);
+            let thrust_axis = output.attitude.rotate_vector(DOWN, false) * -1.0;
+            let accel = thrust_axis * (GRAVITY * output.thrust / mass_hover)
+                + Vector3::new(0.0, 0.0, GRAVITY);
+            state.velocity += accel * dt;
+            state.position += state.velocity * dt;
+        }
+        let error = (state.position - Vector3::new(5.0, -3.0, -10.0)).norm();
+        assert!(error < 0.05, "error {error} m");
+        assert!(state.velocity.norm() < 0.05);
+    }
+}
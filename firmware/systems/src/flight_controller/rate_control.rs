@@ -0,0 +1,111 @@
+//! Angular rate control
+//!
+//! Innermost loop of the cascade - converts body rate setpoints into the
+//! torque demands of the mixer, per axis PID (rad/s error to normalized torque).
+
+use rusty_robot_common::Vector3;
+
+use super::mixer::Demands;
+use super::pid::{Pid, PidGains};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateGains {
+    pub roll: PidGains,
+    pub pitch: PidGains,
+    pub yaw: PidGains,
+}
+impl Default for RateGains {
+    /// starting point for a 5 inch quadcopter
+    fn default() -> Self {
+        let roll = PidGains {
+            kp: 0.15,
+            ki: 0.2,
+            kd: 0.003,
+            ff: 0.0,
+            i_limit: 0.3,
+            d_cutoff_hz: 30.0,
+        };
+        RateGains {
+            roll,
+            pitch: roll,
+            yaw: PidGains {
+                kp: 0.2,
+                ki: 0.1,
+                kd: 0.0,
+                ff: 0.0,
+                i_limit: 0.3,
+                d_cutoff_hz: 0.0,
+            },
+        }
+    }
+}
+
+pub struct RateController {
+    roll: Pid,
+    pitch: Pid,
+    yaw: Pid,
+}
+
+impl RateController {
+    pub fn new(gains: RateGains) -> Self {
+        RateController {
+            roll: Pid::new(gains.roll),
+            pitch: Pid::new(gains.pitch),
+            yaw: Pid::new(gains.yaw),
+        }
+    }
+
+    pub fn set_gains(&mut self, gains: RateGains) {
+        self.roll.gains = gains.roll;
+        self.pitch.gains = gains.pitch;
+        self.yaw.gains = gains.yaw;
+    }
+
+    /// clear the integrals (i.e. while landed so they don't wind up)
+    pub fn reset(&mut self) {
+        self.roll.reset();
+        self.pitch.reset();
+        self.yaw.reset();
+    }
+
+    /// torque demands of the rates (deg/s) `dt` seconds after the previous
+    ///     `saturated` - the mixer could not provide the previous demands
+    pub fn update(
+        &mut self,
+        setpoint: &Vector3,
+        rate: &Vector3,
+        thrust: f32,
+        dt: f32,
+        saturated: bool,
+    ) -> Demands {
+        let setpoint = *setpoint * (core::f32::consts::PI / 180.0);
+        let rate = *rate * (core::f32::consts::PI / 180.0);
+        Demands {
+            roll: self.roll.update(setpoint.x, rate.x, dt, saturated),
+            pitch: self.pitch.update(setpoint.y, rate.y, dt, saturated),
+            yaw: self.yaw.update(setpoint.z, rate.z, dt, saturated),
+            thrust,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demands_oppose_rate_error() {
+        let mut controller = RateController::new(RateGains::default());
+        let demands = controller.update(
+            &Vector3::new(90.0, 0.0, -45.0),
+            &Vector3::new(0.0, 30.0, 0.0),
+            0.5,
+            0.001,
+            false,
+        );
+        assert!(demands.roll > 0.0);
+        assert!(demands.pitch < 0.0);
+        assert!(demands.yaw < 0.0);
+        assert_eq!(demands.thrust, 0.5);
+    }
+}
@@ -0,0 +1,432 @@
+//! In-air reset recovery
+//!
+//! The flight controller may reset at anytime (i.e. `panic_reset`), possibly
+//! while in the air. The critical state is periodically stored into memory
+//! retained across a warm reset (not initialized by the startup code), so
+//! that upon boot the flight resumes rather than dropping the vehicle.
+//!
+//! The retained memory is untrusted (i.e. random after power on), so the
+//! snapshot is validated by a magic number and a checksum (CRC-32).
+
+use rusty_robot_common::{Quaternion, Vector3, crc32};
+
+use super::multicopter::Guidance;
+use super::position_control::Target;
+use super::state_machine::{Active, Return, State};
+use crate::estimation::ekf::NavState;
+use crate::estimation::geodetic::GeoPoint;
+
+/// size of the retained memory (bytes)
+pub const RETAINED_SIZE: usize = 160;
+
+/// identifies (the version of) the snapshot layout
+const MAGIC: u32 = 0x5257_5335; // "RWS5"
+
+/// critical state of the flight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub armed: bool,
+    pub state: State,
+    /// source of the vectoring
+    pub guidance: Guidance,
+    /// current waypoint of the mission
+    pub mission_index: u16,
+    /// time (seconds) since the mission was first started
+    pub mission_elapsed: Option<f32>,
+    /// position of the navigation origin
+    pub origin: Option<GeoPoint>,
+    /// position recorded upon arming
+    pub home: Option<GeoPoint>,
+    pub navigation: NavState,
+    /// normalized collective thrust
+    pub hover_thrust: f32,
+    /// heading maintained by the position control (radians)
+    pub heading: f32,
+}
+
+/// content of the memory retained across resets
+///     the memory may be uninitialized, so its bytes are copied in and out
+///     (i.e. by volatile accesses) rather than referenced
+pub struct Retained {
+    bytes: [u8; RETAINED_SIZE],
+}
+
+impl Default for Retained {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Retained {
+    pub const fn new() -> Self {
+        Retained {
+            bytes: [0; RETAINED_SIZE],
+        }
+    }
+
+    /// content as read from the retained memory
+    pub const fn from_bytes(bytes: [u8; RETAINED_SIZE]) -> Self {
+        Retained { bytes }
+    }
+
+    /// content to write into the retained memory
+    pub fn bytes(&self) -> &[u8; RETAINED_SIZE] {
+        &self.bytes
+    }
+
+    pub fn store(&mut self, snapshot: &Snapshot) {
+        let mut writer = Writer {
+            bytes: [0; RETAINED_SIZE],
+            offset: 0,
+        };
+        writer.u32(MAGIC);
+        writer.u8(snapshot.armed as u8);
+        writer.u16(snapshot.mission_index);
+        writer.u8(snapshot.mission_elapsed.is_some() as u8);
+        writer.f32(snapshot.mission_elapsed.unwrap_or(0.0));
+        writer.state(&snapshot.state);
+        writer.u8(match snapshot.guidance {
+            Guidance::Commands => COMMANDS,
+            Guidance::Mission => MISSION,
+            Guidance::Direct => DIRECT,
+            Guidance::Follow => FOLLOW,
+        });
+        writer.point(&snapshot.origin);
+        writer.point(&snapshot.home);
+        let nav = &snapshot.navigation;
+        let attitude = nav.attitude;
+        for value in [attitude.w, attitude.x, attitude.y, attitude.z] {
+            writer.f32(value);
+        }
+        for v in [nav.velocity, nav.position, nav.gyro_bias, nav.accel_bias] {
+            writer.vector(&v);
+        }
+        writer.f32(snapshot.hover_thrust);
+        writer.f32(snapshot.heading);
+
+        let crc = crc32(&writer.bytes[..RETAINED_SIZE - 4]);
+        writer.bytes[RETAINED_SIZE - 4..].copy_from_slice(&crc.to_le_bytes());
+        self.bytes = writer.bytes;
+    }
+
+    /// the stored snapshot (if valid)
+    pub fn load(&self) -> Result<Snapshot, &'static str> {
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&self.bytes[RETAINED_SIZE - 4..]);
+        if crc32(&self.bytes[..RETAINED_SIZE - 4]) != u32::from_le_bytes(checksum) {
+            return Err("invalid checksum");
+        }
+        let mut reader = Reader {
+            bytes: &self.bytes,
+            offset: 0,
+        };
+        if reader.u32() != MAGIC {
+            return Err("no snapshot");
+        }
+        let armed = reader.u8() != 0;
+        let mission_index = reader.u16();
+        let started = reader.u8() != 0;
+        let elapsed = reader.f32();
+        let mission_elapsed = started.then_some(elapsed);
+        let state = reader.state()?;
+        let guidance = match reader.u8() {
+            COMMANDS => Guidance::Commands,
+            MISSION => Guidance::Mission,
+            DIRECT => Guidance::Direct,
+            FOLLOW => Guidance::Follow,
+            _ => return Err("unknown guidance"),
+        };
+        let origin = reader.point();
+        let home = reader.point();
+        let attitude = Quaternion {
+            w: reader.f32(),
+            x: reader.f32(),
+            y: reader.f32(),
+            z: reader.f32(),
+        };
+        Ok(Snapshot {
+            armed,
+            state,
+            guidance,
+            mission_index,
+            mission_elapsed,
+            origin,
+            home,
+            navigation: NavState {
+                attitude,
+                velocity: reader.vector(),
+                position: reader.vector(),
+                gyro_bias: reader.vector(),
+                accel_bias: reader.vector(),
+            },
+            hover_thrust: reader.f32(),
+            heading: reader.f32(),
+        })
+    }
+
+    /// discard the snapshot (i.e. upon an intentional reset)
+    pub fn invalidate(&mut self) {
+        self.bytes = [0; RETAINED_SIZE];
+    }
+}
+
+// state encoding
+const SAFE: u8 = 0;
+const LAUNCH: u8 = 1;
+const HOLD: u8 = 2;
+const VECTORING_POSITION: u8 = 3;
+const VECTORING_TRAJECTORY: u8 = 4;
+const LAND: u8 = 5;
+const VECTORING_WAYPOINT: u8 = 6;
+const RETURN_CLIMB: u8 = 7;
+const RETURN_NAVIGATE: u8 = 8;
+
+// guidance encoding
+const COMMANDS: u8 = 0;
+const MISSION: u8 = 1;
+const DIRECT: u8 = 2;
+const FOLLOW: u8 = 3;
+
+struct Writer {
+    bytes: [u8; RETAINED_SIZE],
+    offset: usize,
+}
+impl Writer {
+    fn put(&mut self, data: &[u8]) {
+        self.bytes[self.offset..self.offset + data.len()].copy_from_slice(data);
+        self.offset += data.len();
+    }
+    fn u8(&mut self, value: u8) {
+        self.put(&[value]);
+    }
+    fn u16(&mut self, value: u16) {
+        self.put(&value.to_le_bytes());
+    }
+    fn u32(&mut self, value: u32) {
+        self.put(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.put(&value.to_le_bytes());
+    }
+    fn f64(&mut self, value: f64) {
+        self.put(&value.to_le_bytes());
+    }
+    fn vector(&mut self, v: &Vector3) {
+        self.f32(v.x);
+        self.f32(v.y);
+        self.f32(v.z);
+    }
+    fn point(&mut self, point: &Option<GeoPoint>) {
+        let (valid, point) = match point {
+            Some(point) => (1, *point),
+            None => (0, GeoPoint::default()),
+        };
+        self.u8(valid);
+        self.f64(point.latitude);
+        self.f64(point.longitude);
+        self.f32(point.altitude);
+    }
+    /// tag and (fixed size) parameters of the state
+    ///     the time of entry is not retained (time restarts upon boot)
+    fn state(&mut self, state: &State) {
+        let (tag, v, rate) = match state {
+            State::HasMission | State::Safe => (SAFE, Vector3::ZERO, 0.0),
+            State::Launch { target, .. } => (LAUNCH, *target, 0.0),
+            State::Active(Active::Hold(position)) => (HOLD, *position, 0.0),
+            State::Active(Active::Vectoring(Target::Position(position))) => {
+                (VECTORING_POSITION, *position, 0.0)
+            }
+            State::Active(Active::Vectoring(Target::Trajectory { direction, rate })) => {
+                (VECTORING_TRAJECTORY, *direction, *rate)
+            }
+            State::Active(Active::Vectoring(Target::Waypoint { position, speed })) => {
+                (VECTORING_WAYPOINT, *position, *speed)
+            }
+            State::Active(Active::Return(Return::Climb(position))) => {
+                (RETURN_CLIMB, *position, 0.0)
+            }
+            State::Active(Active::Return(Return::Navigate(position))) => {
+                (RETURN_NAVIGATE, *position, 0.0)
+            }
+            State::Land { .. } => (LAND, Vector3::ZERO, 0.0),
+        };
+        self.u8(tag);
+        self.vector(&v);
+        self.f32(rate);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8; RETAINED_SIZE],
+    offset: usize,
+}
+impl Reader<'_> {
+    fn take<const L: usize>(&mut self) -> [u8; L] {
+        let mut data = [0; L];
+        data.copy_from_slice(&self.bytes[self.offset..self.offset + L]);
+        self.offset += L;
+        data
+    }
+    fn u8(&mut self) -> u8 {
+        self.take::<1>()[0]
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take())
+    }
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.take())
+    }
+    fn f32(&mut self) -> f32 {
+        f32::from_le_bytes(self.take())
+    }
+    fn f64(&mut self) -> f64 {
+        f64::from_le_bytes(self.take())
+    }
+    fn vector(&mut self) -> Vector3 {
+        Vector3::new(self.f32(), self.f32(), self.f32())
+    }
+    fn point(&mut self) -> Option<GeoPoint> {
+        let valid = self.u8() != 0;
+        let point = GeoPoint {
+            latitude: self.f64(),
+            longitude: self.f64(),
+            altitude: self.f32(),
+        };
+        valid.then_some(point)
+    }
+    fn state(&mut self) -> Result<State, &'static str> {
+        let tag = self.u8();
+        let v = self.vector();
+        let rate = self.f32();
+        Ok(match tag {
+            SAFE => State::Safe,
+            LAUNCH => State::Launch {
+                target: v,
+                since: 0.0,
+            },
+            HOLD => State::Active(Active::Hold(v)),
+            VECTORING_POSITION => State::Active(Active::Vectoring(Target::Position(v))),
+            VECTORING_TRAJECTORY => {
+                State::Active(Active::Vectoring(Target::Trajectory { direction: v, rate }))
+            }
+            LAND => State::Land { since: 0.0 },
+            VECTORING_WAYPOINT => State::Active(Active::Vectoring(Target::Waypoint {
+                position: v,
+                speed: rate,
+            })),
+            RETURN_CLIMB => State::Active(Active::Return(Return::Climb(v))),
+            RETURN_NAVIGATE => State::Active(Active::Return(Return::Navigate(v))),
+            _ => return Err("unknown state"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            armed: true,
+            state: State::Active(Active::Vectoring(Target::Trajectory {
+                direction: Vector3::new(1.0, -1.0, 0.0),
+                rate: 3.0,
+            })),
+            guidance: Guidance::Direct,
+            mission_index: 7,
+            mission_elapsed: Some(42.5),
+            origin: Some(GeoPoint {
+                latitude: 47.6,
+                longitude: -122.3,
+                altitude: 100.0,
+            }),
+            home: Some(GeoPoint {
+                latitude: 47.6001,
+                longitude: -122.3002,
+                altitude: 98.5,
+            }),
+            navigation: NavState {
+                attitude: Vector3::new(0.1, -0.2, 1.5).euler_to_quaternion(),
+                velocity: Vector3::new(2.0, -2.0, 0.1),
+                position: Vector3::new(120.0, -40.0, -30.0),
+                gyro_bias: Vector3::new(0.1, 0.2, -0.3),
+                accel_bias: Vector3::new(0.01, 0.02, -0.03),
+            },
+            hover_thrust: 0.42,
+            heading: 1.5,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut retained = Retained::new();
+        assert_eq!(retained.load(), Err("invalid checksum"));
+        for state in [
+            State::Safe,
+            State::Launch {
+                target: Vector3::new(0.0, 0.0, -2.5),
+                since: 0.0,
+            },
+            State::Active(Active::Hold(Vector3::new(1.0, 2.0, -3.0))),
+            State::Active(Active::Vectoring(Target::Waypoint {
+                position: Vector3::new(50.0, -20.0, -10.0),
+                speed: 4.0,
+            })),
+            State::Active(Active::Return(Return::Climb(Vector3::new(5.0, 5.0, -20.0)))),
+            State::Active(Active::Return(Return::Navigate(Vector3::new(
+                0.0, 0.0, -20.0,
+            )))),
+            State::Land { since: 0.0 },
+        ] {
+            let expected = Snapshot {
+                state,
+                ..snapshot()
+            };
+            retained.store(&expected);
+            assert_eq!(retained.load(), Ok(expected));
+        }
+        for guidance in [
+            Guidance::Commands,
+            Guidance::Mission,
+            Guidance::Direct,
+            Guidance::Follow,
+        ] {
+            let expected = Snapshot {
+                guidance,
+                ..snapshot()
+            };
+            retained.store(&expected);
+            assert_eq!(retained.load(), Ok(expected));
+        }
+        let expected = Snapshot {
+            mission_elapsed: None,
+            origin: None,
+            home: None,
+            ..snapshot()
+        };
+        retained.store(&expected);
+        assert_eq!(retained.load(), Ok(expected));
+        retained.store(&snapshot());
+        assert_eq!(retained.load(), Ok(snapshot()));
+
+        retained.invalidate();
+        assert!(retained.load().is_err());
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let mut retained = Retained::new();
+        retained.store(&snapshot());
+        for i in [0, 10, 60, RETAINED_SIZE - 1] {
+            let mut bytes = *retained.bytes();
+            bytes[i] ^= 0x10;
+            let corrupted = Retained::from_bytes(bytes);
+            assert_eq!(corrupted.load(), Err("invalid checksum"));
+        }
+        // i.e. memory after power on
+        let random = Retained::from_bytes(core::array::from_fn(|i| {
+            (i as u8).wrapping_mul(113).wrapping_add(7)
+        }));
+        assert!(random.load().is_err());
+    }
+}
@@ -0,0 +1,671 @@
+//! Flight state machine
+//!
+//! Per the [state diagram](docs/design.md)
+//!     * HasMission? - the flight controller may reset at anytime (it may be in
+//!       the air), so upon boot an active mission resumes (Active), otherwise
+//!       the motors are disabled (Safe)
+//!     * Safe - launches once explicitly armed (pre-arm checks passed)
+//!     * Launch - climbs to the initial position, aborting (Land) as soon as
+//!       it can be determined the position can't be attained (SafePosition?)
+//!     * Active - Hold (maintain position), Vectoring (toward the target) or
+//!       Return (climb to the safe altitude, then toward home, then Land)
+//!     * Land - descends until touchdown, then disables the motors (Safe)
+//!
+//! Transitions are the result of events (commands, failures) or of the
+//! inputs (guards evaluated each update), where every transition is logged.
+//! Retargeting while Vectoring (i.e. a trajectory per the sticks) isn't a
+//! transition.
+
+use log::*;
+use rusty_robot_common::Vector3;
+
+use super::position_control::Target;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// upon boot, until resolved whether a mission is active
+    HasMission,
+    /// motors disabled
+    Safe,
+    /// climbing to the initial position (NED)
+    Launch {
+        target: Vector3,
+        since: f32,
+    },
+    Active(Active),
+    /// controlled descent
+    Land {
+        since: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Active {
+    /// maintain the position (NED)
+    Hold(Vector3),
+    /// move toward the target
+    Vectoring(Target),
+    /// return to home
+    Return(Return),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Return {
+    /// climb to the position (NED) at the safe altitude
+    Climb(Vector3),
+    /// toward the position (NED) above home at the safe altitude
+    Navigate(Vector3),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// resolves HasMission? - the state retained prior to the boot (if any)
+    Boot { resume: Option<State> },
+    /// launch to the initial position
+    Launch,
+    /// maintain the current position
+    Hold,
+    /// move toward the target
+    Vector(Target),
+    /// return to home (then land)
+    Return,
+    /// unable to continue toward the intent
+    Failure(&'static str),
+    /// commanded landing
+    Land,
+    /// touchdown detected by other means than the navigation (i.e. impact)
+    Touchdown,
+}
+
+/// sensor derived inputs of the guards
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Inputs {
+    /// time since boot (seconds)
+    pub time: f32,
+    /// explicitly armed (pre-arm checks passed)
+    pub armed: bool,
+    /// estimated position (NED, m)
+    pub position: Vector3,
+    /// estimated velocity (NED, m/s)
+    pub velocity: Vector3,
+    /// the navigation estimate is usable
+    pub navigation_healthy: bool,
+    /// commanded collective thrust (normalized)
+    pub thrust: f32,
+    /// estimated collective thrust to hover (normalized)
+    pub hover_thrust: f32,
+    /// position of home (NED, m)
+    pub home: Option<Vector3>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    pub from: State,
+    pub to: State,
+    pub reason: &'static str,
+    /// time of the transition (seconds since boot)
+    pub time: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StateConfig {
+    /// height of the initial position above the launch point (m)
+    pub launch_altitude: f32,
+    /// distance from the initial position considered attained (m)
+    pub launch_radius: f32,
+    /// duration to attain the initial position (seconds)
+    pub launch_timeout: f32,
+    /// duration after which the launch must have climbed (seconds)
+    pub launch_progress_time: f32,
+    /// height to climb within the progress time (m)
+    pub launch_progress_height: f32,
+    /// minimum height above home of the return (m)
+    pub return_altitude: f32,
+    /// horizontal distance from home at which the return lands (m)
+    pub return_radius: f32,
+    /// rate of the controlled descent (m/s)
+    pub landing_rate: f32,
+    /// touchdown - speed below (m/s)
+    pub landed_speed: f32,
+    /// touchdown - thrust below the fraction of the hover thrust
+    pub landed_thrust: f32,
+    /// touchdown - duration of the conditions (seconds)
+    pub landed_time: f32,
+}
+impl Default for StateConfig {
+    fn default() -> Self {
+        StateConfig {
+            launch_altitude: 2.5,
+            launch_radius: 0.5,
+            launch_timeout: 15.0,
+            launch_progress_time: 3.0,
+            launch_progress_height: 0.3,
+            return_altitude: 20.0,
+            return_radius: 1.0,
+            landing_rate: 0.7,
+            landed_speed: 0.3,
+            landed_thrust: 0.5,
+            landed_time: 1.0,
+        }
+    }
+}
+
+pub struct StateMachine {
+    pub config: StateConfig,
+    state: State,
+    /// altitude (down) at the start of the launch
+    launch_origin: f32,
+    /// start of the touchdown conditions
+    touchdown_since: Option<f32>,
+    latest: Option<Transition>,
+}
+
+impl StateMachine {
+    pub fn new(config: StateConfig) -> Self {
+        StateMachine {
+            config,
+            state: State::HasMission,
+            launch_origin: 0.0,
+            touchdown_since: None,
+            latest: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// the motors are allowed to spin
+    pub fn is_flying(&self) -> bool {
+        matches!(
+            self.state,
+            State::Launch { .. } | State::Active(_) | State::Land { .. }
+        )
+    }
+
+    /// most recent transition
+    pub fn latest_transition(&self) -> Option<Transition> {
+        self.latest
+    }
+
+    /// apply the event, where rejected events (guards) leave the state unchanged
+    pub fn handle(&mut self, event: Event, inputs: &Inputs) -> Result<State, &'static str> {
+        let next = match (self.state, event) {
+            (State::HasMission, Event::Boot { resume }) => match resume {
+                Some(State::Active(active)) => (State::Active(active), "mission resumed"),
+                // the launch timing was lost, hold where the reset occurred
+                Some(State::Launch { .. }) => (
+                    State::Active(Active::Hold(inputs.position)),
+                    "mission resumed",
+                ),
+                Some(State::Land { .. }) => (State::Land { since: inputs.time }, "landing resumed"),
+                Some(State::HasMission | State::Safe) | None => (State::Safe, "no mission"),
+            },
+            (_, Event::Boot { .. }) => return Err("already booted"),
+
+            (State::Safe, Event::Launch) => {
+                if !inputs.armed {
+                    return Err("not armed");
+                }
+                if !inputs.navigation_healthy {
+                    return Err("navigation unhealthy");
+                }
+                self.launch_origin = inputs.position.z;
+                let target = inputs.position + Vector3::new(0.0, 0.0, -self.config.launch_altitude);
+                (
+                    State::Launch {
+                        target,
+                        since: inputs.time,
+                    },
+                    "launch",
+                )
+            }
+            (_, Event::Launch) => return Err("not safe"),
+
+            (State::Active(_), Event::Hold) => {
+                (State::Active(Active::Hold(inputs.position)), "hold")
+            }
+            (State::Active(Active::Vectoring(_)), Event::Vector(target)) => {
+                self.state = State::Active(Active::Vectoring(target));
+                return Ok(self.state);
+            }
+            (State::Active(_), Event::Vector(target)) => {
+                (State::Active(Active::Vectoring(target)), "vectoring")
+            }
+            (_, Event::Hold | Event::Vector(_)) => return Err("not active"),
+
+            (State::Active(Active::Return(_)), Event::Return) => return Ok(self.state),
+            (State::Launch { .. } | State::Active(_), Event::Return) => {
+                let Some(home) = inputs.home else {
+                    return Err("no home");
+                };
+                if !inputs.navigation_healthy {
+                    return Err("navigation unhealthy");
+                }
+                // climb (never descend) to the safe altitude
+                let altitude = inputs.position.z.min(home.z - self.config.return_altitude);
+                let climb = Vector3::new(inputs.position.x, inputs.position.y, altitude);
+                (
+                    State::Active(Active::Return(Return::Climb(climb))),
+                    "return",
+                )
+            }
+            (_, Event::Return) => return Err("not flying"),
+
+            (State::Launch { .. } | State::Active(_), Event::Failure(reason)) => {
+                (State::Land { since: inputs.time }, reason)
+            }
+            (State::Launch { .. } | State::Active(_), Event::Land) => {
+                (State::Land { since: inputs.time }, "land")
+            }
+            (State::Land { .. }, Event::Failure(_) | Event::Land) => return Ok(self.state),
+            (_, Event::Failure(_) | Event::Land) => return Err("not flying"),
+
+            (State::Land { .. }, Event::Touchdown) => (State::Safe, "touchdown"),
+            (_, Event::Touchdown) => return Err("not landing"),
+        };
+        self.transition(next.0, next.1, inputs.time);
+        Ok(self.state)
+    }
+
+    /// evaluate the guards of the state per the latest inputs
+    pub fn update(&mut self, inputs: &Inputs) -> State {
+        let config = self.config;
+        match self.state {
+            State::Launch { target, since } => {
+                // SafePosition?
+                let attained = (target - inputs.position).norm() < config.launch_radius;
+                let climbed = self.launch_origin - inputs.position.z;
+                let elapsed = inputs.time - since;
+                if !inputs.navigation_healthy {
+                    self.transition(
+                        State::Land { since: inputs.time },
+                        "navigation lost",
+                        inputs.time,
+                    );
+                } else if attained {
+                    self.transition(
+                        State::Active(Active::Hold(target)),
+                        "initial position attained",
+                        inputs.time,
+                    );
+                } else if elapsed > config.launch_progress_time
+                    && climbed < config.launch_progress_height
+                {
+                    self.transition(
+                        State::Land { since: inputs.time },
+                        "not climbing",
+                        inputs.time,
+                    );
+                } else if elapsed > config.launch_timeout {
+                    self.transition(
+                        State::Land { since: inputs.time },
+                        "initial position not attained",
+                        inputs.time,
+                    );
+                }
+            }
+            State::Active(active) => {
+                if !inputs.navigation_healthy {
+                    self.transition(
+                        State::Land { since: inputs.time },
+                        "navigation lost",
+                        inputs.time,
+                    );
+                } else if let (Active::Return(phase), Some(home)) = (active, inputs.home) {
+                    self.update_return(phase, &home, inputs);
+                }
+            }
+            State::Land { .. } => {
+                let touchdown = inputs.velocity.norm() < config.landed_speed
+                    && inputs.thrust < config.landed_thrust * inputs.hover_thrust;
+                match (touchdown, self.touchdown_since) {
+                    (false, _) => self.touchdown_since = None,
+                    (true, None) => self.touchdown_since = Some(inputs.time),
+                    (true, Some(since)) => {
+                        if inputs.time - since >= config.landed_time {
+                            self.transition(State::Safe, "landed", inputs.time);
+                        }
+                    }
+                }
+            }
+            State::HasMission | State::Safe => {}
+        }
+        self.state
+    }
+
+    /// climb, then toward home, then Land
+    fn update_return(&mut self, phase: Return, home: &Vector3, inputs: &Inputs) {
+        match phase {
+            Return::Climb(target) => {
+                if (target.z - inputs.position.z).abs() < self.config.launch_radius {
+                    let above = Vector3::new(home.x, home.y, target.z);
+                    self.transition(
+                        State::Active(Active::Return(Return::Navigate(above))),
+                        "safe altitude attained",
+                        inputs.time,
+                    );
+                }
+            }
+            Return::Navigate(target) => {
+                let (dx, dy) = (target.x - inputs.position.x, target.y - inputs.position.y);
+                if libm::sqrtf(dx * dx + dy * dy) < self.config.return_radius {
+                    self.transition(
+                        State::Land { since: inputs.time },
+                        "home attained",
+                        inputs.time,
+                    );
+                }
+            }
+        }
+    }
+
+    fn transition(&mut self, to: State, reason: &'static str, time: f32) {
+        let transition = Transition {
+            from: self.state,
+            to,
+            reason,
+            time,
+        };
+        info!("{:?} -> {:?} [{reason}]", transition.from, transition.to);
+        self.state = to;
+        self.touchdown_since = None;
+        self.latest = Some(transition);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.1;
+
+    /// scripted vehicle - moves per the commanded vertical rate
+    struct Script {
+        inputs: Inputs,
+    }
+    impl Script {
+        fn new() -> Self {
+            Script {
+                inputs: Inputs {
+                    armed: true,
+                    navigation_healthy: true,
+                    hover_thrust: 0.5,
+                    ..Default::default()
+                },
+            }
+        }
+
+        /// advance with the vertical velocity (NED) and thrust
+        fn step(&mut self, machine: &mut StateMachine, climb: f32, thrust: f32) -> State {
+            self.inputs.time += DT;
+            self.inputs.velocity = Vector3::new(0.0, 0.0, climb);
+            self.inputs.position += self.inputs.velocity * DT;
+            self.inputs.thrust = thrust;
+            machine.update(&self.inputs)
+        }
+    }
+
+    fn booted(resume: Option<State>, script: &Script) -> StateMachine {
+        let mut machine = StateMachine::new(StateConfig::default());
+        assert_eq!(machine.state(), State::HasMission);
+        machine
+            .handle(Event::Boot { resume }, &script.inputs)
+            .unwrap();
+        machine
+    }
+
+    #[test]
+    fn boots_safe_without_mission() {
+        let script = Script::new();
+        let mut machine = booted(None, &script);
+        assert_eq!(machine.state(), State::Safe);
+        assert!(!machine.is_flying());
+        assert_eq!(
+            machine.handle(Event::Boot { resume: None }, &script.inputs),
+            Err("already booted")
+        );
+        assert_eq!(
+            machine.handle(Event::Hold, &script.inputs),
+            Err("not active")
+        );
+        assert_eq!(
+            machine.handle(Event::Failure("test"), &script.inputs),
+            Err("not flying")
+        );
+        assert_eq!(machine.state(), State::Safe);
+    }
+
+    #[test]
+    fn resumes_mission_in_air() {
+        let mut script = Script::new();
+        script.inputs.position = Vector3::new(10.0, -5.0, -30.0);
+        let target = Target::Position(Vector3::new(100.0, 0.0, -30.0));
+        let resume = Some(State::Active(Active::Vectoring(target)));
+        let machine = booted(resume, &script);
+        assert_eq!(machine.state(), State::Active(Active::Vectoring(target)));
+        assert_eq!(
+            machine.latest_transition().unwrap().reason,
+            "mission resumed"
+        );
+
+        // reset during the launch - holds where the reset occurred
+        let resume = Some(State::Launch {
+            target: Vector3::new(0.0, 0.0, -2.5),
+            since: 3.0,
+        });
+        let machine = booted(resume, &script);
+        assert_eq!(
+            machine.state(),
+            State::Active(Active::Hold(Vector3::new(10.0, -5.0, -30.0)))
+        );
+
+        // reset while landing - continues to land
+        let machine = booted(Some(State::Land { since: 20.0 }), &script);
+        assert_eq!(machine.state(), State::Land { since: 0.0 });
+    }
+
+    #[test]
+    fn flies_mission() {
+        let mut script = Script::new();
+        let mut machine = booted(None, &script);
+
+        // launch rejected until navigation is available
+        script.inputs.armed = false;
+        assert_eq!(
+            machine.handle(Event::Launch, &script.inputs),
+            Err("not armed")
+        );
+        script.inputs.armed = true;
+        script.inputs.navigation_healthy = false;
+        assert_eq!(
+            machine.handle(Event::Launch, &script.inputs),
+            Err("navigation unhealthy")
+        );
+        script.inputs.navigation_healthy = true;
+        assert!(matches!(
+            machine.handle(Event::Launch, &script.inputs),
+            Ok(State::Launch { .. })
+        ));
+        assert_eq!(
+            machine.handle(Event::Launch, &script.inputs),
+            Err("not safe")
+        );
+
+        // climb to the initial position
+        let mut state = machine.state();
+        for _ in 0..100 {
+            state = script.step(&mut machine, -1.0, 0.6);
+            if matches!(state, State::Active(_)) {
+                break;
+            }
+        }
+        assert_eq!(
+            state,
+            State::Active(Active::Hold(Vector3::new(0.0, 0.0, -2.5)))
+        );
+
+        let target = Target::Position(Vector3::new(100.0, 0.0, -10.0));
+        assert_eq!(
+            machine.handle(Event::Vector(target), &script.inputs),
+            Ok(State::Active(Active::Vectoring(target)))
+        );
+        let vectoring = machine.latest_transition();
+        let target = Target::Trajectory {
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            rate: 2.0,
+        };
+        assert_eq!(
+            machine.handle(Event::Vector(target), &script.inputs),
+            Ok(State::Active(Active::Vectoring(target)))
+        );
+        assert_eq!(machine.latest_transition(), vectoring);
+        assert!(matches!(
+            machine.handle(Event::Hold, &script.inputs),
+            Ok(State::Active(Active::Hold(_)))
+        ));
+
+        // failure lands, then disarms upon touchdown
+        assert!(matches!(
+            machine.handle(Event::Failure("low battery"), &script.inputs),
+            Ok(State::Land { .. })
+        ));
+        assert_eq!(machine.latest_transition().unwrap().reason, "low battery");
+        for _ in 0..30 {
+            script.step(&mut machine, 0.7, 0.45);
+        }
+        assert!(matches!(machine.state(), State::Land { .. }));
+        // on the ground - thrust reduced, no movement
+        for _ in 0..9 {
+            assert!(matches!(
+                script.step(&mut machine, 0.0, 0.1),
+                State::Land { .. }
+            ));
+        }
+        // within the landed time, briefly moving restarts the detection
+        script.step(&mut machine, 0.5, 0.1);
+        for _ in 0..10 {
+            script.step(&mut machine, 0.0, 0.1);
+        }
+        assert!(matches!(machine.state(), State::Land { .. }));
+        script.step(&mut machine, 0.0, 0.1);
+        assert_eq!(script.step(&mut machine, 0.0, 0.1), State::Safe);
+        assert_eq!(machine.latest_transition().unwrap().reason, "landed");
+    }
+
+    #[test]
+    fn aborts_launch_without_climb() {
+        let mut script = Script::new();
+        let mut machine = booted(None, &script);
+        machine.handle(Event::Launch, &script.inputs).unwrap();
+        // i.e. a motor failure, the vehicle doesn't leave the ground
+        let mut state = machine.state();
+        for _ in 0..40 {
+            state = script.step(&mut machine, 0.0, 0.9);
+        }
+        assert!(matches!(state, State::Land { .. }));
+        assert_eq!(machine.latest_transition().unwrap().reason, "not climbing");
+    }
+
+    #[test]
+    fn aborts_launch_upon_timeout() {
+        let mut script = Script::new();
+        let mut machine = booted(None, &script);
+        machine.handle(Event::Launch, &script.inputs).unwrap();
+        // climbing too slowly to attain the position
+        for _ in 0..200 {
+            if let State::Land { .. } = script.step(&mut machine, -0.12, 0.55) {
+                break;
+            }
+        }
+        let transition = machine.latest_transition().unwrap();
+        assert_eq!(transition.reason, "initial position not attained");
+        assert!(transition.time > 15.0);
+    }
+
+    #[test]
+    fn lands_upon_navigation_loss() {
+        let mut script = Script::new();
+        let resume = Some(State::Active(Active::Hold(Vector3::ZERO)));
+        let mut machine = booted(resume, &script);
+        script.inputs.navigation_healthy = false;
+        assert!(matches!(
+            script.step(&mut machine, 0.0, 0.5),
+            State::Land { .. }
+        ));
+        assert_eq!(
+            machine.latest_transition().unwrap().reason,
+            "navigation lost"
+        );
+        // touchdown detected without navigation
+        assert_eq!(
+            machine.handle(Event::Touchdown, &script.inputs),
+            Ok(State::Safe)
+        );
+        assert_eq!(
+            machine.handle(Event::Touchdown, &script.inputs),
+            Err("not landing")
+        );
+    }
+
+    #[test]
+    fn returns_home() {
+        let mut script = Script::new();
+        script.inputs.position = Vector3::new(40.0, 30.0, -5.0);
+        let resume = Some(State::Active(Active::Hold(script.inputs.position)));
+        let mut machine = booted(resume, &script);
+        assert_eq!(
+            machine.handle(Event::Return, &script.inputs),
+            Err("no home")
+        );
+
+        // climbs to the safe altitude
+        script.inputs.home = Some(Vector3::ZERO);
+        let climb = Vector3::new(40.0, 30.0, -20.0);
+        assert_eq!(
+            machine.handle(Event::Return, &script.inputs),
+            Ok(State::Active(Active::Return(Return::Climb(climb))))
+        );
+        assert_eq!(
+            machine.handle(Event::Return, &script.inputs),
+            Ok(State::Active(Active::Return(Return::Climb(climb))))
+        );
+        let mut state = machine.state();
+        for _ in 0..100 {
+            state = script.step(&mut machine, -2.0, 0.6);
+            if matches!(state, State::Active(Active::Return(Return::Navigate(_)))) {
+                break;
+            }
+        }
+        assert_eq!(
+            state,
+            State::Active(Active::Return(Return::Navigate(Vector3::new(
+                0.0, 0.0, -20.0
+            ))))
+        );
+
+        // lands once above home
+        script.inputs.position = Vector3::new(2.0, 0.0, -20.0);
+        assert!(matches!(
+            script.step(&mut machine, 0.0, 0.5),
+            State::Active(Active::Return(_))
+        ));
+        script.inputs.position = Vector3::new(0.5, 0.0, -20.0);
+        assert!(matches!(
+            script.step(&mut machine, 0.0, 0.5),
+            State::Land { .. }
+        ));
+        assert_eq!(machine.latest_transition().unwrap().reason, "home attained");
+
+        // above the safe altitude - returns at the current altitude
+        script.inputs.position = Vector3::new(10.0, 0.0, -50.0);
+        let resume = Some(State::Active(Active::Hold(script.inputs.position)));
+        let mut machine = booted(resume, &script);
+        assert_eq!(
+            machine.handle(Event::Return, &script.inputs),
+            Ok(State::Active(Active::Return(Return::Climb(
+                script.inputs.position
+            ))))
+        );
+    }
+}
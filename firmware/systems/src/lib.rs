@@ -1,4 +1,7 @@
 #![no_std]
 
 
+pub mod calibration;
+pub mod estimation;
+pub mod filtering;
 pub mod flight_controller;